@@ -1,12 +1,11 @@
 // BVH with AABB
 
-use crate::Vec3;
-// use crate::core::Real;
-use crate::core::R_MAX;
+use crate::core::{Real, Spectrum, EPS, R_MAX};
+use crate::hitable::{Hit, HitList, Hitable};
+use crate::materials::Material;
 use crate::ray::Ray;
-// use std::cmp::{min, max};
-// use crate::hitable::{Hitable, Hit};
-//use rand::prelude::*;
+use crate::Vec3;
+use std::sync::Arc;
 
 #[derive(Clone, Copy, Debug)]
 pub struct AABB {
@@ -24,7 +23,41 @@ impl AABB {
     pub fn max(&self) -> Vec3 {
         self.max
     }
+
+    /// Smallest box containing all of the given points.
+    pub fn from_points(points: &[Vec3]) -> AABB {
+        let mut bbox = AABB::new(points[0], points[0]);
+        for p in points[1..].iter() {
+            bbox = AABB::surrounding_box(bbox, AABB::new(*p, *p));
+        }
+        bbox
+    }
+
+    /// Grow the box by `delta` in every direction. Used to give flat
+    /// primitives, like planes, some thickness.
+    pub fn pad(&self, delta: Real) -> AABB {
+        let d = Vec3(delta, delta, delta);
+        AABB::new(self.min - d, self.max + d)
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area(&self) -> Real {
+        let d = self.max - self.min;
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
     pub fn hit(&self, r: &Ray) -> bool {
+        self.hit_range(r, EPS, R_MAX)
+    }
+
+    /// Slab test, only accepting intersections with the box between the ray
+    /// parameters `t_min` and `t_max`.
+    pub fn hit_range(&self, r: &Ray, t_min: Real, t_max: Real) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
         for a in 0..3 {
             let inv_d = 1.0 / r.direction[a];
             let mut t0 = (self.min[a] - r.origin[a]) * inv_d;
@@ -32,13 +65,14 @@ impl AABB {
             if inv_d < 0.0 {
                 std::mem::swap(&mut t0, &mut t1);
             }
-            let tmin = t0.max(0.0001);
-            let tmax = t1.min(R_MAX);
-            if tmax <= tmin {
+            // NaNs (0 * inf) are ignored by max/min.
+            t_min = t0.max(t_min);
+            t_max = t1.min(t_max);
+            if t_max < t_min {
                 return false;
             }
         }
-        return true;
+        true
     }
 
     pub fn surrounding_box(box0: AABB, box1: AABB) -> AABB {
@@ -53,50 +87,384 @@ impl AABB {
             box0.max().2.max(box1.max().2),
         );
 
-        return AABB::new(small, big);
+        AABB::new(small, big)
     }
 }
 
-//pub struct BVHNode {
-//    left: Box<Hitable>,
-//    right: Box<Hitable>,
-//    bbox: AABB
-//}
-//impl BVHNode {
-//    fn compare_box_axis(a: &Hitable, b: &Hitable) -> i8 {
-//        let box_left = a.bounding_box(0, 0);
-//        let box_right = b.bounding_box(0, 0);
-//
-//
-//    }
-//    pub fn bvh_node(&self, n: usize) {
-//        let axis = rand::random::<f64>() * 3.0 as u8;
-//        if axis == 0 {
-//            // TODO sorting function...
-//        }
-//    }
-//}
-//impl Hitable for BVHNode {
-//    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
-//        return Some(self.bbox);
-//    }
-//
-//    fn hit(&self, r: &Ray) -> Option<Hit> {
-//        if self.bbox.hit(r) {
-//            let left_rec = self.left.hit(r);
-//            let right_rec = self.left.hit(r);
-//            if left_rec.is_some() && right_rec.is_some() {
-//                if left_rec.as_ref().unwrap().t < right_rec.as_ref().unwrap().t {
-//                    return left_rec;
-//                } else {
-//                    return right_rec;
-//                }
-//            } else if left_rec.is_some() {
-//                return left_rec;
-//            } else if right_rec.is_some() {
-//                return right_rec;
-//            }
-//        }
-//        return None
-//    }
-//}
+#[derive(Clone, Copy, Debug)]
+enum BVHNodeKind {
+    /// Primitives `first..first + count` of the index list.
+    Leaf { first: usize, count: usize },
+    /// The first child directly follows this node, the second one is at
+    /// `second_child`. `axis` is the axis the primitives were split along.
+    Interior { second_child: usize, axis: usize },
+}
+
+#[derive(Clone, Copy, Debug)]
+struct BVHNode {
+    bbox: AABB,
+    kind: BVHNodeKind,
+}
+
+/// Bounding volume hierarchy over a list of primitives, only described by
+/// their bounding boxes. The primitives themselves are owned by the user of
+/// the tree, which is given the primitive index when a leaf is reached.
+///
+/// The tree is built with the surface area heuristic and stored as a flat
+/// list of nodes in depth first order.
+pub struct BVHTree {
+    nodes: Vec<BVHNode>,
+    indices: Vec<usize>,
+}
+
+const SAH_N_BUCKETS: usize = 12;
+const SAH_TRAVERSAL_COST: Real = 0.125;
+const MAX_PRIMS_IN_LEAF: usize = 4;
+
+struct BuildPrim {
+    index: usize,
+    bbox: AABB,
+    centroid: Vec3,
+}
+
+impl BVHTree {
+    pub fn new(boxes: &[AABB]) -> BVHTree {
+        let mut prims: Vec<BuildPrim> = boxes
+            .iter()
+            .enumerate()
+            .map(|(index, bbox)| BuildPrim {
+                index,
+                bbox: *bbox,
+                centroid: bbox.centroid(),
+            })
+            .collect();
+
+        let mut tree = BVHTree {
+            nodes: Vec::with_capacity(2 * boxes.len()),
+            indices: Vec::with_capacity(boxes.len()),
+        };
+        if !prims.is_empty() {
+            tree.build_recursive(&mut prims);
+        }
+        tree
+    }
+
+    pub fn bounding_box(&self) -> Option<AABB> {
+        self.nodes.first().map(|node| node.bbox)
+    }
+
+    fn build_recursive(&mut self, prims: &mut [BuildPrim]) -> usize {
+        let node_index = self.nodes.len();
+
+        let mut bbox = prims[0].bbox;
+        let mut centroid_bounds = AABB::new(prims[0].centroid, prims[0].centroid);
+        for prim in prims[1..].iter() {
+            bbox = AABB::surrounding_box(bbox, prim.bbox);
+            centroid_bounds =
+                AABB::surrounding_box(centroid_bounds, AABB::new(prim.centroid, prim.centroid));
+        }
+
+        let extent = centroid_bounds.max() - centroid_bounds.min();
+        let mut axis = 0;
+        if extent[1] > extent[axis] {
+            axis = 1;
+        }
+        if extent[2] > extent[axis] {
+            axis = 2;
+        }
+
+        let make_leaf = prims.len() <= 2 || extent[axis] <= 0.0;
+        let mid = if make_leaf {
+            None
+        } else {
+            BVHTree::partition_sah(prims, &bbox, &centroid_bounds, axis)
+        };
+
+        match mid {
+            None => {
+                self.nodes.push(BVHNode {
+                    bbox,
+                    kind: BVHNodeKind::Leaf {
+                        first: self.indices.len(),
+                        count: prims.len(),
+                    },
+                });
+                self.indices.extend(prims.iter().map(|prim| prim.index));
+            }
+            Some(mid) => {
+                self.nodes.push(BVHNode {
+                    bbox,
+                    kind: BVHNodeKind::Interior {
+                        second_child: 0,
+                        axis,
+                    },
+                });
+                let (left, right) = prims.split_at_mut(mid);
+                self.build_recursive(left);
+                let second_child = self.build_recursive(right);
+                self.nodes[node_index].kind = BVHNodeKind::Interior { second_child, axis };
+            }
+        }
+
+        node_index
+    }
+
+    /// Partition `prims` along `axis` at the bucket boundary with the lowest
+    /// surface area heuristic cost. Returns the index of the first primitive
+    /// in the second half, or `None` if a leaf is cheaper.
+    fn partition_sah(
+        prims: &mut [BuildPrim],
+        bbox: &AABB,
+        centroid_bounds: &AABB,
+        axis: usize,
+    ) -> Option<usize> {
+        let c_min = centroid_bounds.min()[axis];
+        let c_extent = centroid_bounds.max()[axis] - c_min;
+        let bucket_of = |prim: &BuildPrim| -> usize {
+            let b = (SAH_N_BUCKETS as Real * (prim.centroid[axis] - c_min) / c_extent) as usize;
+            b.min(SAH_N_BUCKETS - 1)
+        };
+
+        let mut counts = [0usize; SAH_N_BUCKETS];
+        let mut bounds: [Option<AABB>; SAH_N_BUCKETS] = [None; SAH_N_BUCKETS];
+        for prim in prims.iter() {
+            let b = bucket_of(prim);
+            counts[b] += 1;
+            bounds[b] = Some(match bounds[b] {
+                Some(bb) => AABB::surrounding_box(bb, prim.bbox),
+                None => prim.bbox,
+            });
+        }
+
+        let merge = |a: Option<AABB>, b: Option<AABB>| -> Option<AABB> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(AABB::surrounding_box(a, b)),
+                (a, None) => a,
+                (None, b) => b,
+            }
+        };
+        let area = |b: Option<AABB>| b.map_or(0.0, |b| b.surface_area());
+
+        // Cost of splitting after bucket i.
+        let mut best_cost = R_MAX;
+        let mut best_split = 0;
+        for i in 0..(SAH_N_BUCKETS - 1) {
+            let (mut b0, mut b1) = (None, None);
+            let (mut count0, mut count1) = (0, 0);
+            for j in 0..=i {
+                b0 = merge(b0, bounds[j]);
+                count0 += counts[j];
+            }
+            for j in (i + 1)..SAH_N_BUCKETS {
+                b1 = merge(b1, bounds[j]);
+                count1 += counts[j];
+            }
+            let cost = SAH_TRAVERSAL_COST
+                + (count0 as Real * area(b0) + count1 as Real * area(b1))
+                    / bbox.surface_area().max(EPS);
+            if cost < best_cost {
+                best_cost = cost;
+                best_split = i;
+            }
+        }
+
+        let leaf_cost = prims.len() as Real;
+        if prims.len() <= MAX_PRIMS_IN_LEAF && leaf_cost <= best_cost {
+            return None;
+        }
+
+        // In-place partition.
+        let mut mid = 0;
+        for i in 0..prims.len() {
+            if bucket_of(&prims[i]) <= best_split {
+                prims.swap(i, mid);
+                mid += 1;
+            }
+        }
+        if mid == 0 || mid == prims.len() {
+            mid = prims.len() / 2;
+        }
+        Some(mid)
+    }
+
+    /// Find the closest hit along `r`. `hit_prim` is called with the index of
+    /// every primitive whose bounding box is hit.
+    pub fn hit<S, F>(&self, r: &Ray, mut hit_prim: F) -> Option<Hit<S>>
+    where
+        S: Spectrum,
+        F: FnMut(usize, &Ray) -> Option<Hit<S>>,
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let dir_is_neg = [
+            r.direction.x() < 0.0,
+            r.direction.y() < 0.0,
+            r.direction.z() < 0.0,
+        ];
+
+        let mut closest: Option<Hit<S>> = None;
+        let mut closest_t = R_MAX;
+        let mut stack: Vec<usize> = Vec::with_capacity(64);
+        let mut current = 0;
+        loop {
+            let node = &self.nodes[current];
+            if node.bbox.hit_range(r, EPS, closest_t) {
+                match node.kind {
+                    BVHNodeKind::Leaf { first, count } => {
+                        for &prim in self.indices[first..(first + count)].iter() {
+                            if let Some(rec) = hit_prim(prim, r) {
+                                if rec.t < closest_t {
+                                    closest_t = rec.t;
+                                    closest = Some(rec);
+                                }
+                            }
+                        }
+                    }
+                    BVHNodeKind::Interior { second_child, axis } => {
+                        // Visit the near child first.
+                        if dir_is_neg[axis] {
+                            stack.push(current + 1);
+                            current = second_child;
+                        } else {
+                            stack.push(second_child);
+                            current += 1;
+                        }
+                        continue;
+                    }
+                }
+            }
+            match stack.pop() {
+                Some(next) => current = next,
+                None => break,
+            }
+        }
+        closest
+    }
+}
+
+/// A list of hitables stored in a bounding volume hierarchy. Objects without
+/// a bounding box are tested separately for every ray.
+pub struct BVH<S: Spectrum> {
+    objects: HitList<S>,
+    tree: BVHTree,
+    bounded: Vec<usize>,
+    unbounded: Vec<usize>,
+}
+
+impl<S: Spectrum> BVH<S> {
+    pub fn new(objects: HitList<S>) -> BVH<S> {
+        let mut boxes = Vec::with_capacity(objects.len());
+        let mut bounded = Vec::with_capacity(objects.len());
+        let mut unbounded = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            match object.bounding_box() {
+                Some(bbox) => {
+                    boxes.push(bbox);
+                    bounded.push(i);
+                }
+                None => unbounded.push(i),
+            }
+        }
+
+        BVH {
+            tree: BVHTree::new(&boxes),
+            objects,
+            bounded,
+            unbounded,
+        }
+    }
+
+    /// The objects in the order they were given.
+    pub fn objects(&self) -> &HitList<S> {
+        &self.objects
+    }
+}
+
+impl<S: Spectrum> Hitable<S> for BVH<S> {
+    fn hit(&self, r: &Ray) -> Option<Hit<S>> {
+        let mut rec = self
+            .tree
+            .hit(r, |i, r| self.objects[self.bounded[i]].hit(r));
+        for &i in self.unbounded.iter() {
+            if let Some(temp_rec) = self.objects[i].hit(r) {
+                if rec.as_ref().is_none_or(|rec| temp_rec.t < rec.t) {
+                    rec = Some(temp_rec);
+                }
+            }
+        }
+        rec
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.tree.bounding_box()
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material<S> + Send>> {
+        self.objects.materials()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Sphere;
+    use crate::spectrum::SampledSpectrum;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_point(rng: &mut StdRng, size: Real) -> Vec3 {
+        Vec3(rng.gen(), rng.gen(), rng.gen()) * size
+    }
+
+    fn spheres(seed: u64) -> HitList<SampledSpectrum> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut list: HitList<SampledSpectrum> = Vec::new();
+        for _ in 0..200 {
+            list.push(Box::new(Sphere {
+                center: random_point(&mut rng, 10.0),
+                radius: 0.05 + 0.5 * rng.gen::<Real>(),
+                material: None,
+            }));
+        }
+        list
+    }
+
+    #[test]
+    fn bvh_hits_match_brute_force() {
+        let list = spheres(7);
+        let bvh = BVH::new(spheres(7));
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = random_point(&mut rng, 14.0) - Vec3(2.0, 2.0, 2.0);
+            let target = random_point(&mut rng, 10.0);
+            let r = Ray::new(origin, (target - origin).make_unit_vector());
+            match (list.hit(&r), bvh.hit(&r)) {
+                (None, None) => {}
+                (Some(a), Some(b)) => {
+                    assert!((a.t - b.t).abs() < 1e-4, "{} != {}", a.t, b.t);
+                    hits += 1;
+                }
+                (a, b) => panic!("brute force hit {}, BVH hit {}", a.is_some(), b.is_some()),
+            }
+        }
+        assert!(hits > 100, "only {} rays hit a sphere", hits);
+    }
+
+    #[test]
+    fn bvh_bounds_all_objects() {
+        let list = spheres(3);
+        let bbox = BVH::new(spheres(3)).bounding_box().unwrap();
+        for object in list.iter() {
+            let b = object.bounding_box().unwrap();
+            for i in 0..3 {
+                assert!(bbox.min()[i] <= b.min()[i] && bbox.max()[i] >= b.max()[i]);
+            }
+        }
+    }
+}
@@ -81,4 +81,38 @@ impl Camera {
                 - offset,
         };
     }
+
+    /// Density per solid angle of `get_ray` sending a ray in `direction`
+    /// from a point on the lens, for `s` and `t` picked uniformly.
+    pub fn direction_pdf(&self, direction: Vec3) -> Real {
+        let cos = -direction.make_unit_vector().dot(self.w);
+        if cos <= 0.0 {
+            return 0.0;
+        }
+        // Area of the film at unit distance from the lens.
+        let focus_dist = (self.origin - self.lower_left_corner).dot(self.w);
+        let area = self.horizontal.length() * self.vertical.length() / (focus_dist * focus_dist);
+        1.0 / (area * cos * cos * cos)
+    }
+
+    /// Ray from a random point on the lens to `p` and the film coordinates
+    /// `s` and `t` that `get_ray` would give it. `None` if `p` is not in
+    /// view.
+    pub fn film_point(&self, p: Vec3) -> Option<(Real, Real, Ray)> {
+        let rd = self.lens_radius * random_in_unit_disk();
+        let origin = self.origin + self.u * rd.x() + self.v * rd.y();
+        let direction = p - origin;
+        let cos = -direction.dot(self.w);
+        if cos <= 0.0 {
+            return None;
+        }
+        let focus_dist = (self.origin - self.lower_left_corner).dot(self.w);
+        let film = origin + direction * (focus_dist / cos) - self.lower_left_corner;
+        let s = film.dot(self.horizontal) / self.horizontal.squared_length();
+        let t = film.dot(self.vertical) / self.vertical.squared_length();
+        if !(0.0..1.0).contains(&s) || !(0.0..1.0).contains(&t) {
+            return None;
+        }
+        Some((s, t, Ray { origin, direction }))
+    }
 }
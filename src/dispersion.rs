@@ -0,0 +1,109 @@
+//! Wavelength dependent index of refraction of transparent materials, for
+//! `materials::Dielectric`.
+
+use crate::core::*;
+
+use serde::Deserialize;
+
+/// Index of refraction as a function of the wavelength.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dispersion {
+    /// The same index at every wavelength.
+    Constant(Real),
+    /// Cauchy's equation `n = a + b / l^2 + c / l^4`, with the wavelength
+    /// `l` in micrometers.
+    Cauchy { a: Real, b: Real, c: Real },
+    /// Sellmeier equation `n^2 = 1 + sum(b_i l^2 / (l^2 - c_i))`, with the
+    /// wavelength `l` in micrometers and `c_i` in square micrometers.
+    Sellmeier { b: [Real; 3], c: [Real; 3] },
+}
+
+impl Dispersion {
+    /// Index of refraction at `lambda` in nanometers.
+    pub fn ior(&self, lambda: Real) -> Real {
+        let l2 = (lambda / 1000.0) * (lambda / 1000.0);
+        match self {
+            Dispersion::Constant(n) => *n,
+            Dispersion::Cauchy { a, b, c } => a + b / l2 + c / (l2 * l2),
+            Dispersion::Sellmeier { b, c } => {
+                let n2 = 1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<Real>();
+                n2.max(1.0).sqrt()
+            }
+        }
+    }
+
+    /// Whether the index changes with the wavelength.
+    pub fn is_dispersive(&self) -> bool {
+        match self {
+            Dispersion::Constant(_) => false,
+            Dispersion::Cauchy { b, c, .. } => *b != 0.0 || *c != 0.0,
+            Dispersion::Sellmeier { .. } => true,
+        }
+    }
+}
+
+/// A glass with a known dispersion.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GlassPreset {
+    /// Schott N-BK7 borosilicate crown glass.
+    Bk7,
+    /// Fused silica, after Malitson (1965).
+    FusedSilica,
+    /// Water at 20 °C, Cauchy's equation fitted to 405 and 707 nm.
+    Water,
+}
+
+impl GlassPreset {
+    pub fn dispersion(self) -> Dispersion {
+        match self {
+            GlassPreset::Bk7 => Dispersion::Sellmeier {
+                b: [1.039612, 0.2317923, 1.010469],
+                c: [0.006000699, 0.02001791, 103.5607],
+            },
+            GlassPreset::FusedSilica => Dispersion::Sellmeier {
+                b: [0.6961663, 0.4079426, 0.8974794],
+                c: [0.004679148, 0.01351206, 97.934],
+            },
+            GlassPreset::Water => Dispersion::Cauchy {
+                a: 1.3248,
+                b: 0.00295,
+                c: 0.0,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_match_the_d_line() {
+        // Indices at the sodium D line, 589.3 nm.
+        for (preset, n) in [
+            (GlassPreset::Bk7, 1.5168),
+            (GlassPreset::FusedSilica, 1.4585),
+            (GlassPreset::Water, 1.3333),
+        ] {
+            let ior = preset.dispersion().ior(589.3);
+            assert!((ior - n).abs() < 1e-3, "{:?}: {} != {}", preset, ior, n);
+        }
+    }
+
+    #[test]
+    fn blue_is_refracted_more_than_red() {
+        for preset in [
+            GlassPreset::Bk7,
+            GlassPreset::FusedSilica,
+            GlassPreset::Water,
+        ] {
+            let dispersion = preset.dispersion();
+            assert!(dispersion.is_dispersive());
+            assert!(dispersion.ior(400.0) > dispersion.ior(700.0));
+        }
+        let constant = Dispersion::Constant(1.5);
+        assert!(!constant.is_dispersive());
+        assert_eq!(constant.ior(400.0), constant.ior(700.0));
+    }
+}
@@ -0,0 +1,447 @@
+//! Loading of measured excitation–emission (Donaldson) matrices.
+//!
+//! A matrix file is plain text with the values separated by commas,
+//! semicolons, tabs or spaces, and `#` starts a comment. The first row holds
+//! the emission wavelengths, optionally after a label. Every following row
+//! holds an excitation wavelength and the measured values for each emission
+//! wavelength:
+//!
+//! ```text
+//! ex/em, 400,  410,  420
+//! 400,   0.80, 0.02, 0.01
+//! 410,   0.00, 0.81, 0.03
+//! 420,   0.00, 0.00, 0.79
+//! ```
+//!
+//! The values are radiance factors as measured with the excitation
+//! wavelengths of the file: where the excitation and emission wavelength are
+//! the same the value is the reflectance, elsewhere it is the fluorescence
+//! for light in one excitation step.
+
+use crate::core::*;
+use crate::spectrum::{spectral_grid, SpectralGrid};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum DonaldsonError {
+    Io(std::io::Error),
+    Parse(String),
+}
+
+impl fmt::Display for DonaldsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DonaldsonError::Io(err) => write!(f, "{}", err),
+            DonaldsonError::Parse(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DonaldsonError {}
+
+impl From<std::io::Error> for DonaldsonError {
+    fn from(err: std::io::Error) -> Self {
+        DonaldsonError::Io(err)
+    }
+}
+
+fn parse_error<T>(msg: String) -> Result<T, DonaldsonError> {
+    Err(DonaldsonError::Parse(msg))
+}
+
+/// Unit of the wavelengths in a matrix file.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+pub enum WavelengthUnit {
+    #[default]
+    #[serde(rename = "nm")]
+    #[value(name = "nm")]
+    Nanometer,
+    #[serde(rename = "um")]
+    #[value(name = "um")]
+    Micrometer,
+}
+
+impl WavelengthUnit {
+    fn to_nanometers(self, v: Real) -> Real {
+        match self {
+            WavelengthUnit::Nanometer => v,
+            WavelengthUnit::Micrometer => v * 1000.0,
+        }
+    }
+}
+
+/// Wavelengths outside of this range, in nanometers, are most likely given
+/// in another unit.
+const PLAUSIBLE_LAMBDA: (Real, Real) = (100.0, 3000.0);
+
+/// A measured excitation–emission matrix.
+#[derive(Clone, Debug)]
+pub struct DonaldsonMatrix {
+    /// Excitation wavelengths in nanometers.
+    pub excitation: Vec<Real>,
+    /// Emission wavelengths in nanometers.
+    pub emission: Vec<Real>,
+    /// One row of values per excitation wavelength, with a value per
+    /// emission wavelength.
+    pub values: Vec<Real>,
+}
+
+/// Load a matrix file, see the module documentation for the format.
+pub fn load_donaldson(
+    path: &Path,
+    unit: WavelengthUnit,
+) -> Result<DonaldsonMatrix, DonaldsonError> {
+    let text = std::fs::read_to_string(path)?;
+    parse_donaldson(&text, unit)
+}
+
+fn parse_row(line: &str, line_no: usize) -> Result<(Option<String>, Vec<Real>), DonaldsonError> {
+    let mut tokens = line
+        .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+        .filter(|t| !t.is_empty())
+        .peekable();
+    let label = match tokens.peek() {
+        Some(t) if t.parse::<Real>().is_err() => tokens.next().map(|t| t.to_string()),
+        _ => None,
+    };
+    let values = tokens
+        .map(|t| {
+            t.parse::<Real>()
+                .map_err(|_| DonaldsonError::Parse(format!("line {}: bad number '{}'", line_no, t)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((label, values))
+}
+
+fn check_wavelengths(lambda: &[Real], what: &str) -> Result<(), DonaldsonError> {
+    if lambda.len() < 2 {
+        return parse_error(format!("expected at least two {} wavelengths", what));
+    }
+    if lambda.windows(2).any(|w| w[0] >= w[1]) {
+        return parse_error(format!("{} wavelengths must be strictly increasing", what));
+    }
+    let (low, high) = PLAUSIBLE_LAMBDA;
+    if let Some(l) = lambda.iter().find(|l| **l < low || **l > high) {
+        return parse_error(format!(
+            "{} wavelength {} nm is outside of {} to {} nm, check the wavelength unit",
+            what, l, low, high
+        ));
+    }
+    Ok(())
+}
+
+/// Parse the text of a matrix file. Small negative values from measurement
+/// noise are set to zero.
+pub fn parse_donaldson(
+    text: &str,
+    unit: WavelengthUnit,
+) -> Result<DonaldsonMatrix, DonaldsonError> {
+    let mut emission: Option<Vec<Real>> = None;
+    let mut excitation = Vec::new();
+    let mut values = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (label, row) = parse_row(line, line_no)?;
+        let emission = match &emission {
+            None => {
+                emission = Some(row.iter().map(|l| unit.to_nanometers(*l)).collect());
+                continue;
+            }
+            Some(emission) => emission,
+        };
+        if label.is_some() {
+            return parse_error(format!(
+                "line {}: expected an excitation wavelength",
+                line_no
+            ));
+        }
+        if row.len() != emission.len() + 1 {
+            return parse_error(format!(
+                "line {}: expected an excitation wavelength and {} values, got {} numbers",
+                line_no,
+                emission.len(),
+                row.len()
+            ));
+        }
+        if let Some(v) = row[1..].iter().find(|v| !v.is_finite()) {
+            return parse_error(format!("line {}: value {} is not finite", line_no, v));
+        }
+        excitation.push(unit.to_nanometers(row[0]));
+        values.extend(row[1..].iter().map(|v| v.max(0.0)));
+    }
+
+    let emission = match emission {
+        Some(emission) => emission,
+        None => return parse_error("no emission wavelengths found".to_string()),
+    };
+    check_wavelengths(&emission, "emission")?;
+    check_wavelengths(&excitation, "excitation")?;
+    Ok(DonaldsonMatrix {
+        excitation,
+        emission,
+        values,
+    })
+}
+
+/// Index of the interval of `lambda` that holds `l`, and the position within
+/// it. `None` outside of `lambda`.
+fn locate(lambda: &[Real], l: Real) -> Option<(usize, Real)> {
+    if l < lambda[0] || l > lambda[lambda.len() - 1] {
+        return None;
+    }
+    let i = find_interval(lambda.len() as i32, |i| lambda[i as usize] <= l) as usize;
+    Some((i, (l - lambda[i]) / (lambda[i + 1] - lambda[i])))
+}
+
+/// Width of the wavelength step around sample `i`.
+fn step(lambda: &[Real], i: usize) -> Real {
+    let lo = lambda[i.saturating_sub(1)];
+    let hi = lambda[(i + 1).min(lambda.len() - 1)];
+    (hi - lo) / ((i + 1).min(lambda.len() - 1) - i.saturating_sub(1)) as Real
+}
+
+impl DonaldsonMatrix {
+    /// The matrix with excitation and emission swapped, for files that
+    /// have the excitation wavelengths in the first row.
+    pub fn transposed(&self) -> DonaldsonMatrix {
+        let n_em = self.emission.len();
+        let n_ex = self.excitation.len();
+        let mut values = vec![0.0; n_ex * n_em];
+        for j in 0..n_ex {
+            for k in 0..n_em {
+                values[k * n_ex + j] = self.values[j * n_em + k];
+            }
+        }
+        DonaldsonMatrix {
+            excitation: self.emission.clone(),
+            emission: self.excitation.clone(),
+            values,
+        }
+    }
+
+    pub fn value(&self, i_ex: usize, i_em: usize) -> Real {
+        self.values[i_ex * self.emission.len() + i_em]
+    }
+
+    /// Emission sample on the diagonal for excitation sample `i_ex`, where
+    /// the value is the reflectance.
+    fn diagonal(&self, i_ex: usize) -> Option<usize> {
+        let l = self.excitation[i_ex];
+        let (k, t) = locate(&self.emission, l)?;
+        let k = if t < 0.5 { k } else { k + 1 };
+        if (self.emission[k] - l).abs() <= 0.5 * step(&self.emission, k) {
+            Some(k)
+        } else {
+            None
+        }
+    }
+
+    /// Split the matrix into the reflectance on the diagonal, as wavelengths
+    /// and values, and the fluorescence per nanometer of excitation, with
+    /// the diagonal left out.
+    fn split(&self) -> (Vec<Real>, Vec<Real>, Vec<Real>) {
+        let n_em = self.emission.len();
+        let mut refl_lambda = Vec::new();
+        let mut refl = Vec::new();
+        let mut fluor = self.values.clone();
+        for j in 0..self.excitation.len() {
+            if let Some(k) = self.diagonal(j) {
+                refl_lambda.push(self.excitation[j]);
+                refl.push(self.value(j, k));
+                fluor[j * n_em + k] = 0.0;
+            }
+            let width = step(&self.excitation, j);
+            fluor[j * n_em..(j + 1) * n_em]
+                .iter_mut()
+                .for_each(|v| *v /= width);
+        }
+        (refl_lambda, refl, fluor)
+    }
+
+    /// Check that the measurement covers some of the spectral grid.
+    pub fn check_grid(&self, grid: &SpectralGrid) -> Result<(), DonaldsonError> {
+        let low = self.excitation[0].min(self.emission[0]);
+        let high =
+            self.excitation[self.excitation.len() - 1].max(self.emission[self.emission.len() - 1]);
+        if high <= grid.lambda_start || low >= grid.lambda_end {
+            return parse_error(format!(
+                "the measured wavelengths {} to {} nm are outside of the spectral grid {} to {} nm",
+                low, high, grid.lambda_start, grid.lambda_end
+            ));
+        }
+        Ok(())
+    }
+
+    /// Resample onto the spectral grid as a re-radiation matrix for
+    /// `SampledSpectrum`, one row per outgoing bin. Every bin is averaged
+    /// over a few wavelengths, the matrix is zero outside of the measurement.
+    /// Measured re-radiation at shorter wavelengths is kept.
+    pub fn sampled_matrix(&self) -> Vec<Real> {
+        const SUB_SAMPLES: usize = 4;
+        let grid = spectral_grid();
+        let n = grid.n;
+        let width = grid.bin_width();
+        let sub = |bin: usize, s: usize| {
+            grid.lambda[bin] + width * (s as Real + 0.5) / SUB_SAMPLES as Real
+        };
+
+        let (refl_lambda, refl, fluor) = self.split();
+        let n_em = self.emission.len();
+        let fluorescence = |l_i: Real, l_o: Real| -> Real {
+            match (locate(&self.excitation, l_i), locate(&self.emission, l_o)) {
+                (Some((j, tj)), Some((k, tk))) => {
+                    let f = |j: usize, k: usize| fluor[j * n_em + k];
+                    lerp(
+                        tj,
+                        lerp(tk, f(j, k), f(j, k + 1)),
+                        lerp(tk, f(j + 1, k), f(j + 1, k + 1)),
+                    )
+                }
+                _ => 0.0,
+            }
+        };
+
+        let mut m = vec![0.0; n * n];
+        for i_o in 0..n {
+            for i_i in 0..n {
+                let mut sum = 0.0;
+                for s_i in 0..SUB_SAMPLES {
+                    for s_o in 0..SUB_SAMPLES {
+                        sum += fluorescence(sub(i_i, s_i), sub(i_o, s_o));
+                    }
+                }
+                m[i_o * n + i_i] = sum * width / (SUB_SAMPLES * SUB_SAMPLES) as Real;
+            }
+
+            if refl_lambda.len() >= 2 {
+                let reflectance: Real = (0..SUB_SAMPLES)
+                    .filter_map(|s| {
+                        let (k, t) = locate(&refl_lambda, sub(i_o, s))?;
+                        Some(lerp(t, refl[k], refl[k + 1]))
+                    })
+                    .sum();
+                m[i_o * n + i_o] += reflectance / SUB_SAMPLES as Real;
+            }
+        }
+        m
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "\
+ex/em, 400,  410,  420
+400,   0.80, 0.02, 0.01
+410,   0.00, 0.81, 0.03
+420,   0.00, 0.00, 0.79
+";
+
+    #[test]
+    fn parses_the_example() {
+        let m = parse_donaldson(EXAMPLE, WavelengthUnit::Nanometer).unwrap();
+        assert_eq!(m.excitation, vec![400.0, 410.0, 420.0]);
+        assert_eq!(m.emission, vec![400.0, 410.0, 420.0]);
+        assert_eq!(m.value(0, 1), 0.02);
+        assert_eq!(m.value(1, 2), 0.03);
+        assert_eq!(m.value(2, 2), 0.79);
+    }
+
+    #[test]
+    fn separators_comments_and_units() {
+        let text =
+            "# measured in micrometers\n0.4;0.5\n\n0.4\t0.5 0.1 # first row\n0.5,-0.01,0.6\n";
+        let m = parse_donaldson(text, WavelengthUnit::Micrometer).unwrap();
+        assert_eq!(m.excitation, vec![400.0, 500.0]);
+        assert_eq!(m.emission, vec![400.0, 500.0]);
+        // Negative noise is set to zero.
+        assert_eq!(m.values, vec![0.5, 0.1, 0.0, 0.6]);
+    }
+
+    #[test]
+    fn transposed_swaps_excitation_and_emission() {
+        let m = parse_donaldson(
+            "em, 500, 600, 700\n400, 1, 2, 3\n450, 4, 5, 6\n",
+            WavelengthUnit::Nanometer,
+        )
+        .unwrap();
+        let t = m.transposed();
+        assert_eq!(t.excitation, m.emission);
+        assert_eq!(t.emission, m.excitation);
+        for j in 0..2 {
+            for k in 0..3 {
+                assert_eq!(t.value(k, j), m.value(j, k));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let bad = [
+            ("", "no emission wavelengths"),
+            (
+                "400, 410\n400, 0.1\n",
+                "expected an excitation wavelength and 2 values",
+            ),
+            ("400, 410\n400, 0.1, x\n", "bad number 'x'"),
+            (
+                "400, 410\nex, 0.1, 0.2\n",
+                "expected an excitation wavelength",
+            ),
+            ("400, 410\n400, 0.1, inf\n410, 0.1, 0.2\n", "not finite"),
+            (
+                "410, 400\n400, 0.1, 0.2\n410, 0.1, 0.2\n",
+                "strictly increasing",
+            ),
+            ("400, 410\n400, 0.1, 0.2\n", "at least two excitation"),
+            (
+                "0.4, 0.41\n400, 0.1, 0.2\n410, 0.1, 0.2\n",
+                "check the wavelength unit",
+            ),
+        ];
+        for (text, expected) in bad.iter() {
+            match parse_donaldson(text, WavelengthUnit::Nanometer) {
+                Err(DonaldsonError::Parse(msg)) => assert!(
+                    msg.contains(expected),
+                    "'{}' does not contain '{}'",
+                    msg,
+                    expected
+                ),
+                _ => panic!("{:?} was not rejected", text),
+            }
+        }
+    }
+
+    #[test]
+    fn reflectance_goes_on_the_diagonal() {
+        let lambda: Vec<Real> = (0..=40).map(|i| 300.0 + 10.0 * i as Real).collect();
+        let mut text = String::from("ex/em");
+        for l in lambda.iter() {
+            text += &format!(", {}", l);
+        }
+        for (j, l) in lambda.iter().enumerate() {
+            text += &format!("\n{}", l);
+            for k in 0..lambda.len() {
+                text += if j == k { ", 0.5" } else { ", 0" };
+            }
+        }
+        let m = parse_donaldson(&text, WavelengthUnit::Nanometer).unwrap();
+        let n = spectral_grid().n;
+        let sampled = m.sampled_matrix();
+        for i_o in 0..n {
+            for i_i in 0..n {
+                let expected = if i_i == i_o { 0.5 } else { 0.0 };
+                assert!((sampled[i_o * n + i_i] - expected).abs() < 1e-4);
+            }
+        }
+    }
+}
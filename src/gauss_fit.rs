@@ -0,0 +1,405 @@
+//! Fitting of `GaussRecord` mixtures to measured re-radiation matrices.
+//!
+//! The Gaussians are fitted to the matrix as the renderer uses it, resampled
+//! onto the spectral grid, so that `rerad_matrix` of the fitted records
+//! gives back the measured matrix as closely as possible. Each Gaussian is
+//! first placed on the largest remaining residual, then all of them are
+//! refined together with Levenberg-Marquardt.
+
+use crate::core::*;
+use crate::donaldson::DonaldsonMatrix;
+use crate::materials::{GaussRecord, ReRadReport};
+use crate::spectrum::{spectral_grid, SampledSpectrum};
+
+use std::fmt::Write;
+
+/// Parameters per Gaussian: lambda_in, lambda_out, sigma_in, sigma_out and
+/// amplitude_out.
+const N_PARAMS: usize = 5;
+
+/// Sigmas are kept above this, in nanometers.
+const MIN_SIGMA: f64 = 0.1;
+
+/// Result of `fit_gauss_records`.
+pub struct GaussFit {
+    pub records: Vec<GaussRecord>,
+    /// Root mean square difference to the resampled matrix.
+    pub rms: Real,
+    /// Largest absolute value of the resampled matrix, to put `rms` in
+    /// relation.
+    pub max_value: Real,
+    pub iterations: usize,
+    /// What is not physically plausible about the fitted re-radiation, see
+    /// `ReRadReport::problems`. Empty if nothing is.
+    pub problems: Vec<String>,
+}
+
+/// A matrix entry to fit: excitation and emission wavelength and value.
+struct Cell {
+    lambda_i: f64,
+    lambda_o: f64,
+    value: f64,
+}
+
+fn gauss(p: &[f64], l_i: f64, l_o: f64) -> f64 {
+    let d_i = (l_i - p[0]) / p[2];
+    let d_o = (l_o - p[1]) / p[3];
+    p[4] * (-0.5 * (d_i * d_i + d_o * d_o)).exp()
+}
+
+fn eval(params: &[f64], cell: &Cell) -> f64 {
+    params
+        .chunks(N_PARAMS)
+        .map(|p| gauss(p, cell.lambda_i, cell.lambda_o))
+        .sum()
+}
+
+fn sum_squares(params: &[f64], cells: &[Cell]) -> f64 {
+    cells
+        .iter()
+        .map(|c| (eval(params, c) - c.value).powi(2))
+        .sum()
+}
+
+/// Solve `a x = b` by Gaussian elimination with partial pivoting. `a` is
+/// `n` by `n`, row major. `None` if `a` is singular.
+fn solve(mut a: Vec<f64>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|x, y| {
+            a[x * n + col]
+                .abs()
+                .partial_cmp(&a[y * n + col].abs())
+                .unwrap()
+        })?;
+        if a[pivot * n + col].abs() < 1e-300 {
+            return None;
+        }
+        if pivot != col {
+            for k in 0..n {
+                a.swap(pivot * n + k, col * n + k);
+            }
+            b.swap(pivot, col);
+        }
+        for row in col + 1..n {
+            let f = a[row * n + col] / a[col * n + col];
+            for k in col..n {
+                a[row * n + k] -= f * a[col * n + k];
+            }
+            b[row] -= f * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let s: f64 = (row + 1..n).map(|k| a[row * n + k] * x[k]).sum();
+        x[row] = (b[row] - s) / a[row * n + row];
+    }
+    Some(x)
+}
+
+/// Levenberg-Marquardt on `params`, only the Gaussians from `first` on are
+/// changed. Returns the number of iterations done.
+fn levenberg_marquardt(
+    params: &mut [f64],
+    first: usize,
+    cells: &[Cell],
+    max_iterations: usize,
+) -> usize {
+    // Keep the Gaussians on the grid, or they can wander off to fit the
+    // matrix with their tails.
+    let grid = spectral_grid();
+    let bounds = (grid.lambda_start as f64, grid.lambda_end as f64);
+    let free = first * N_PARAMS..params.len();
+    let n = free.len();
+    let mut damping = 1e-3;
+    let mut error = sum_squares(params, cells);
+
+    for iteration in 0..max_iterations {
+        // Normal equations J^T J and J^T r for the free parameters.
+        let mut jtj = vec![0.0; n * n];
+        let mut jtr = vec![0.0; n];
+        let mut row = vec![0.0; n];
+        for cell in cells.iter() {
+            let r = eval(params, cell) - cell.value;
+            for (g, p) in params[free.clone()].chunks(N_PARAMS).enumerate() {
+                let d_i = cell.lambda_i - p[0];
+                let d_o = cell.lambda_o - p[1];
+                let s_i2 = p[2] * p[2];
+                let s_o2 = p[3] * p[3];
+                let e = (-0.5 * (d_i * d_i / s_i2 + d_o * d_o / s_o2)).exp();
+                let v = p[4] * e;
+                let j = &mut row[g * N_PARAMS..(g + 1) * N_PARAMS];
+                j[0] = v * d_i / s_i2;
+                j[1] = v * d_o / s_o2;
+                j[2] = v * d_i * d_i / (s_i2 * p[2]);
+                j[3] = v * d_o * d_o / (s_o2 * p[3]);
+                j[4] = e;
+            }
+            for a in 0..n {
+                if row[a] == 0.0 {
+                    continue;
+                }
+                jtr[a] += row[a] * r;
+                for b in 0..n {
+                    jtj[a * n + b] += row[a] * row[b];
+                }
+            }
+        }
+
+        // Try steps with increasing damping until one lowers the error.
+        let mut improved = false;
+        while damping < 1e12 {
+            let mut a = jtj.clone();
+            for k in 0..n {
+                a[k * n + k] += damping * jtj[k * n + k].max(1e-12);
+            }
+            let step = match solve(a, jtr.iter().map(|v| -v).collect()) {
+                Some(step) => step,
+                None => {
+                    damping *= 10.0;
+                    continue;
+                }
+            };
+            let mut candidate = params.to_vec();
+            for (k, s) in step.iter().enumerate() {
+                candidate[free.start + k] += s;
+            }
+            for p in candidate[free.clone()].chunks_mut(N_PARAMS) {
+                p[0] = p[0].clamp(bounds.0, bounds.1);
+                p[1] = p[1].clamp(bounds.0, bounds.1);
+                if p[0] > p[1] {
+                    let mid = 0.5 * (p[0] + p[1]);
+                    p[0] = mid;
+                    p[1] = mid;
+                }
+                p[2] = p[2].abs().clamp(MIN_SIGMA, bounds.1 - bounds.0);
+                p[3] = p[3].abs().clamp(MIN_SIGMA, bounds.1 - bounds.0);
+                p[4] = p[4].max(0.0);
+            }
+            let candidate_error = sum_squares(&candidate, cells);
+            if candidate_error < error {
+                let converged = error - candidate_error <= 1e-10 * error;
+                params.copy_from_slice(&candidate);
+                error = candidate_error;
+                damping = (damping * 0.1).max(1e-12);
+                improved = !converged;
+                break;
+            }
+            damping *= 10.0;
+        }
+        if !improved {
+            return iteration + 1;
+        }
+    }
+    max_iterations
+}
+
+/// Fit `n_gauss` Gaussians to `matrix` resampled onto the spectral grid.
+pub fn fit_gauss_records(
+    matrix: &DonaldsonMatrix,
+    n_gauss: usize,
+    max_iterations: usize,
+) -> GaussFit {
+    let grid = spectral_grid();
+    let sampled = matrix.sampled_matrix();
+    let n = grid.n;
+    // The renderer only re-radiates at the same or a longer wavelength, the
+    // other entries are zero. They are still fitted, so that no Gaussian
+    // hides there and reaches over with its tail.
+    let cells: Vec<Cell> = (0..n)
+        .flat_map(|i_o| (0..n).map(move |i_i| (i_i, i_o)))
+        .map(|(i_i, i_o)| Cell {
+            lambda_i: grid.lambda[i_i] as f64,
+            lambda_o: grid.lambda[i_o] as f64,
+            value: if i_i <= i_o {
+                sampled[i_o * n + i_i] as f64
+            } else {
+                0.0
+            },
+        })
+        .collect();
+
+    let mut params: Vec<f64> = Vec::with_capacity(n_gauss * N_PARAMS);
+    let mut iterations = 0;
+    for g in 0..n_gauss {
+        let peak = cells
+            .iter()
+            .map(|c| (c, c.value - eval(&params, c)))
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        let (cell, residual) = match peak {
+            Some((cell, residual)) if residual > 0.0 => (cell, residual),
+            _ => break,
+        };
+        let sigma = 2.0 * grid.bin_width() as f64;
+        params.extend_from_slice(&[cell.lambda_i, cell.lambda_o, sigma, sigma, residual]);
+        iterations += levenberg_marquardt(&mut params, g, &cells, max_iterations);
+    }
+    iterations += levenberg_marquardt(&mut params, 0, &cells, max_iterations);
+
+    let rms = (sum_squares(&params, &cells) / cells.len() as f64).sqrt();
+    let max_value = cells.iter().map(|c| c.value.abs()).fold(0.0, f64::max);
+    let records: Vec<GaussRecord> = params
+        .chunks(N_PARAMS)
+        .map(|p| GaussRecord {
+            lambda_in: p[0] as Real,
+            lambda_out: p[1] as Real,
+            amplitude_out: p[4] as Real,
+            sigma_in: p[2] as Real,
+            sigma_out: p[3] as Real,
+        })
+        .collect();
+    // Check the records as the renderer will, on the matrix they give.
+    let fitted = SampledSpectrum::full_rerad_matrix(&|lambda_i, lambda_o| {
+        records.iter().map(|g| g.eval(lambda_i, lambda_o)).sum()
+    });
+    GaussFit {
+        problems: ReRadReport::new(&fitted).problems(),
+        records,
+        rms: rms as Real,
+        max_value: max_value as Real,
+        iterations,
+    }
+}
+
+/// The fitted records as a `lambertian_rerad` material of a scene file.
+pub fn to_scene_material(name: &str, records: &[GaussRecord]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "[materials.{}]", name);
+    let _ = writeln!(out, "type = \"lambertian_rerad\"");
+    let _ = writeln!(out, "rerad = [");
+    for g in records {
+        let _ = writeln!(
+            out,
+            "    {{ lambda_in = {:.2}, lambda_out = {:.2}, amplitude_out = {:e}, \
+             sigma_in = {:.3}, sigma_out = {:.3} }},",
+            g.lambda_in, g.lambda_out, g.amplitude_out, g.sigma_in, g.sigma_out
+        );
+    }
+    let _ = writeln!(out, "]");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::donaldson::{parse_donaldson, WavelengthUnit};
+    use crate::hitable::Hitable;
+    use crate::materials::ReRadiation;
+    use crate::ray::Ray;
+    use crate::scene_file::parse_scene;
+    use crate::spectrum::SampledSpectrum;
+    use std::path::Path;
+
+    fn known_records() -> Vec<GaussRecord> {
+        vec![
+            GaussRecord {
+                lambda_in: 400.0,
+                lambda_out: 550.0,
+                amplitude_out: 0.05,
+                sigma_in: 15.0,
+                sigma_out: 20.0,
+            },
+            GaussRecord {
+                lambda_in: 460.0,
+                lambda_out: 620.0,
+                amplitude_out: 0.03,
+                sigma_in: 12.0,
+                sigma_out: 25.0,
+            },
+        ]
+    }
+
+    /// A matrix file measured every 2 nm whose resampled matrix is
+    /// `records` on the grid. Resampling averages over the bins, so the file
+    /// is shifted by half a bin.
+    fn matrix_file(records: &[GaussRecord]) -> String {
+        const STEP: Real = 2.0;
+        let grid = spectral_grid();
+        let half_bin = 0.5 * grid.bin_width();
+        let n = ((grid.lambda_end - grid.lambda_start) / STEP) as usize;
+        let lambda: Vec<Real> = (0..=n)
+            .map(|i| grid.lambda_start + STEP * i as Real)
+            .collect();
+        let mut text = String::from("ex/em");
+        for l in lambda.iter() {
+            text += &format!(", {}", l);
+        }
+        for l_i in lambda.iter() {
+            text += &format!("\n{}", l_i);
+            for l_o in lambda.iter() {
+                let v: Real = records
+                    .iter()
+                    .map(|g| g.eval(l_i - half_bin, l_o - half_bin))
+                    .sum();
+                text += &format!(", {:e}", v * STEP / grid.bin_width());
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn recovers_known_gaussians() {
+        let known = known_records();
+        let matrix = parse_donaldson(&matrix_file(&known), WavelengthUnit::Nanometer).unwrap();
+        let fit = fit_gauss_records(&matrix, known.len(), 200);
+        assert!(fit.rms < 1e-2 * fit.max_value, "rms {}", fit.rms);
+        assert!(fit.problems.is_empty(), "{:?}", fit.problems);
+
+        for g in known.iter() {
+            let f = fit
+                .records
+                .iter()
+                .min_by(|a, b| {
+                    let d = |r: &GaussRecord| (r.lambda_in - g.lambda_in).abs();
+                    d(a).partial_cmp(&d(b)).unwrap()
+                })
+                .unwrap();
+            assert!((f.lambda_in - g.lambda_in).abs() < 1.0, "{:?}", f);
+            assert!((f.lambda_out - g.lambda_out).abs() < 1.0, "{:?}", f);
+            assert!((f.sigma_in / g.sigma_in - 1.0).abs() < 0.05, "{:?}", f);
+            assert!((f.sigma_out / g.sigma_out - 1.0).abs() < 0.05, "{:?}", f);
+            assert!(
+                (f.amplitude_out / g.amplitude_out - 1.0).abs() < 0.05,
+                "{:?}",
+                f
+            );
+        }
+    }
+
+    #[test]
+    fn reports_implausible_fits() {
+        // Re-radiates more than it receives between 400 and 500 nm.
+        let mut bright = known_records();
+        bright[0].amplitude_out = 0.5;
+        let matrix = parse_donaldson(&matrix_file(&bright), WavelengthUnit::Nanometer).unwrap();
+        let fit = fit_gauss_records(&matrix, bright.len(), 200);
+        assert!(
+            fit.problems
+                .iter()
+                .any(|p| p.contains("times the incoming energy")),
+            "{:?}",
+            fit.problems
+        );
+    }
+
+    #[test]
+    fn scene_material_has_the_records() {
+        let known = known_records();
+        let scene = format!(
+            "[camera]\nlookfrom = [0.0, 0.0, -5.0]\nlookat = [0.0, 0.0, 0.0]\nfov = 40.0\n\n\
+             {}\n[[objects]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, 0.0]\nradius = 1.0\n\
+             material = \"fitted\"\n",
+            to_scene_material("fitted", &known)
+        );
+        let (world, _) = parse_scene::<SampledSpectrum>(&scene, Path::new(""), 1.0).unwrap();
+        let hit = world
+            .hit(&Ray::new(Vec3(0.0, 0.0, -5.0), Vec3(0.0, 0.0, 1.0)))
+            .unwrap();
+        let white = SampledSpectrum::new(1.0);
+        let loaded = hit.material.as_ref().unwrap().apply_diffuse(&hit, &white);
+        let expected = ReRadiation::<SampledSpectrum>::new(known).apply(&white);
+        for (a, b) in loaded.values().iter().zip(expected.values().iter()) {
+            assert!((a - b).abs() <= 1e-3 * b.abs() + 1e-6, "{} != {}", a, b);
+        }
+    }
+}
@@ -1,34 +1,51 @@
+use crate::aabb::AABB;
+use crate::core::{Real, Spectrum, R_MAX};
 use crate::materials::Material;
 use crate::ray::Ray;
 use crate::Vec3;
-// use crate::aabb::AABB;
-use crate::core::{Real, R_MAX};
 use std::sync::Arc;
 
 #[derive(Clone)]
-pub struct Hit {
+pub struct Hit<S: Spectrum> {
     pub t: Real,
     pub p: Vec3,
     pub u: Real,
     pub v: Real,
     pub normal: Vec3,
-    pub material: Option<Arc<dyn Material + Send>>,
+    pub material: Option<Arc<dyn Material<S> + Send>>,
 }
 
-pub trait Hitable: Sync + Send {
-    fn hit(&self, r: &Ray) -> Option<Hit>;
+pub trait Hitable<S: Spectrum>: Sync + Send {
+    fn hit(&self, r: &Ray) -> Option<Hit<S>>;
+    /// Box enclosing the object, `None` if it is unbounded.
+    fn bounding_box(&self) -> Option<AABB>;
     fn pdf_value(&self, origin: Vec3, v: Vec3) -> Real {
         return 0.0;
     }
     fn random(&self, origin: Vec3) -> Vec3 {
         return Vec3(1.0, 0.0, 0.0);
     }
+    /// Surface area, for picking lights in proportion to their power. Zero
+    /// if the object can not be sampled with `random` and `pdf_value`.
+    fn area(&self) -> Real {
+        0.0
+    }
+    /// A point picked uniformly over the surface, as a hit with `t` zero.
+    /// `None` if the object can not be sampled, see `area`.
+    fn random_point(&self) -> Option<Hit<S>> {
+        None
+    }
+    /// Materials of the object, an object with several materials gives
+    /// each of them.
+    fn materials(&self) -> Vec<Arc<dyn Material<S> + Send>> {
+        Vec::new()
+    }
 }
 
-pub type HitList = Vec<Box<dyn Hitable>>;
-impl Hitable for Vec<Box<dyn Hitable>> {
-    fn hit(&self, r: &Ray) -> Option<Hit> {
-        let mut rec: Option<Hit> = None;
+pub type HitList<S> = Vec<Box<dyn Hitable<S>>>;
+impl<S: Spectrum> Hitable<S> for Vec<Box<dyn Hitable<S>>> {
+    fn hit(&self, r: &Ray) -> Option<Hit<S>> {
+        let mut rec: Option<Hit<S>> = None;
         let mut closest_so_far = R_MAX;
         for item in self.iter() {
             match item.hit(&r) {
@@ -44,22 +61,19 @@ impl Hitable for Vec<Box<dyn Hitable>> {
         rec
     }
 
-    //fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
-    //    if self.len() < 1 {return None}
-    //    let mut bbox;
-    //    match self[0].bounding_box(t0, t1) {
-    //        Some(temp_bbox) => { bbox = temp_bbox; },
-    //        None => { return None }
-    //    }
+    fn bounding_box(&self) -> Option<AABB> {
+        let mut bbox: Option<AABB> = None;
+        for item in self.iter() {
+            let item_bbox = item.bounding_box()?;
+            bbox = Some(match bbox {
+                Some(bbox) => AABB::surrounding_box(bbox, item_bbox),
+                None => item_bbox,
+            });
+        }
+        bbox
+    }
 
-    //    for item in self {
-    //        match item.bounding_box(t0, t1) {
-    //            Some(temp_bbox) => {
-    //                bbox = AABB::surrounding_box(bbox, temp_bbox);
-    //            },
-    //            None => {return None;}
-    //        }
-    //    }
-    //    return Some(bbox);
-    //}
+    fn materials(&self) -> Vec<Arc<dyn Material<S> + Send>> {
+        self.iter().flat_map(|item| item.materials()).collect()
+    }
 }
@@ -0,0 +1,1450 @@
+//! Integrators compute the light arriving at the camera along a ray and add
+//! it to a pixel.
+
+use crate::camera::Camera;
+use crate::core::*;
+use crate::hitable::{Hit, Hitable};
+use crate::light::LightList;
+use crate::materials::{Material, ScatterRecord};
+use crate::ray::{CosinePDF, HitablePDF, MixturePDF, Ray, PDF};
+use crate::spectrum::{
+    cie_xyz, hero_wavelength_pdf, sample_hero_wavelengths, spectral_grid, xyz_to_rgb,
+    N_HERO_WAVELENGTHS,
+};
+use clap::ValueEnum;
+use std::sync::Arc;
+
+/// Everything an integrator needs to know about the scene.
+pub struct Scene<'a, S: Spectrum> {
+    pub world: &'a dyn Hitable<S>,
+    /// Lights that paths are sent towards. `None` if the scene has no lights
+    /// that can be sampled.
+    pub light: Option<&'a LightList<'a, S>>,
+    pub camera: &'a Camera,
+    /// Light arriving from outside of the scene.
+    pub env_light: Option<S>,
+}
+
+pub trait Integrator<S: Spectrum>: Sync {
+    /// Add the light arriving along the camera ray `r`, multiplied by
+    /// `gain`, to a pixel. `rgb` is linear RGB. `spectral` holds one value
+    /// per coefficient of `S`, or is empty if no spectral image is kept.
+    /// Light the sample brings to other pixels goes to `splats`.
+    fn add_sample(
+        &self,
+        r: &Ray,
+        scene: &Scene<S>,
+        gain: Real,
+        rgb: &mut [Real; 3],
+        spectral: &mut [Real],
+        splats: &mut Splats,
+    );
+}
+
+/// Light added to any pixel of the image, by paths traced from the lights.
+/// Holds the sum over the samples in the layout of the image, rows from the
+/// top, and is divided by the number of samples per pixel.
+pub struct Splats {
+    width: usize,
+    height: usize,
+    /// Linear RGB, three values per pixel.
+    pub rgb: Vec<Real>,
+    /// One value per coefficient of the spectrum per pixel, empty if no
+    /// spectral image is kept.
+    pub spectral: Vec<Real>,
+}
+
+impl Splats {
+    pub fn new(width: usize, height: usize, n_bands: usize) -> Splats {
+        Splats {
+            width,
+            height,
+            rgb: vec![0.0; width * height * 3],
+            spectral: vec![0.0; width * height * n_bands],
+        }
+    }
+
+    /// Add `spec` to the pixel at the film coordinates `s` and `t`, as for
+    /// `Camera::get_ray`.
+    pub fn add<S: Spectrum>(&mut self, s: Real, t: Real, spec: &S) {
+        let x = ((s * self.width as Real) as usize).min(self.width - 1);
+        let y = ((t * self.height as Real) as usize).min(self.height - 1);
+        let pixel = (self.height - 1 - y) * self.width + x;
+        let mut rgb = [0.0; 3];
+        spec.to_rgb(&mut rgb);
+        for (c, v) in self.rgb[pixel * 3..pixel * 3 + 3].iter_mut().zip(rgb) {
+            *c += v;
+        }
+        if !self.spectral.is_empty() {
+            let n_bands = spec.values().len();
+            let offset = pixel * n_bands;
+            for (c, v) in self.spectral[offset..offset + n_bands]
+                .iter_mut()
+                .zip(spec.values())
+            {
+                *c += v;
+            }
+        }
+    }
+}
+
+/// Length of the paths. A path always scatters `min` times unless it leaves
+/// the scene or is absorbed, after that it is ended by Russian roulette with
+/// the probability of losing its throughput. It never scatters more than
+/// `max` times.
+#[derive(Copy, Clone, Debug)]
+pub struct Bounces {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl Bounces {
+    /// Probability that the path continues after scattering for the
+    /// `bounce`th time, counted from 0, with the largest component of its
+    /// throughput at `throughput`.
+    fn survival(self, bounce: usize, throughput: Real) -> Real {
+        if bounce < self.min {
+            1.0
+        } else {
+            throughput.min(1.0)
+        }
+    }
+}
+
+/// Path tracer that carries the whole spectrum along every path. At every
+/// diffuse hit it picks either a direction towards a light or one sampled
+/// from the material.
+pub struct PathIntegrator {
+    pub bounces: Bounces,
+}
+
+impl<S: Spectrum> Integrator<S> for PathIntegrator {
+    fn add_sample(
+        &self,
+        r: &Ray,
+        scene: &Scene<S>,
+        gain: Real,
+        rgb: &mut [Real; 3],
+        spectral: &mut [Real],
+        splats: &mut Splats,
+    ) {
+        let spec = trace(r, scene, None, self.bounces) * gain;
+        add_spectrum(&spec, rgb, spectral);
+    }
+}
+
+/// How `MisIntegrator` weights the light and the material sample of a
+/// direction (Veach 1997).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MisHeuristic {
+    /// Weights in proportion to the densities.
+    Balance,
+    /// Weights in proportion to the squared densities, which favours the
+    /// better strategy more.
+    Power,
+}
+
+impl MisHeuristic {
+    /// Weight of a sample drawn with the density `pdf`, when the other
+    /// strategy would have drawn it with `other_pdf`.
+    pub fn weight(self, pdf: Real, other_pdf: Real) -> Real {
+        if pdf.is_infinite() {
+            return 1.0;
+        }
+        let (a, b) = match self {
+            MisHeuristic::Balance => (pdf, other_pdf),
+            MisHeuristic::Power => (pdf * pdf, other_pdf * other_pdf),
+        };
+        if a + b > 0.0 {
+            a / (a + b)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Path tracer with next event estimation. At every diffuse hit a shadow ray
+/// is sent to a point on a light and the path continues in a direction
+/// sampled from the material. Both find the light, so their contributions
+/// are weighted with multiple importance sampling and emission is not
+/// counted twice.
+pub struct MisIntegrator {
+    pub heuristic: MisHeuristic,
+    pub bounces: Bounces,
+}
+
+impl<S: Spectrum> Integrator<S> for MisIntegrator {
+    fn add_sample(
+        &self,
+        r: &Ray,
+        scene: &Scene<S>,
+        gain: Real,
+        rgb: &mut [Real; 3],
+        spectral: &mut [Real],
+        splats: &mut Splats,
+    ) {
+        let spec = trace(r, scene, Some(self.heuristic), self.bounces) * gain;
+        add_spectrum(&spec, rgb, spectral);
+    }
+}
+
+/// Add the spectrum `spec` to a pixel, see `Integrator::add_sample`.
+fn add_spectrum<S: Spectrum>(spec: &S, rgb: &mut [Real; 3], spectral: &mut [Real]) {
+    let mut sample_rgb = [0.0; 3];
+    spec.to_rgb(&mut sample_rgb);
+    for (c, v) in rgb.iter_mut().zip(sample_rgb) {
+        *c += v;
+    }
+    for (c, v) in spectral.iter_mut().zip(spec.values()) {
+        *c += v;
+    }
+}
+
+/// What a vertex of a path does to the light arriving from the next vertex.
+enum Scattering<S: Spectrum> {
+    /// Reflected or refracted into a single direction and tinted.
+    Specular(S),
+    /// Scattered by `material` at `rec`. The light arriving is `direct`,
+    /// from a shadow ray, and the light from the next vertex times `weight`.
+    Diffuse {
+        material: Arc<dyn Material<S> + Send>,
+        rec: Hit<S>,
+        direct: S,
+        weight: Real,
+    },
+}
+
+struct Vertex<S: Spectrum> {
+    emitted: S,
+    scattering: Scattering<S>,
+}
+
+/// Light arriving along `r`. With `mis` a light is sampled at every diffuse
+/// hit, see `MisIntegrator`, otherwise a direction towards a light or from
+/// the material is picked.
+///
+/// The path is followed from the camera and its vertices are kept. Once it
+/// ends, the light is gathered from the last vertex back to the camera, as
+/// re-radiating materials can only be applied to the whole spectrum
+/// arriving at them.
+fn trace<S: Spectrum>(r: &Ray, scene: &Scene<S>, mis: Option<MisHeuristic>, bounces: Bounces) -> S {
+    let mut vertices: Vec<Vertex<S>> = Vec::new();
+    let mut ray = *r;
+    // Density the material sampled `ray` with, if emission it finds is
+    // weighted against the shadow rays.
+    let mut material_pdf = None;
+    // Light scattered towards the camera for white light at the end of the
+    // path, to end paths that carry little light.
+    let mut throughput = S::new(1.0);
+    let mut dispersed = None;
+    let mut bounce = 0;
+    let tail = loop {
+        let rec = match scene.world.hit(&ray) {
+            Some(rec) => rec,
+            None => break scene.env_light.unwrap_or_default(),
+        };
+        let mat = match rec.material.clone() {
+            Some(mat) => mat,
+            None => break S::default(),
+        };
+
+        let mut emitted = mat.emitted(&ray, &rec, rec.u, rec.v, rec.p);
+        if let (Some(heuristic), Some(pdf), Some(light)) = (mis, material_pdf, scene.light) {
+            emitted = emitted * heuristic.weight(pdf, light.pdf_value(ray.origin, ray.direction));
+        }
+        if bounce >= bounces.max {
+            break emitted;
+        }
+        let srec = match scatter(mat.as_ref(), &ray, &rec, &mut dispersed) {
+            Some(srec) => srec,
+            None => break emitted,
+        };
+
+        if let Some(specular_ray) = srec.specular_ray {
+            let response = throughput * srec.attenuation;
+            let survival = bounces.survival(bounce, response.max_component_value());
+            if rand::random::<Real>() >= survival {
+                break emitted;
+            }
+            throughput = response / survival;
+            vertices.push(Vertex {
+                emitted,
+                scattering: Scattering::Specular(srec.attenuation / survival),
+            });
+            ray = specular_ray;
+            material_pdf = None;
+            bounce += 1;
+            continue;
+        }
+
+        let mat_pdf = srec.pdf.unwrap();
+        let mut direct = S::default();
+        let (hitable_pdf, mixture_pdf);
+        let p: &dyn PDF = match (mis, scene.light) {
+            (Some(heuristic), Some(light)) => {
+                if let Some((shadow_ray, factor)) =
+                    sample_light(&ray, &rec, mat.as_ref(), mat_pdf.as_ref(), light, heuristic)
+                {
+                    direct = light_emission(&shadow_ray, scene) * factor;
+                }
+                mat_pdf.as_ref()
+            }
+            (None, Some(light)) => {
+                hitable_pdf = HitablePDF::new(light, rec.p);
+                mixture_pdf = MixturePDF::new(&hitable_pdf, mat_pdf.as_ref());
+                &mixture_pdf
+            }
+            (_, None) => mat_pdf.as_ref(),
+        };
+        let scattered = Ray::new(rec.p, p.generate());
+        let pdf_val = p.value(scattered.direction);
+        let mut weight = if pdf_val > 0.0 {
+            mat.scattering_pdf(&ray, &rec, &scattered) / pdf_val
+        } else {
+            0.0
+        };
+        let response = throughput * mat.apply_diffuse(&rec, &S::new(1.0)) * weight;
+        let survival = bounces.survival(bounce, response.max_component_value());
+        let continues = weight > 0.0 && rand::random::<Real>() < survival;
+        if continues {
+            weight /= survival;
+            throughput = response / survival;
+        } else {
+            weight = 0.0;
+        }
+        vertices.push(Vertex {
+            emitted,
+            scattering: Scattering::Diffuse {
+                material: mat,
+                rec,
+                direct,
+                weight,
+            },
+        });
+        if !continues {
+            break S::default();
+        }
+        ray = scattered;
+        material_pdf = mis.map(|_| pdf_val);
+        bounce += 1;
+    };
+
+    vertices.iter().rev().fold(tail, |li, vertex| {
+        vertex.emitted
+            + match &vertex.scattering {
+                Scattering::Specular(attenuation) => *attenuation * li,
+                Scattering::Diffuse {
+                    material,
+                    rec,
+                    direct,
+                    weight,
+                } => material.apply_diffuse(rec, &(*direct + li * *weight)),
+            }
+    })
+}
+
+/// Shadow ray from the hit `rec` towards a point on `light`, for light
+/// scattered along `r`. Returns the ray and the scattering density towards
+/// it, weighted against `mat_pdf` with `heuristic` and divided by the
+/// density of the light sample. `None` if the material does not scatter in
+/// that direction.
+fn sample_light<S: Spectrum>(
+    r: &Ray,
+    rec: &Hit<S>,
+    mat: &dyn Material<S>,
+    mat_pdf: &dyn PDF,
+    light: &dyn Hitable<S>,
+    heuristic: MisHeuristic,
+) -> Option<(Ray, Real)> {
+    let shadow_ray = Ray::new(rec.p, light.random(rec.p));
+    let light_pdf = light.pdf_value(rec.p, shadow_ray.direction);
+    let scattering_pdf_val = mat.scattering_pdf(r, rec, &shadow_ray);
+    if light_pdf <= 0.0 || scattering_pdf_val <= 0.0 {
+        return None;
+    }
+    let weight = heuristic.weight(light_pdf, mat_pdf.value(shadow_ray.direction));
+    Some((shadow_ray, scattering_pdf_val * weight / light_pdf))
+}
+
+/// Emission reaching the origin of the shadow ray `r`, black if the first
+/// object it hits does not emit.
+fn light_emission<S: Spectrum>(r: &Ray, scene: &Scene<S>) -> S {
+    match scene.world.hit(r) {
+        Some(rec) => match rec.material.as_ref() {
+            Some(mat) => mat.emitted(r, &rec, rec.u, rec.v, rec.p),
+            None => S::default(),
+        },
+        None => S::default(),
+    }
+}
+
+/// The coefficient of the spectrum a path follows through dispersive
+/// materials and the wavelength within it, see `scatter`.
+type Dispersed = Option<(usize, Real)>;
+
+/// Scatter `r_in` at the hit `rec` of `mat`. At the first dispersive
+/// material of a path a coefficient and a wavelength within it are picked as
+/// in `Dielectric::scatter`, and later dispersive materials keep them, so
+/// light leaving a glass object is not lost to a different pick than the
+/// one it entered with. Re-radiating materials change the wavelength of the
+/// light, so the next dispersive material picks again.
+fn scatter<S: Spectrum>(
+    mat: &dyn Material<S>,
+    r_in: &Ray,
+    rec: &Hit<S>,
+    dispersed: &mut Dispersed,
+) -> Option<ScatterRecord<S>> {
+    if mat.rerad_report().is_some() {
+        *dispersed = None;
+    }
+    if !mat.is_dispersive() {
+        return mat.scatter(r_in, rec);
+    }
+    let (i, lambda, scale) = match *dispersed {
+        Some((i, lambda)) => (i, lambda, 1.0),
+        None => {
+            // The other coefficients are left out, so the picked one is
+            // scaled by their number.
+            let n = S::default().values().len();
+            let i = ((rand::random::<Real>() * n as Real) as usize).min(n - 1);
+            let (start, end) = S::coefficient_range(i);
+            let lambda = lerp(rand::random::<Real>(), start, end);
+            *dispersed = Some((i, lambda));
+            (i, lambda, n as Real)
+        }
+    };
+    let mut srec = mat.scatter_lambda(r_in, rec, lambda)?;
+    let mut mask = S::default();
+    mask.values_mut()[i] = scale;
+    srec.attenuation *= mask;
+    Some(srec)
+}
+
+/// Path tracer with hero wavelength sampling (Wilkie et al. 2014). Every
+/// path carries `N_HERO_WAVELENGTHS` wavelengths instead of the whole
+/// spectrum, and the wavelengths are weighted by their probability when
+/// they are added to the pixel. Re-radiating materials switch the
+/// wavelengths of the path with `Material::sample_diffuse_lambda`, and
+/// dispersive materials keep only the hero wavelength. With `mis` lights are
+/// sampled at diffuse hits as in `MisIntegrator`.
+pub struct HeroIntegrator {
+    pub mis: Option<MisHeuristic>,
+    pub bounces: Bounces,
+}
+
+type HeroValues = [Real; N_HERO_WAVELENGTHS];
+
+impl Integrator<SampledSpectrum> for HeroIntegrator {
+    fn add_sample(
+        &self,
+        r: &Ray,
+        scene: &Scene<SampledSpectrum>,
+        gain: Real,
+        rgb: &mut [Real; 3],
+        spectral: &mut [Real],
+        splats: &mut Splats,
+    ) {
+        let lambda = sample_hero_wavelengths(rand::random::<Real>());
+        let values = trace_hero(r, scene, self.mis, self.bounces, &lambda);
+
+        // Each wavelength is an estimate of the whole spectrum, so the
+        // estimates are averaged.
+        let weight = gain / (hero_wavelength_pdf() * N_HERO_WAVELENGTHS as Real);
+        let bin_width = spectral_grid().bin_width();
+        let mut xyz = [0.0; 3];
+        for (l, v) in lambda.iter().zip(values) {
+            for (c, cmf) in xyz.iter_mut().zip(cie_xyz(*l)) {
+                *c += cmf * v * weight;
+            }
+            if !spectral.is_empty() {
+                spectral[SampledSpectrum::coefficient_index(*l)] += v * weight / bin_width;
+            }
+        }
+        let mut sample_rgb = [0.0; 3];
+        xyz_to_rgb(&xyz, &mut sample_rgb);
+        for (c, v) in rgb.iter_mut().zip(sample_rgb) {
+            *c += v;
+        }
+    }
+}
+
+/// Light arriving along `r` at the wavelengths `lambda`. Follows `trace`,
+/// with the spectra of the scene evaluated at the wavelengths of the path.
+/// Every wavelength carries its own throughput, so the light is added up as
+/// the path is followed.
+fn trace_hero(
+    r: &Ray,
+    scene: &Scene<SampledSpectrum>,
+    mis: Option<MisHeuristic>,
+    bounces: Bounces,
+    lambda: &HeroValues,
+) -> HeroValues {
+    let mut val = [0.0; N_HERO_WAVELENGTHS];
+    let mut throughput = [1.0; N_HERO_WAVELENGTHS];
+    let mut lambda = *lambda;
+    // The other wavelengths were dropped at a dispersive material and only
+    // the first is followed.
+    let mut hero_only = false;
+    let mut ray = *r;
+    let mut material_pdf = None;
+    for bounce in 0..=bounces.max {
+        let rec = match scene.world.hit(&ray) {
+            Some(rec) => rec,
+            None => {
+                if let Some(illum) = &scene.env_light {
+                    for j in 0..N_HERO_WAVELENGTHS {
+                        val[j] += throughput[j] * illum.eval(lambda[j]);
+                    }
+                }
+                break;
+            }
+        };
+        let mat = match rec.material.as_ref() {
+            Some(mat) => mat,
+            None => break,
+        };
+
+        let emitted = mat.emitted(&ray, &rec, rec.u, rec.v, rec.p);
+        let mut emission_weight = 1.0;
+        if let (Some(heuristic), Some(pdf), Some(light)) = (mis, material_pdf, scene.light) {
+            emission_weight = heuristic.weight(pdf, light.pdf_value(ray.origin, ray.direction));
+        }
+        for j in 0..N_HERO_WAVELENGTHS {
+            val[j] += throughput[j] * emitted.eval(lambda[j]) * emission_weight;
+        }
+        if bounce == bounces.max {
+            break;
+        }
+        let dispersive = mat.is_dispersive();
+        let srec = if dispersive {
+            mat.scatter_lambda(&ray, &rec, lambda[0])
+        } else {
+            mat.scatter(&ray, &rec)
+        };
+        let srec = match srec {
+            Some(srec) => srec,
+            None => break,
+        };
+
+        if let Some(specular_ray) = srec.specular_ray {
+            if dispersive && !hero_only {
+                // Only the hero wavelength follows the scattered direction,
+                // so it alone estimates the spectrum from here on.
+                hero_only = true;
+                throughput[0] *= N_HERO_WAVELENGTHS as Real;
+                throughput[1..].fill(0.0);
+            }
+            for j in 0..N_HERO_WAVELENGTHS {
+                throughput[j] *= srec.attenuation.eval(lambda[j]);
+            }
+            ray = specular_ray;
+            material_pdf = None;
+        } else {
+            // Every wavelength may continue at another wavelength.
+            let mut lambda_in = [0.0; N_HERO_WAVELENGTHS];
+            let mut transfer = [0.0; N_HERO_WAVELENGTHS];
+            for j in 0..N_HERO_WAVELENGTHS {
+                (lambda_in[j], transfer[j]) =
+                    mat.sample_diffuse_lambda(&rec, lambda[j], rand::random::<Real>());
+            }
+
+            let mat_pdf = srec.pdf.unwrap();
+            let (hitable_pdf, mixture_pdf);
+            let p: &dyn PDF = match (mis, scene.light) {
+                (Some(heuristic), Some(light)) => {
+                    if let Some((shadow_ray, factor)) =
+                        sample_light(&ray, &rec, mat.as_ref(), mat_pdf.as_ref(), light, heuristic)
+                    {
+                        let le = light_emission(&shadow_ray, scene);
+                        for j in 0..N_HERO_WAVELENGTHS {
+                            val[j] += throughput[j] * transfer[j] * le.eval(lambda_in[j]) * factor;
+                        }
+                    }
+                    mat_pdf.as_ref()
+                }
+                (None, Some(light)) => {
+                    hitable_pdf = HitablePDF::new(light, rec.p);
+                    mixture_pdf = MixturePDF::new(&hitable_pdf, mat_pdf.as_ref());
+                    &mixture_pdf
+                }
+                (_, None) => mat_pdf.as_ref(),
+            };
+            let scattered = Ray::new(rec.p, p.generate());
+            let pdf_val = p.value(scattered.direction);
+            if pdf_val <= 0.0 {
+                break;
+            }
+            let scattering_pdf_val = mat.scattering_pdf(&ray, &rec, &scattered);
+            for j in 0..N_HERO_WAVELENGTHS {
+                throughput[j] *= transfer[j] * scattering_pdf_val / pdf_val;
+            }
+            lambda = lambda_in;
+            ray = scattered;
+            material_pdf = mis.map(|_| pdf_val);
+        }
+
+        let max_throughput = throughput.iter().fold(0.0, |a: Real, &b| a.max(b));
+        let survival = bounces.survival(bounce, max_throughput);
+        if rand::random::<Real>() >= survival {
+            break;
+        }
+        for t in throughput.iter_mut() {
+            *t /= survival;
+        }
+    }
+    val
+}
+
+/// Bidirectional path tracer (Veach 1997). Every camera path is joined by a
+/// path traced from a point on a light, and each vertex of one is connected
+/// to each vertex of the other with a shadow ray. Light path vertices are
+/// also connected to the camera, which lands on any pixel and is added to
+/// the `Splats`. All the ways of building a path are weighted with multiple
+/// importance sampling, so caustics and small lights that a camera path
+/// rarely finds come from the light paths.
+///
+/// The whole spectrum is carried along both paths. Light paths apply
+/// re-radiation in the direction the light travels, and camera paths gather
+/// the light from their last vertex back to the camera as in `trace`, so
+/// fluorescent materials work from both sides. Materials that pick a
+/// specular or a diffuse lobe at random are weighted by the lobe they
+/// picked.
+pub struct BdptIntegrator {
+    pub heuristic: MisHeuristic,
+    pub bounces: Bounces,
+}
+
+impl<S: Spectrum> Integrator<S> for BdptIntegrator {
+    fn add_sample(
+        &self,
+        r: &Ray,
+        scene: &Scene<S>,
+        gain: Real,
+        rgb: &mut [Real; 3],
+        spectral: &mut [Real],
+        splats: &mut Splats,
+    ) {
+        let spec = self.trace(r, scene, gain, splats) * gain;
+        add_spectrum(&spec, rgb, spectral);
+    }
+}
+
+/// Densities of a path vertex per area, for the weights of
+/// `BdptIntegrator`.
+#[derive(Copy, Clone, Debug)]
+struct VertexPdfs {
+    /// Sampled from the previous vertex of its own path.
+    fwd: Real,
+    /// Sampled from the next vertex, by the path traced the other way.
+    rev: Real,
+    /// Scattered into a single direction, so it can not be connected.
+    delta: bool,
+}
+
+/// A vertex of a camera or light path of `BdptIntegrator`.
+struct BdptVertex<S: Spectrum> {
+    p: Vec3,
+    normal: Vec3,
+    /// Hit and material of the vertex, `None` at the camera.
+    surface: Option<(Hit<S>, Arc<dyn Material<S> + Send>)>,
+    /// Ray that found the vertex.
+    r_in: Ray,
+    pdfs: VertexPdfs,
+    /// On light paths the light arriving along `r_in`, divided by the
+    /// density of the path. Unused on camera paths.
+    beta: S,
+}
+
+impl<S: Spectrum> BdptVertex<S> {
+    fn surface(&self) -> (&Hit<S>, &dyn Material<S>) {
+        let (rec, mat) = self.surface.as_ref().unwrap();
+        (rec, mat.as_ref())
+    }
+}
+
+impl BdptIntegrator {
+    /// Light arriving along `r` through the current pixel, the light on
+    /// other pixels is added to `splats` multiplied by `gain`.
+    fn trace<S: Spectrum>(&self, r: &Ray, scene: &Scene<S>, gain: Real, splats: &mut Splats) -> S {
+        let (camera_path, mut folds, mut tail) = self.camera_path(r, scene);
+        let light_path = match scene.light {
+            Some(lights) => self.light_path(lights, scene),
+            None => Vec::new(),
+        };
+
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                if s + t < 2 || (s == 1 && t == 1) || s + t - 2 > self.bounces.max {
+                    continue;
+                }
+                if s == 0 {
+                    // The camera path found a light, its emission is weighted.
+                    let weight = self.emission_weight(&camera_path[..t], scene);
+                    match folds.get_mut(t - 2) {
+                        Some(vertex) => vertex.emitted = vertex.emitted * weight,
+                        None => tail = tail * weight,
+                    }
+                } else if t == 1 {
+                    self.splat(&light_path[..s], scene, gain, splats);
+                } else if let Some(Vertex {
+                    scattering: Scattering::Diffuse { direct, .. },
+                    ..
+                }) = folds.get_mut(t - 2)
+                {
+                    *direct += self.connect(&camera_path[..t], &light_path[..s], scene);
+                }
+            }
+        }
+
+        folds.iter().rev().fold(tail, |li, vertex| {
+            vertex.emitted
+                + match &vertex.scattering {
+                    Scattering::Specular(attenuation) => *attenuation * li,
+                    Scattering::Diffuse {
+                        material,
+                        rec,
+                        direct,
+                        weight,
+                    } => material.apply_diffuse(rec, &(*direct + li * *weight)),
+                }
+        })
+    }
+
+    /// Follow the camera ray `r` as in `trace`. Returns the vertices, with
+    /// the camera first, what each vertex that scattered does to the light,
+    /// and the light arriving at the last of those. The emission of the
+    /// vertices is not yet weighted.
+    fn camera_path<S: Spectrum>(
+        &self,
+        r: &Ray,
+        scene: &Scene<S>,
+    ) -> (Vec<BdptVertex<S>>, Vec<Vertex<S>>, S) {
+        let mut path = vec![BdptVertex {
+            p: r.origin,
+            normal: -1.0 * scene.camera.w,
+            surface: None,
+            r_in: *r,
+            pdfs: VertexPdfs {
+                fwd: 1.0,
+                rev: 0.0,
+                delta: false,
+            },
+            beta: S::default(),
+        }];
+        let mut folds: Vec<Vertex<S>> = Vec::new();
+        let mut ray = *r;
+        let mut pdf_dir = scene.camera.direction_pdf(r.direction);
+        let mut throughput = S::new(1.0);
+        let mut dispersed = None;
+        let mut bounce = 0;
+        let tail = loop {
+            let rec = match scene.world.hit(&ray) {
+                Some(rec) => rec,
+                None => break scene.env_light.unwrap_or_default(),
+            };
+            let mat = match rec.material.clone() {
+                Some(mat) => mat,
+                None => break S::default(),
+            };
+            let emitted = mat.emitted(&ray, &rec, rec.u, rec.v, rec.p);
+            path.push(BdptVertex {
+                p: rec.p,
+                normal: rec.normal,
+                surface: Some((rec.clone(), mat.clone())),
+                r_in: ray,
+                pdfs: VertexPdfs {
+                    fwd: to_area(pdf_dir, ray.origin, rec.p, rec.normal),
+                    rev: 0.0,
+                    delta: false,
+                },
+                beta: S::default(),
+            });
+            if bounce >= self.bounces.max {
+                break emitted;
+            }
+            let srec = match scatter(mat.as_ref(), &ray, &rec, &mut dispersed) {
+                Some(srec) => srec,
+                None => break emitted,
+            };
+
+            if let Some(specular_ray) = srec.specular_ray {
+                let response = throughput * srec.attenuation;
+                let survival = self
+                    .bounces
+                    .survival(bounce, response.max_component_value());
+                if rand::random::<Real>() >= survival {
+                    break emitted;
+                }
+                throughput = response / survival;
+                path.last_mut().unwrap().pdfs.delta = true;
+                folds.push(Vertex {
+                    emitted,
+                    scattering: Scattering::Specular(srec.attenuation / survival),
+                });
+                ray = specular_ray;
+                pdf_dir = 0.0;
+                bounce += 1;
+                continue;
+            }
+
+            let mat_pdf = srec.pdf.unwrap();
+            let scattered = Ray::new(rec.p, mat_pdf.generate());
+            let pdf_val = mat_pdf.value(scattered.direction);
+            let mut weight = if pdf_val > 0.0 {
+                mat.scattering_pdf(&ray, &rec, &scattered) / pdf_val
+            } else {
+                0.0
+            };
+            let response = throughput * mat.apply_diffuse(&rec, &S::new(1.0)) * weight;
+            let survival = self
+                .bounces
+                .survival(bounce, response.max_component_value());
+            let continues = weight > 0.0 && rand::random::<Real>() < survival;
+            if continues {
+                weight /= survival;
+                throughput = response / survival;
+            } else {
+                weight = 0.0;
+            }
+            set_reverse_pdf(&mut path, mat.as_ref(), &scattered);
+            folds.push(Vertex {
+                emitted,
+                scattering: Scattering::Diffuse {
+                    material: mat,
+                    rec,
+                    direct: S::default(),
+                    weight,
+                },
+            });
+            if !continues {
+                break S::default();
+            }
+            ray = scattered;
+            pdf_dir = pdf_val;
+            bounce += 1;
+        };
+        (path, folds, tail)
+    }
+
+    /// Trace a path from a point on one of the `lights`. The first vertex
+    /// is on the light.
+    fn light_path<S: Spectrum>(
+        &self,
+        lights: &LightList<S>,
+        scene: &Scene<S>,
+    ) -> Vec<BdptVertex<S>> {
+        let mut path = Vec::new();
+        let (rec, pdf_pos) = match lights.random_point() {
+            Some(sample) => sample,
+            None => return path,
+        };
+        let mat = match rec.material.clone() {
+            Some(mat) => mat,
+            None => return path,
+        };
+        let direction = CosinePDF::new(rec.normal).generate();
+        let pdf_dir = emission_pdf(rec.normal, direction);
+        let le = mat.emitted(
+            &Ray::new(rec.p + direction, -1.0 * direction),
+            &rec,
+            rec.u,
+            rec.v,
+            rec.p,
+        );
+        let mut beta = le * (abs_cos(rec.normal, direction) / (pdf_pos * pdf_dir));
+        path.push(BdptVertex {
+            p: rec.p,
+            normal: rec.normal,
+            surface: Some((rec.clone(), mat)),
+            r_in: Ray::NONE,
+            pdfs: VertexPdfs {
+                fwd: pdf_pos,
+                rev: 0.0,
+                delta: false,
+            },
+            beta: S::new(1.0 / pdf_pos),
+        });
+        if pdf_dir <= 0.0 {
+            return path;
+        }
+        // Russian roulette follows the light relative to what left the light.
+        let beta_start = beta.max_component_value();
+        let mut ray = Ray::new(rec.p, direction);
+        let mut pdf_dir = pdf_dir;
+        let mut dispersed = None;
+        let mut bounce = 0;
+        while path.len() <= self.bounces.max && beta_start > 0.0 {
+            let rec = match scene.world.hit(&ray) {
+                Some(rec) => rec,
+                None => break,
+            };
+            let mat = match rec.material.clone() {
+                Some(mat) => mat,
+                None => break,
+            };
+            path.push(BdptVertex {
+                p: rec.p,
+                normal: rec.normal,
+                surface: Some((rec.clone(), mat.clone())),
+                r_in: ray,
+                pdfs: VertexPdfs {
+                    fwd: to_area(pdf_dir, ray.origin, rec.p, rec.normal),
+                    rev: 0.0,
+                    delta: false,
+                },
+                beta,
+            });
+            let srec = match scatter(mat.as_ref(), &ray, &rec, &mut dispersed) {
+                Some(srec) => srec,
+                None => break,
+            };
+
+            if let Some(specular_ray) = srec.specular_ray {
+                path.last_mut().unwrap().pdfs.delta = true;
+                beta *= srec.attenuation;
+                ray = specular_ray;
+                pdf_dir = 0.0;
+            } else {
+                let mat_pdf = srec.pdf.unwrap();
+                let scattered = Ray::new(rec.p, mat_pdf.generate());
+                let pdf_val = mat_pdf.value(scattered.direction);
+                let cos_in = abs_cos(rec.normal, ray.direction);
+                if pdf_val <= 0.0 || cos_in <= 0.0 {
+                    break;
+                }
+                // The material is evaluated as seen from the scattered
+                // direction, with the light arriving along the ray.
+                let viewer = Ray::new(rec.p + scattered.direction, -1.0 * scattered.direction);
+                let towards_light = Ray::new(rec.p, -1.0 * ray.direction);
+                let scattering_pdf_val = mat.scattering_pdf(&viewer, &rec, &towards_light);
+                beta = mat.apply_diffuse(&rec, &beta)
+                    * (scattering_pdf_val * abs_cos(rec.normal, scattered.direction)
+                        / (cos_in * pdf_val));
+                set_reverse_pdf(&mut path, mat.as_ref(), &scattered);
+                ray = scattered;
+                pdf_dir = pdf_val;
+            }
+
+            let survival = self
+                .bounces
+                .survival(bounce, beta.max_component_value() / beta_start);
+            if rand::random::<Real>() >= survival {
+                break;
+            }
+            beta = beta / survival;
+            bounce += 1;
+        }
+        path
+    }
+
+    /// Weight of the emission found by the last vertex of `camera`.
+    fn emission_weight<S: Spectrum>(&self, camera: &[BdptVertex<S>], scene: &Scene<S>) -> Real {
+        let t = camera.len();
+        if t == 2 {
+            // Light seen directly is only found by camera paths.
+            return 1.0;
+        }
+        let x = &camera[t - 1];
+        let area_pdf = match scene.light {
+            Some(lights) => lights.area_pdf(&x.r_in, x.surface().0),
+            None => 0.0,
+        };
+        if area_pdf <= 0.0 {
+            return 1.0;
+        }
+        let mut c: Vec<VertexPdfs> = camera.iter().map(|v| v.pdfs).collect();
+        c[t - 1].rev = area_pdf;
+        let prev = &camera[t - 2];
+        c[t - 2].rev = to_area(
+            emission_pdf(x.normal, prev.p - x.p),
+            x.p,
+            prev.p,
+            prev.normal,
+        );
+        mis_weight(self.heuristic, &c, &[])
+    }
+
+    /// Light brought to the last vertex of `camera` by the last vertex of
+    /// `light`, weighted and ready to be scattered by the camera vertex.
+    fn connect<S: Spectrum>(
+        &self,
+        camera: &[BdptVertex<S>],
+        light: &[BdptVertex<S>],
+        scene: &Scene<S>,
+    ) -> S {
+        let (t, s) = (camera.len(), light.len());
+        let x = &camera[t - 1];
+        let y = &light[s - 1];
+        if x.pdfs.delta || y.pdfs.delta {
+            return S::default();
+        }
+        let (x_rec, x_mat) = x.surface();
+        let d = y.p - x.p;
+        let distance_squared = d.squared_length();
+        let to_y = Ray::new(x.p, d);
+        let scattering_pdf_val = x_mat.scattering_pdf(&x.r_in, x_rec, &to_y);
+        if distance_squared <= 0.0 || scattering_pdf_val <= 0.0 {
+            return S::default();
+        }
+        let ly = leaving_light(light, &to_y);
+        if ly.is_black() || !visible(scene, x.p, y.p) {
+            return S::default();
+        }
+
+        let mut c: Vec<VertexPdfs> = camera.iter().map(|v| v.pdfs).collect();
+        let mut l: Vec<VertexPdfs> = light.iter().map(|v| v.pdfs).collect();
+        let (y_rec, y_mat) = y.surface();
+        let y_pdf = if s == 1 {
+            emission_pdf(y.normal, -1.0 * d)
+        } else {
+            material_pdf(y_mat, y_rec, &y.r_in, -1.0 * d)
+        };
+        c[t - 1].rev = to_area(y_pdf, y.p, x.p, x.normal);
+        if t > 2 {
+            let prev = &camera[t - 2];
+            let pdf = material_pdf(x_mat, x_rec, &Ray::new(y.p, -1.0 * d), prev.p - x.p);
+            c[t - 2].rev = to_area(pdf, x.p, prev.p, prev.normal);
+        }
+        l[s - 1].rev = to_area(material_pdf(x_mat, x_rec, &x.r_in, d), x.p, y.p, y.normal);
+        if s > 1 {
+            let prev = &light[s - 2];
+            let pdf = material_pdf(y_mat, y_rec, &to_y, prev.p - y.p);
+            l[s - 2].rev = to_area(pdf, y.p, prev.p, prev.normal);
+        }
+        let weight = mis_weight(self.heuristic, &c, &l);
+        ly * (scattering_pdf_val * weight / distance_squared)
+    }
+
+    /// Connect the last vertex of `light` to the camera and add the light
+    /// to the pixel it lands on.
+    fn splat<S: Spectrum>(
+        &self,
+        light: &[BdptVertex<S>],
+        scene: &Scene<S>,
+        gain: Real,
+        splats: &mut Splats,
+    ) {
+        let s = light.len();
+        let y = &light[s - 1];
+        if y.pdfs.delta {
+            return;
+        }
+        let (film_s, film_t, to_y) = match scene.camera.film_point(y.p) {
+            Some(sample) => sample,
+            None => return,
+        };
+        let ly = leaving_light(light, &to_y);
+        if ly.is_black() || !visible(scene, to_y.origin, y.p) {
+            return;
+        }
+        let pdf_camera = scene.camera.direction_pdf(to_y.direction);
+        let distance_squared = to_y.direction.squared_length();
+
+        let mut l: Vec<VertexPdfs> = light.iter().map(|v| v.pdfs).collect();
+        l[s - 1].rev = to_area(pdf_camera, to_y.origin, y.p, y.normal);
+        if s > 1 {
+            let (y_rec, y_mat) = y.surface();
+            let prev = &light[s - 2];
+            let pdf = material_pdf(y_mat, y_rec, &to_y, prev.p - y.p);
+            l[s - 2].rev = to_area(pdf, y.p, prev.p, prev.normal);
+        }
+        let camera = VertexPdfs {
+            fwd: 1.0,
+            rev: 0.0,
+            delta: false,
+        };
+        let weight = mis_weight(self.heuristic, &[camera], &l);
+        let importance = pdf_camera / distance_squared;
+        splats.add(film_s, film_t, &(ly * (importance * weight * gain)));
+    }
+}
+
+/// Light leaving the last vertex of the light path `light` towards the
+/// viewer that `r_in` comes from, times the cosine at the vertex.
+fn leaving_light<S: Spectrum>(light: &[BdptVertex<S>], r_in: &Ray) -> S {
+    let y = &light[light.len() - 1];
+    let (rec, mat) = y.surface();
+    let cos_out = abs_cos(y.normal, r_in.direction);
+    if light.len() == 1 {
+        return mat.emitted(r_in, rec, rec.u, rec.v, rec.p) * y.beta * cos_out;
+    }
+    let cos_in = abs_cos(y.normal, y.r_in.direction);
+    if cos_in <= 0.0 {
+        return S::default();
+    }
+    let towards_light = Ray::new(y.p, -1.0 * y.r_in.direction);
+    let scattering_pdf_val = mat.scattering_pdf(r_in, rec, &towards_light);
+    mat.apply_diffuse(rec, &y.beta) * (scattering_pdf_val * cos_out / cos_in)
+}
+
+/// Weight of the path built from the vertices `camera` and `light` against
+/// all other ways of building it, from the ratios of the densities.
+fn mis_weight(heuristic: MisHeuristic, camera: &[VertexPdfs], light: &[VertexPdfs]) -> Real {
+    // Delta vertices have no density, their ratio cancels.
+    let remap = |pdf: Real| {
+        let pdf = if pdf == 0.0 { 1.0 } else { pdf };
+        match heuristic {
+            MisHeuristic::Balance => pdf,
+            MisHeuristic::Power => pdf * pdf,
+        }
+    };
+    let mut sum = 0.0;
+    let mut ratio = 1.0;
+    for i in (1..camera.len()).rev() {
+        ratio *= remap(camera[i].rev) / remap(camera[i].fwd);
+        if !camera[i].delta && !camera[i - 1].delta {
+            sum += ratio;
+        }
+    }
+    ratio = 1.0;
+    for i in (0..light.len()).rev() {
+        ratio *= remap(light[i].rev) / remap(light[i].fwd);
+        let prev_delta = i > 0 && light[i - 1].delta;
+        if !light[i].delta && !prev_delta {
+            sum += ratio;
+        }
+    }
+    1.0 / (1.0 + sum)
+}
+
+/// Set the reverse density of the second to last vertex of `path`, for the
+/// last vertex scattering towards it with `mat` when the path arrives along
+/// `scattered` the other way.
+fn set_reverse_pdf<S: Spectrum>(
+    path: &mut [BdptVertex<S>],
+    mat: &dyn Material<S>,
+    scattered: &Ray,
+) {
+    let n = path.len();
+    if n < 2 {
+        return;
+    }
+    let (x, prev) = (&path[n - 1], &path[n - 2]);
+    let viewer = Ray::new(x.p + scattered.direction, -1.0 * scattered.direction);
+    let pdf = material_pdf(mat, x.surface().0, &viewer, prev.p - x.p);
+    path[n - 2].pdfs.rev = to_area(pdf, x.p, prev.p, prev.normal);
+}
+
+/// Density per solid angle of the material at `rec` scattering the ray
+/// `r_in` into `direction`. Zero if it scatters specularly.
+fn material_pdf<S: Spectrum>(
+    mat: &dyn Material<S>,
+    rec: &Hit<S>,
+    r_in: &Ray,
+    direction: Vec3,
+) -> Real {
+    match mat.scatter(r_in, rec).and_then(|srec| srec.pdf) {
+        Some(pdf) => pdf.value(direction),
+        None => 0.0,
+    }
+}
+
+/// Density per solid angle of light paths leaving a light with the normal
+/// `normal` in `direction`.
+fn emission_pdf(normal: Vec3, direction: Vec3) -> Real {
+    normal
+        .make_unit_vector()
+        .dot(direction.make_unit_vector())
+        .max(0.0)
+        / PI
+}
+
+/// Convert the density `pdf` per solid angle at `from` to a density per
+/// area at the point `to` with the normal `normal`.
+fn to_area(pdf: Real, from: Vec3, to: Vec3, normal: Vec3) -> Real {
+    let d = to - from;
+    let distance_squared = d.squared_length();
+    if distance_squared <= 0.0 {
+        return 0.0;
+    }
+    pdf * abs_cos(normal, d) / distance_squared
+}
+
+fn abs_cos(normal: Vec3, v: Vec3) -> Real {
+    normal.make_unit_vector().dot(v.make_unit_vector()).abs()
+}
+
+/// Whether nothing blocks the segment from `a` to `b`.
+fn visible<S: Spectrum>(scene: &Scene<S>, a: Vec3, b: Vec3) -> bool {
+    match scene.world.hit(&Ray::new(a, b - a)) {
+        Some(rec) => rec.t > 1.0 - 1e-3,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+    use crate::model::Sphere;
+
+    const BOUNCES: Bounces = Bounces { min: 3, max: 500 };
+
+    /// Inside of a sphere that emits `emit` and reflects `albedo`, with the
+    /// normals facing in. The radiance everywhere is `emit / (1 - albedo)`.
+    fn furnace(
+        albedo: SampledSpectrum,
+        emit: SampledSpectrum,
+    ) -> Vec<Box<dyn Hitable<SampledSpectrum>>> {
+        vec![Box::new(Sphere {
+            center: Vec3::ZEROS,
+            radius: -1.0,
+            material: Some(Arc::new(Lambertian {
+                albedo: Arc::new(albedo),
+                emit: Arc::new(emit),
+            })),
+        })]
+    }
+
+    fn camera() -> Camera {
+        Camera::new(
+            Vec3::ZEROS,
+            Vec3(0.0, 0.0, 1.0),
+            Vec3(0.0, 1.0, 0.0),
+            40.0,
+            1.0,
+            0.0,
+            1.0,
+        )
+    }
+
+    /// Mean linear RGB of `n` samples along the ray from the centre of the
+    /// scene.
+    fn estimate<I: Integrator<SampledSpectrum>>(
+        integrator: &I,
+        scene: &Scene<SampledSpectrum>,
+        n: usize,
+    ) -> [Real; 3] {
+        let r = Ray::new(Vec3::ZEROS, Vec3(0.0, 0.0, 1.0));
+        let mut rgb = [0.0; 3];
+        let mut splats = Splats::new(1, 1, 0);
+        for _ in 0..n {
+            integrator.add_sample(&r, scene, 1.0 / n as Real, &mut rgb, &mut [], &mut splats);
+        }
+        for (c, s) in rgb.iter_mut().zip(splats.rgb) {
+            *c += s / n as Real;
+        }
+        rgb
+    }
+
+    fn assert_rgb_close(a: [Real; 3], b: [Real; 3], tolerance: Real) {
+        for (a, b) in a.iter().zip(b.iter()) {
+            assert!((a - b).abs() <= tolerance * b.abs(), "{:?} != {:?}", a, b);
+        }
+    }
+
+    /// Albedo rising from 0.2 at the start of the spectrum to 0.8 at the
+    /// end, and the radiance it gives in the furnace for white emission.
+    fn ramp_albedo() -> (SampledSpectrum, [Real; 3]) {
+        let grid = spectral_grid();
+        let albedo =
+            SampledSpectrum::from_sampled(&[grid.lambda_start, grid.lambda_end], &[0.2, 0.8], 2);
+        let mut radiance = albedo;
+        for v in radiance.values_mut() {
+            *v = 1.0 / (1.0 - *v);
+        }
+        let mut rgb = [0.0; 3];
+        radiance.to_rgb(&mut rgb);
+        (albedo, rgb)
+    }
+
+    #[test]
+    fn hero_wavelengths_cover_the_range() {
+        let grid = spectral_grid();
+        let range = grid.lambda_end - grid.lambda_start;
+        for u in [0.0, 0.3, 0.99] {
+            let lambda = sample_hero_wavelengths(u);
+            assert_eq!(lambda[0], grid.lambda_start + u * range);
+            for l in lambda.iter() {
+                assert!(*l >= grid.lambda_start && *l < grid.lambda_end, "{}", l);
+            }
+            // Spread evenly, wrapping around at the end of the range.
+            let mut sorted = lambda;
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            for pair in sorted.windows(2) {
+                let step = range / N_HERO_WAVELENGTHS as Real;
+                assert!((pair[1] - pair[0] - step).abs() < 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn hero_matches_the_full_spectrum_in_a_furnace() {
+        let (albedo, expected) = ramp_albedo();
+        let world = furnace(albedo, SampledSpectrum::new(1.0));
+        let camera = camera();
+        let scene = Scene {
+            world: &world,
+            light: None,
+            camera: &camera,
+            env_light: None,
+        };
+        let path = estimate(&PathIntegrator { bounces: BOUNCES }, &scene, 20000);
+        assert_rgb_close(path, expected, 0.03);
+        let hero = HeroIntegrator {
+            mis: None,
+            bounces: BOUNCES,
+        };
+        assert_rgb_close(estimate(&hero, &scene, 20000), expected, 0.03);
+    }
+
+    /// A small black light of radiance `EMIT` straight above a large ground
+    /// of albedo `GROUND`, and the radiance of the ground right below the
+    /// light. The light subtends the cone with the sine `r / d`, so the
+    /// ground reflects `GROUND * EMIT * (r / d)^2`.
+    const EMIT: Real = 10.0;
+    const GROUND: Real = 0.5;
+
+    fn lit_ground() -> (Vec<Box<dyn Hitable<SampledSpectrum>>>, [Real; 3]) {
+        let lambertian = |albedo: Real, emit: Real| {
+            Some(Arc::new(Lambertian {
+                albedo: Arc::new(SampledSpectrum::new(albedo)),
+                emit: Arc::new(SampledSpectrum::new(emit)),
+            }) as Arc<dyn Material<SampledSpectrum> + Send>)
+        };
+        let world: Vec<Box<dyn Hitable<SampledSpectrum>>> = vec![
+            Box::new(Sphere {
+                center: Vec3(0.0, -1000.0, 0.0),
+                radius: 1000.0,
+                material: lambertian(GROUND, 0.0),
+            }),
+            Box::new(Sphere {
+                center: Vec3(0.0, 3.0, 0.0),
+                radius: 0.5,
+                material: lambertian(0.0, EMIT),
+            }),
+        ];
+        let mut rgb = [0.0; 3];
+        SampledSpectrum::new(GROUND * EMIT * 0.25 / 9.0).to_rgb(&mut rgb);
+        (world, rgb)
+    }
+
+    /// Mean linear RGB of `n` samples of the ground below the light, and
+    /// what it should be.
+    fn estimate_ground<I: Integrator<SampledSpectrum>>(
+        integrator: &I,
+        n: usize,
+    ) -> ([Real; 3], [Real; 3]) {
+        let (world, expected) = lit_ground();
+        let lights = LightList::new(&world);
+        let camera = camera();
+        let scene = Scene {
+            world: &world,
+            light: Some(&lights),
+            camera: &camera,
+            env_light: None,
+        };
+        let r = Ray::new(Vec3(0.0, 1.0, -1.0), Vec3(0.0, -1.0, 1.0));
+        let mut rgb = [0.0; 3];
+        let mut splats = Splats::new(1, 1, 0);
+        for _ in 0..n {
+            integrator.add_sample(&r, &scene, 1.0 / n as Real, &mut rgb, &mut [], &mut splats);
+        }
+        (rgb, expected)
+    }
+
+    #[test]
+    fn mis_weights_add_up_to_one() {
+        for heuristic in [MisHeuristic::Balance, MisHeuristic::Power] {
+            let (a, b) = (0.3, 1.7);
+            let sum = heuristic.weight(a, b) + heuristic.weight(b, a);
+            assert!((sum - 1.0).abs() < 1e-6);
+            assert_eq!(heuristic.weight(Real::INFINITY, 1.0), 1.0);
+            assert_eq!(heuristic.weight(0.0, 0.0), 0.0);
+        }
+        assert!(MisHeuristic::Power.weight(2.0, 1.0) > MisHeuristic::Balance.weight(2.0, 1.0));
+    }
+
+    #[test]
+    fn mis_finds_the_direct_light() {
+        for heuristic in [MisHeuristic::Balance, MisHeuristic::Power] {
+            let mis = MisIntegrator {
+                heuristic,
+                bounces: BOUNCES,
+            };
+            let (rgb, expected) = estimate_ground(&mis, 5000);
+            assert_rgb_close(rgb, expected, 0.03);
+            let hero = HeroIntegrator {
+                mis: Some(heuristic),
+                bounces: BOUNCES,
+            };
+            let (rgb, expected) = estimate_ground(&hero, 5000);
+            assert_rgb_close(rgb, expected, 0.03);
+        }
+        let (rgb, expected) = estimate_ground(&PathIntegrator { bounces: BOUNCES }, 20000);
+        assert_rgb_close(rgb, expected, 0.03);
+    }
+
+    #[test]
+    fn roulette_only_after_the_minimum() {
+        let bounces = Bounces { min: 2, max: 10 };
+        assert_eq!(bounces.survival(1, 0.1), 1.0);
+        assert_eq!(bounces.survival(2, 0.1), 0.1);
+        assert_eq!(bounces.survival(2, 3.0), 1.0);
+    }
+
+    /// Mean of `n` samples in the furnace of white emission and grey
+    /// albedo 0.5, relative to the emission.
+    fn grey_furnace<I: Integrator<SampledSpectrum>>(integrator: &I, n: usize) -> Real {
+        let world = furnace(SampledSpectrum::new(0.5), SampledSpectrum::new(1.0));
+        let camera = camera();
+        let scene = Scene {
+            world: &world,
+            light: None,
+            camera: &camera,
+            env_light: None,
+        };
+        let mut white = [0.0; 3];
+        SampledSpectrum::new(1.0).to_rgb(&mut white);
+        estimate(integrator, &scene, n)[1] / white[1]
+    }
+
+    #[test]
+    fn bounces_limit_the_path() {
+        // Every bounce adds half of the light of the one before.
+        for (bounces, expected) in [(0, 1.0), (1, 1.5), (3, 1.875)] {
+            let path = PathIntegrator {
+                bounces: Bounces {
+                    min: bounces,
+                    max: bounces,
+                },
+            };
+            let v = grey_furnace(&path, 100);
+            assert!((v - expected).abs() < 1e-4, "{} != {}", v, expected);
+        }
+    }
+
+    #[test]
+    fn roulette_keeps_the_mean() {
+        let bounces = Bounces { min: 0, max: 500 };
+        let path = PathIntegrator { bounces };
+        let v = grey_furnace(&path, 100000);
+        assert!((v - 2.0).abs() < 0.03, "{}", v);
+        let hero = HeroIntegrator { mis: None, bounces };
+        let v = grey_furnace(&hero, 100000);
+        assert!((v - 2.0).abs() < 0.03, "{}", v);
+    }
+
+    #[test]
+    fn bdpt_agrees_with_path_tracing_in_a_furnace() {
+        let (albedo, expected) = ramp_albedo();
+        let world = furnace(albedo, SampledSpectrum::new(1.0));
+        let lights = LightList::new(&world);
+        let camera = camera();
+        let scene = Scene {
+            world: &world,
+            light: Some(&lights),
+            camera: &camera,
+            env_light: None,
+        };
+        let bounces = Bounces { min: 3, max: 100 };
+        for heuristic in [MisHeuristic::Balance, MisHeuristic::Power] {
+            let bdpt = BdptIntegrator { heuristic, bounces };
+            assert_rgb_close(estimate(&bdpt, &scene, 5000), expected, 0.03);
+            let mis = MisIntegrator { heuristic, bounces };
+            assert_rgb_close(estimate(&mis, &scene, 20000), expected, 0.03);
+        }
+        let path = PathIntegrator { bounces };
+        assert_rgb_close(estimate(&path, &scene, 20000), expected, 0.03);
+    }
+}
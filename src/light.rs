@@ -0,0 +1,204 @@
+//! Lights are the objects of the scene with an emitting material. Paths send
+//! rays towards them to find the light, picking one light in proportion to
+//! the power it emits.
+
+use crate::aabb::AABB;
+use crate::core::*;
+use crate::hitable::{Hit, Hitable};
+use crate::ray::Ray;
+use crate::sampling::Distribution1D;
+
+/// The lights of a scene. As a `Hitable` it is sampled like a single object:
+/// `random` picks a light by its power and a direction towards it, and
+/// `pdf_value` is the density of that over all lights.
+pub struct LightList<'a, S: Spectrum> {
+    lights: Vec<&'a dyn Hitable<S>>,
+    distribution: Distribution1D,
+}
+
+impl<'a, S: Spectrum> LightList<'a, S> {
+    /// Collect the objects with an emitting material. Objects that can not
+    /// be sampled, see `Hitable::area`, are left out and only found by paths
+    /// that happen to hit them.
+    pub fn new(objects: &'a [Box<dyn Hitable<S>>]) -> LightList<'a, S> {
+        let mut lights = Vec::new();
+        let mut power = Vec::new();
+        for object in objects.iter() {
+            let emission: Real = object
+                .materials()
+                .iter()
+                .map(|m| {
+                    let e = m.average_emission();
+                    e.values().iter().sum::<Real>() / e.values().len() as Real
+                })
+                .sum();
+            if emission <= 0.0 {
+                continue;
+            }
+            let area = object.area();
+            if area <= 0.0 {
+                continue;
+            }
+            lights.push(object.as_ref());
+            // Radiance over a Lambertian emitter integrates to pi.
+            power.push(PI * area * emission);
+        }
+        LightList {
+            lights,
+            distribution: Distribution1D::new(&power),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    /// A point on a light, picked by the power of the light and uniformly
+    /// over its surface, and the density of the point per area.
+    pub fn random_point(&self) -> Option<(Hit<S>, Real)> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let (i, pdf, _) = self.distribution.sample_discrete(rand::random::<Real>());
+        let rec = self.lights[i].random_point()?;
+        Some((rec, pdf / self.lights[i].area()))
+    }
+
+    /// Density per area of `random_point` picking the point `rec` that the
+    /// ray `r` hit. Zero if it is not on a light.
+    pub fn area_pdf(&self, r: &Ray, rec: &Hit<S>) -> Real {
+        for (i, light) in self.lights.iter().enumerate() {
+            if let Some(light_rec) = light.hit(r) {
+                if (light_rec.t - rec.t).abs() <= 1e-4 * rec.t {
+                    return self.distribution.discrete_pdf(i) / light.area();
+                }
+            }
+        }
+        0.0
+    }
+}
+
+impl<'a, S: Spectrum> Hitable<S> for LightList<'a, S> {
+    fn hit(&self, r: &Ray) -> Option<Hit<S>> {
+        let mut rec: Option<Hit<S>> = None;
+        for light in self.lights.iter() {
+            if let Some(temp_rec) = light.hit(r) {
+                if rec.as_ref().is_none_or(|rec| temp_rec.t < rec.t) {
+                    rec = Some(temp_rec);
+                }
+            }
+        }
+        rec
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let mut bbox: Option<AABB> = None;
+        for light in self.lights.iter() {
+            let light_bbox = light.bounding_box()?;
+            bbox = Some(match bbox {
+                Some(bbox) => AABB::surrounding_box(bbox, light_bbox),
+                None => light_bbox,
+            });
+        }
+        bbox
+    }
+
+    fn pdf_value(&self, origin: Vec3, v: Vec3) -> Real {
+        self.lights
+            .iter()
+            .enumerate()
+            .map(|(i, light)| self.distribution.discrete_pdf(i) * light.pdf_value(origin, v))
+            .sum()
+    }
+
+    fn random(&self, origin: Vec3) -> Vec3 {
+        let (i, _, _) = self.distribution.sample_discrete(rand::random::<Real>());
+        self.lights[i].random(origin)
+    }
+
+    fn area(&self) -> Real {
+        self.lights.iter().map(|light| light.area()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+    use crate::model::Sphere;
+    use crate::ray::random_in_unit_sphere;
+    use crate::spectrum::SampledSpectrum;
+    use std::sync::Arc;
+
+    fn sphere(x: Real, radius: Real, emit: Real) -> Box<dyn Hitable<SampledSpectrum>> {
+        Box::new(Sphere {
+            center: Vec3(x, 0.0, 0.0),
+            radius,
+            material: Some(Arc::new(Lambertian {
+                albedo: Arc::new(SampledSpectrum::new(0.5)),
+                emit: Arc::new(SampledSpectrum::new(emit)),
+            })),
+        })
+    }
+
+    /// A dim large light, a bright small one with twice its power, and an
+    /// object that does not emit.
+    fn objects() -> Vec<Box<dyn Hitable<SampledSpectrum>>> {
+        vec![
+            sphere(-3.0, 2.0, 1.0),
+            sphere(0.0, 1.0, 0.0),
+            sphere(3.0, 1.0, 8.0),
+        ]
+    }
+
+    #[test]
+    fn lights_are_picked_by_power() {
+        let objects = objects();
+        let lights = LightList::new(&objects);
+        assert_eq!(lights.len(), 2);
+        let n = 30000;
+        let mut bright = 0;
+        for _ in 0..n {
+            let (rec, pdf) = lights.random_point().unwrap();
+            let (area, p) = if rec.p.x() > 0.0 {
+                bright += 1;
+                (4.0 * PI, 2.0 / 3.0)
+            } else {
+                (16.0 * PI, 1.0 / 3.0)
+            };
+            assert!((pdf - p / area).abs() < 1e-6, "{} != {}", pdf, p / area);
+        }
+        let fraction = bright as Real / n as Real;
+        assert!((fraction - 2.0 / 3.0).abs() < 0.02, "{}", fraction);
+
+        // A ray that hits a light finds the same density.
+        let r = Ray::new(Vec3(3.0, 5.0, 0.0), Vec3(0.0, -1.0, 0.0));
+        let rec = lights.hit(&r).unwrap();
+        assert!((lights.area_pdf(&r, &rec) - 2.0 / 3.0 / (4.0 * PI)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn direction_density_integrates_to_one() {
+        let objects = objects();
+        let lights = LightList::new(&objects);
+        let origin = Vec3(0.0, 4.0, 0.0);
+        let n = 200000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let d = random_in_unit_sphere().make_unit_vector();
+            sum += lights.pdf_value(origin, d);
+        }
+        let integral = sum * 4.0 * PI / n as Real;
+        assert!((integral - 1.0).abs() < 0.03, "{}", integral);
+
+        // Sampled directions point at a light.
+        for _ in 0..100 {
+            let d = lights.random(origin);
+            assert!(lights.pdf_value(origin, d) > 0.0);
+        }
+    }
+}
@@ -1,7 +1,8 @@
 #![allow(unused)]
 
+use clap::{Parser, ValueEnum};
 use rayon::prelude::*;
-use std::sync::Arc;
+use std::sync::{Arc, Once};
 
 #[macro_use]
 extern crate lazy_static;
@@ -9,17 +10,35 @@ extern crate lazy_static;
 mod aabb;
 mod camera;
 mod cgmath;
+mod dispersion;
+mod donaldson;
+mod gauss_fit;
 mod hitable;
+mod integrator;
+mod light;
 mod materials;
+mod mesh;
+mod mesh_io;
+mod metals;
+mod microfacet;
 mod model;
+mod output;
 mod ray;
+mod sampling;
+mod scene_file;
 mod scenes;
+mod settings;
 mod spectrum;
+mod texture;
+mod uplift;
 mod utils;
 
 mod core {
     pub type Real = f32;
     pub const EPS: Real = 1e-5;
+    /// Shortest distance along a ray at which it can hit an object, so that
+    /// rays leaving a surface do not hit it again because of rounding.
+    pub const HIT_EPS: Real = 1e-3;
     pub const R_MAX: Real = std::f32::MAX;
     pub const PI: Real = std::f32::consts::PI;
     pub use crate::cgmath::{Quaternion, Vec3};
@@ -28,170 +47,392 @@ mod core {
 }
 use camera::Camera;
 use cgmath::{Quaternion, Vec3};
-use hitable::Hitable;
+use hitable::{HitList, Hitable};
+use integrator::{
+    BdptIntegrator, HeroIntegrator, Integrator, MisIntegrator, PathIntegrator, Scene, Splats,
+};
+use light::LightList;
 use ray::{CosinePDF, HitablePDF, MixturePDF, Ray, PDF};
 // use materials::{Material};
 use crate::core::*;
 use model::*;
 use scenes::*;
+use settings::{Command, FitSettings, IntegratorKind, RenderSettings, SpectrumKind};
 use spectrum::*; //{RGBSpectrum, Spectrum, SampledSpectrum};
 
-const LIGHT_SHAPE: Plane = Plane {
-    origin: Vec3(278.0, 554.0, 279.5),
-    normal: Vec3(0.0, -1.0, 0.0),
-    rot_around_normal: 0.0,
-    width: 130.0,
-    height: 105.0,
-    material: None,
-};
+fn main() -> std::io::Result<()> {
+    let settings = RenderSettings::parse();
 
-lazy_static! {
-    static ref ENV_LIGHT: Option<&'static Spectrum> = None; //Some(&spectrum::ILLUMINATION_HALOGEN);
-}
+    let grid = spectrum::SpectralGrid::new(
+        settings.lambda_start,
+        settings.lambda_end,
+        settings.spectral_bins,
+    );
+    if let Err(err) = grid.and_then(spectrum::set_spectral_grid) {
+        eprintln!("Invalid spectral grid: {}", err);
+        std::process::exit(1);
+    }
+    if let Err(err) = uplift::set_uplift_method(settings.uplift) {
+        eprintln!("Invalid uplifting method: {}", err);
+        std::process::exit(1);
+    }
 
-fn color(
-    r: &Ray,
-    world: &dyn Hitable,
-    light: &dyn Hitable,
-    env_light: Option<&Spectrum>,
-    depth: usize,
-) -> Spectrum {
-    match world.hit(r) {
-        Some(rec) => {
-            let emitted;
-            match rec.material.as_ref() {
-                Some(mat) => {
-                    emitted = mat.emitted(&r, &rec, rec.u, rec.v, rec.p);
-                    if depth < 10 {
-                        if let Some(srec) = mat.scatter(&r, &rec) {
-                            if let Some(specular_ray) = srec.specular_ray {
-                                return srec.attenuation
-                                    * color(&specular_ray, world, light, env_light, depth + 1);
-                                //return color(&specular_ray, world, light, depth+1);
-                            } else {
-                                let hitable_pdf = HitablePDF::new(light, rec.p);
-                                let mat_pdf = srec.pdf.unwrap();
-                                let p = MixturePDF::new(&hitable_pdf, mat_pdf.as_ref());
-                                //let p = CosinePDF::new(rec.normal);
-
-                                let scattered = Ray {
-                                    origin: rec.p,
-                                    direction: p.generate(),
-                                };
-                                let pdf_val = p.value(scattered.direction);
-                                if pdf_val == 0.0 {
-                                    return emitted.clone();
-                                }
-                                let scattering_pdf_val = mat.scattering_pdf(&r, &rec, &scattered);
-
-                                let spectrum_in = scattering_pdf_val
-                                    * color(&scattered, world, light, env_light, depth + 1)
-                                    / (pdf_val + 1e-5);
-
-                                let val = emitted + mat.apply_diffuse(&spectrum_in);
-
-                                return val.clone();
-                            }
-                        }
-                    }
-                }
-                None => {
-                    return spectrum::Spectrum::default();
-                }
+    if let Some(Command::FitRerad(fit)) = &settings.command {
+        return fit_rerad(fit);
+    }
+
+    for path in settings.output.iter() {
+        if output::ImageFormat::from_path(path).is_none() {
+            eprintln!(
+                "Unknown output format for {}, expected .png, .exr or .pfm",
+                path.display()
+            );
+            std::process::exit(1);
+        }
+    }
+    if settings.integrator == IntegratorKind::Bdpt
+        && settings.spectrum.contains(&SpectrumKind::Hero)
+    {
+        eprintln!("The bdpt integrator needs the rgb or sampled spectrum, see --spectrum");
+        std::process::exit(1);
+    }
+    for path in settings.spectral_output.iter() {
+        if !settings.spectrum.contains(&SpectrumKind::Sampled)
+            && !settings.spectrum.contains(&SpectrumKind::Hero)
+        {
+            eprintln!("Spectral output needs the sampled spectrum, see --spectrum");
+            std::process::exit(1);
+        }
+        if output::SpectralFormat::from_path(path).is_none() {
+            eprintln!(
+                "Unknown spectral output format for {}, expected .hdr or .exr",
+                path.display()
+            );
+            std::process::exit(1);
+        }
+    }
+    if settings.min_bounces > settings.max_bounces {
+        eprintln!(
+            "--min-bounces ({}) must not be larger than --max-bounces ({})",
+            settings.min_bounces, settings.max_bounces
+        );
+        std::process::exit(1);
+    }
+
+    if let Some(threads) = settings.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("Failed to set up thread pool.");
+    }
+
+    // Render every representation once, in the order given.
+    let mut kinds: Vec<SpectrumKind> = Vec::new();
+    for kind in settings.spectrum.iter() {
+        if !kinds.contains(kind) {
+            kinds.push(*kind);
+        }
+    }
+    let add_suffix = kinds.len() > 1;
+    let mut written = true;
+    for kind in kinds {
+        let name = kind.to_possible_value().unwrap();
+        let name = name.get_name();
+        let bounces = settings.bounces();
+        let path = PathIntegrator { bounces };
+        let mis = MisIntegrator {
+            heuristic: settings.mis_heuristic,
+            bounces,
+        };
+        let bdpt = BdptIntegrator {
+            heuristic: settings.mis_heuristic,
+            bounces,
+        };
+        let result = match (kind, settings.integrator) {
+            (SpectrumKind::Rgb, IntegratorKind::Path) => {
+                render::<RGBSpectrum, _>(&settings, name, add_suffix, &path)
+            }
+            (SpectrumKind::Rgb, IntegratorKind::Mis) => {
+                render::<RGBSpectrum, _>(&settings, name, add_suffix, &mis)
+            }
+            (SpectrumKind::Rgb, IntegratorKind::Bdpt) => {
+                render::<RGBSpectrum, _>(&settings, name, add_suffix, &bdpt)
+            }
+            (SpectrumKind::Sampled, IntegratorKind::Path) => {
+                render::<SampledSpectrum, _>(&settings, name, add_suffix, &path)
+            }
+            (SpectrumKind::Sampled, IntegratorKind::Mis) => {
+                render::<SampledSpectrum, _>(&settings, name, add_suffix, &mis)
+            }
+            (SpectrumKind::Sampled, IntegratorKind::Bdpt) => {
+                render::<SampledSpectrum, _>(&settings, name, add_suffix, &bdpt)
+            }
+            (SpectrumKind::Hero, integrator) => {
+                let hero = HeroIntegrator {
+                    mis: (integrator == IntegratorKind::Mis).then_some(settings.mis_heuristic),
+                    bounces,
+                };
+                render::<SampledSpectrum, _>(&settings, name, add_suffix, &hero)
             }
-            return emitted.clone();
+        };
+        written &= result.is_ok();
+    }
+
+    if !written {
+        eprintln!("Not all images could be written");
+        std::process::exit(1);
+    }
+    println!("Done.");
+    Ok(())
+}
+
+/// Fit Gaussians to a measured re-radiation matrix and write them as a scene
+/// file material.
+fn fit_rerad(settings: &FitSettings) -> std::io::Result<()> {
+    let path = &settings.matrix;
+    let matrix = donaldson::load_donaldson(path, settings.unit).and_then(|m| {
+        let m = if settings.transpose {
+            m.transposed()
+        } else {
+            m
+        };
+        m.check_grid(spectrum::spectral_grid())?;
+        Ok(m)
+    });
+    let matrix = match matrix {
+        Ok(matrix) => matrix,
+        Err(err) => {
+            eprintln!("Error loading {}: {}", path.display(), err);
+            std::process::exit(1);
+        }
+    };
+
+    eprintln!("Fitting {} Gaussians...", settings.gaussians);
+    let fit = gauss_fit::fit_gauss_records(&matrix, settings.gaussians, settings.iterations);
+    let report = format!(
+        "RMS residual {:e}, {:.2}% of the largest value, after {} iterations",
+        fit.rms,
+        100.0 * fit.rms / fit.max_value.max(EPS),
+        fit.iterations
+    );
+    eprintln!("{}", report);
+    if !fit.problems.is_empty() {
+        for problem in fit.problems.iter() {
+            eprintln!("The fitted re-radiation {}", problem);
+        }
+        eprintln!("Not writing a material the renderer would reject or clamp");
+        std::process::exit(1);
+    }
+
+    let grid = spectrum::spectral_grid();
+    let text = format!(
+        "# Fitted to {} for {} bins between {} and {} nm.\n# {}\n{}",
+        path.display(),
+        grid.n,
+        grid.lambda_start,
+        grid.lambda_end,
+        report,
+        gauss_fit::to_scene_material(&settings.name, &fit.records)
+    );
+    match &settings.output {
+        Some(output) => std::fs::write(output, text),
+        None => {
+            print!("{}", text);
+            Ok(())
         }
-        None => match env_light {
-            Some(illum) => *illum,
-            None => spectrum::Spectrum::default(),
-        },
     }
 }
 
-const NX: usize = 256;
-const NY: usize = 256;
-const NPARTS: usize = 31;
-const NS_PER_PART: usize = 8;
+static RERAD_CHECK: Once = Once::new();
 
-fn main() -> std::io::Result<()> {
-    let mut camera = Camera::none();
+/// Warn about re-radiating materials of `world` that are not physically
+/// plausible, and exit if `--strict-rerad` is given.
+fn check_rerad<S: Spectrum>(world: &dyn Hitable<S>, settings: &RenderSettings) {
+    // Objects share materials, report each of them once.
+    let mut materials = world.materials();
+    let mut seen = Vec::new();
+    materials.retain(|m| {
+        let ptr = Arc::as_ptr(m) as *const ();
+        if seen.contains(&ptr) {
+            return false;
+        }
+        seen.push(ptr);
+        true
+    });
 
-    //let world = make_random_scene();
-    //let world = make_dev_scene(&mut camera);
-    let world = make_cornell(&mut camera);
-    //let world = make_colour_checker(&mut camera);
+    let mut plausible = true;
+    for (i, report) in materials
+        .iter()
+        .filter_map(|m| m.rerad_report())
+        .enumerate()
+    {
+        let name = match &report.name {
+            Some(name) => format!("'{}'", name),
+            None => format!("#{}", i + 1),
+        };
+        if settings.rerad_report {
+            println!("Re-radiating material {}:\n{}", name, report.table());
+        }
+        for problem in report.problems() {
+            eprintln!("Warning: re-radiating material {} {}", name, problem);
+            plausible = false;
+        }
+    }
+    if settings.strict_rerad && !plausible {
+        eprintln!("Not rendering a scene with implausible re-radiating materials");
+        std::process::exit(1);
+    }
+}
 
-    //Initializing temporary buffers for threads...
-    let mut buffer_array = vec![vec![0.0; NX * NY * 4 as usize]; NPARTS];
+/// Render the scene with the spectral representation `S` and `integrator`
+/// and write the images. With `add_suffix` the `name` of the spectrum is
+/// added to the output file names. Every image is written even if one of
+/// them fails, the error of the last that failed is returned.
+fn render<S: Spectrum, I: Integrator<S>>(
+    settings: &RenderSettings,
+    name: &str,
+    add_suffix: bool,
+    integrator: &I,
+) -> std::io::Result<()> {
+    let nx = settings.width;
+    let ny = settings.height;
+    let ns = settings.samples;
+    println!("Rendering with {} spectrum...", name);
+
+    let mut camera = Camera::none();
+    let world: HitList<S> = match &settings.scene_file {
+        Some(path) => match scene_file::load_scene(path, settings.aspect()) {
+            Ok((world, scene_camera)) => {
+                camera = scene_camera;
+                world
+            }
+            Err(err) => {
+                eprintln!("Error loading {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        },
+        None => make_scene(settings.scene, &mut camera, settings.aspect()),
+    };
+    if let Some(exposure) = settings.exposure {
+        camera.exposure = exposure;
+    }
+    let world = aabb::BVH::new(world);
+    // The materials are the same for every spectral representation.
+    RERAD_CHECK.call_once(|| check_rerad(&world, settings));
+    let lights = LightList::new(world.objects());
+    let env_light: Option<S> = None; //Some(spectrum::illumination_halogen());
+    let scene = Scene {
+        world: &world,
+        light: if lights.is_empty() {
+            None
+        } else {
+            Some(&lights)
+        },
+        camera: &camera,
+        env_light,
+    };
+
+    // Split the samples over one buffer per thread. Each buffer holds the sum
+    // of its samples, the average is taken when the buffers are combined.
+    // The spectral buffer, with every wavelength bin, is only kept if a
+    // spectral image is written. Light that samples bring to other pixels is
+    // kept apart in the splats of each buffer.
+    let lambdas = S::lambdas();
+    let n_bands = S::default().values().len();
+    let spectral_len = if settings.spectral_output.is_empty() || lambdas.is_none() {
+        0
+    } else {
+        nx * ny * n_bands
+    };
+    let nparts = rayon::current_num_threads().min(ns).max(1);
+    let splat_bands = if spectral_len == 0 { 0 } else { n_bands };
+    let mut buffer_array: Vec<(usize, Vec<Real>, Vec<Real>, Splats)> = (0..nparts)
+        .map(|i| {
+            let ns_part = ns / nparts + if i < ns % nparts { 1 } else { 0 };
+            (
+                ns_part,
+                vec![0.0; nx * ny * 4],
+                vec![0.0; spectral_len],
+                Splats::new(nx, ny, splat_bands),
+            )
+        })
+        .collect();
 
     // Dispatch threads.
-    buffer_array.par_iter_mut().for_each(|buffer| {
-        for y in 0..NY {
-            for x in 0..NX {
-                let mut col = Vec3::ZEROS;
-                for _s in 0..NS_PER_PART {
-                    let u = (x as Real + rand::random::<Real>()) / (NX as Real);
-                    let v = (y as Real + rand::random::<Real>()) / (NY as Real);
-                    let r = camera.get_ray(u, v);
-
-                    //col += color(&r, &world, world[2].as_ref(), 0);
-                    #[cfg(feature = "use_sampled_spectrum")]
-                    let spectrum_factor = 0.01;
-
-                    #[cfg(not(feature = "use_sampled_spectrum"))]
-                    let spectrum_factor = 1.0;
-
-                    let gain = spectrum_factor * camera.exposure;
-
-                    let spec = gain * color(&r, &world, world[2].as_ref(), *ENV_LIGHT, 0);
-                    let mut rgb: [Real; 3] = [0.0; 3];
-                    spec.to_rgb(&mut rgb);
-
-                    let val = Vec3::new(rgb[0], rgb[1], rgb[2]);
-                    col += val;
+    buffer_array
+        .par_iter_mut()
+        .for_each(|(ns_part, buffer, spectral_buffer, splats)| {
+            for y in 0..ny {
+                for x in 0..nx {
+                    let mut col = [0.0; 3];
+                    let spectral_offset = ((ny - 1 - y) * nx + x) * n_bands;
+                    let spectral = if spectral_buffer.is_empty() {
+                        &mut []
+                    } else {
+                        &mut spectral_buffer[spectral_offset..spectral_offset + n_bands]
+                    };
+                    for _s in 0..*ns_part {
+                        let u = (x as Real + rand::random::<Real>()) / (nx as Real);
+                        let v = (y as Real + rand::random::<Real>()) / (ny as Real);
+                        let r = camera.get_ray(u, v);
+
+                        let gain = S::OUTPUT_SCALE * camera.exposure;
+                        integrator.add_sample(&r, &scene, gain, &mut col, spectral, splats);
+                    }
+                    let offset = ((ny - 1 - y) * nx + x) * 4;
+                    buffer[offset] = col[0];
+                    buffer[offset + 1] = col[1];
+                    buffer[offset + 2] = col[2];
+                    buffer[offset + 3] = *ns_part as Real;
                 }
-                col /= NS_PER_PART as Real;
-                buffer[(((NY - 1 - y) * NX + x) * 4 + 0) as usize] = col.r();
-                buffer[(((NY - 1 - y) * NX + x) * 4 + 1) as usize] = col.g();
-                buffer[(((NY - 1 - y) * NX + x) * 4 + 2) as usize] = col.b();
-                buffer[(((NY - 1 - y) * NX + x) * 4 + 3) as usize] = 1.0;
             }
-        }
-    });
+        });
 
     println!("Averaging...");
-    let mut final_float_buffer = vec![0.0 as Real; (NX * NY * 4) as usize];
-    let mut final_buffer = vec![0 as u8; (NX * NY * 4) as usize];
-    for buffer in buffer_array.iter() {
+    let mut final_float_buffer = vec![0.0 as Real; nx * ny * 4];
+    let mut final_spectral_buffer = vec![0.0 as Real; spectral_len];
+    for (_, buffer, spectral_buffer, splats) in buffer_array.iter() {
         for i in 0..buffer.len() {
-            let pixel_value = final_float_buffer[i] + buffer[i] / (NPARTS as Real);
-            //if pixel_value > 1.0 { pixel_value = 1.0; }
-            final_float_buffer[i] = pixel_value;
+            final_float_buffer[i] += buffer[i] / (ns as Real);
+        }
+        for i in 0..spectral_buffer.len() {
+            final_spectral_buffer[i] += spectral_buffer[i] / (ns as Real);
+        }
+        for (pixel, splat) in final_float_buffer.chunks_mut(4).zip(splats.rgb.chunks(3)) {
+            for (c, v) in pixel.iter_mut().zip(splat) {
+                *c += v / (ns as Real);
+            }
+        }
+        for (c, v) in final_spectral_buffer.iter_mut().zip(&splats.spectral) {
+            *c += v / (ns as Real);
         }
     }
 
-    //for i_x in 0..NX {
-    //    for i_y in 0..NY {
-    //        let offset = (i_y*NX + i_x)*4;
-    //        let pixel_values = &final_float_buffer[offset..(offset + 4)];
-    //        let pixel = Vec3::new(pixel_values[0], pixel_values[1], pixel_values[2]);
-    //        final_float_buffer[offset + 0] = pixel.r();
-    //        final_float_buffer[offset + 1] = pixel.g();
-    //        final_float_buffer[offset + 2] = pixel.b();
-    //        final_float_buffer[offset + 3] = 1.0;
-    //    }
-    //}
-    let iter = final_buffer.iter_mut().zip(final_float_buffer.iter());
-    for (final_pixel, float_pixel) in iter {
-        let gamma_corrected = spectrum::gamma_correct(*float_pixel).min(1.0).max(0.0);
-        *final_pixel = (255.99 * gamma_corrected) as u8;
+    let suffix = if add_suffix { Some(name) } else { None };
+    let mut result = Ok(());
+    for path in settings.output.iter() {
+        let path = output::add_suffix(path, suffix);
+        if let Err(err) = output::write_image(&path, &final_float_buffer, nx, ny, settings.half) {
+            eprintln!("Error writing {}: {}", path.display(), err);
+            result = Err(err);
+        }
     }
-
-    match lodepng::encode32_file("out.png", &final_buffer, NX as usize, NY as usize) {
-        Ok(()) => {}
-        Err(err) => println!("Error writing file: {}", err),
+    if let Some(lambdas) = lambdas {
+        for path in settings.spectral_output.iter() {
+            let path = output::add_suffix(path, suffix);
+            let written = output::write_spectral_image(
+                &path,
+                &final_spectral_buffer,
+                nx,
+                ny,
+                &lambdas,
+                settings.half,
+            );
+            if let Err(err) = written {
+                eprintln!("Error writing {}: {}", path.display(), err);
+                result = Err(err);
+            }
+        }
     }
-
-    println!("Done.");
-    Ok(())
+    result
 }
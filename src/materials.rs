@@ -1,57 +1,108 @@
 use crate::core::*;
+use crate::dispersion::Dispersion;
 use crate::hitable::Hit;
-use crate::ray::Ray;
+use crate::metals::MetalPreset;
+use crate::microfacet::{
+    fresnel_dielectric, Distribution, Microfacet, MicrofacetBSDF, MicrofacetPDF,
+};
+use crate::ray::{fresnel_conductor, reflect, refract, schlick, thin_film_reflectance, Ray, UVW};
+use crate::sampling::Distribution1D;
+use crate::spectrum::{remove_anti_stokes, spectral_grid};
+use crate::texture::Texture;
 use crate::Vec3;
 use crate::{CosinePDF, PDF};
 
-pub trait Material: Sync {
-    fn scatter(&self, r_in: &Ray, rec: &Hit) -> Option<ScatterRecord>;
-    fn emitted(&self, r_in: &Ray, rec: &Hit, u: Real, v: Real, p: Vec3) -> Spectrum; //Vec3 { return Vec3::ZEROS; }
-    fn scattering_pdf(&self, r_in: &Ray, rec: &Hit, scattered: &Ray) -> Real {
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+pub trait Material<S: Spectrum>: Sync {
+    fn scatter(&self, r_in: &Ray, rec: &Hit<S>) -> Option<ScatterRecord<S>>;
+    fn emitted(&self, r_in: &Ray, rec: &Hit<S>, u: Real, v: Real, p: Vec3) -> S; //Vec3 { return Vec3::ZEROS; }
+    /// Emitted radiance averaged over the surface, for picking lights in
+    /// proportion to their power. Black if the material does not emit.
+    fn average_emission(&self) -> S {
+        S::default()
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &Hit<S>, scattered: &Ray) -> Real {
         1.0
     }
-    fn apply_diffuse(&self, spec_in: &Spectrum) -> Spectrum {
-        Spectrum::default()
+    /// Diffusely scattered light for the light `spec_in` arriving at the hit
+    /// `rec`, see `scattering_pdf` for its direction.
+    fn apply_diffuse(&self, rec: &Hit<S>, spec_in: &S) -> S {
+        S::default()
+    }
+    /// Sample the wavelength of the light that is diffusely scattered to
+    /// `lambda_out`, for paths that carry single wavelengths. Returns the
+    /// wavelength and the diffuse transfer divided by its probability. By
+    /// default the wavelength is kept and the transfer is `apply_diffuse`.
+    fn sample_diffuse_lambda(&self, rec: &Hit<S>, lambda_out: Real, u: Real) -> (Real, Real) {
+        (
+            lambda_out,
+            self.apply_diffuse(rec, &S::new(1.0)).eval(lambda_out),
+        )
+    }
+    /// Scatter light of the single wavelength `lambda`, for paths that carry
+    /// single wavelengths. Only differs from `scatter` if the direction
+    /// depends on the wavelength, see `is_dispersive`.
+    fn scatter_lambda(&self, r_in: &Ray, rec: &Hit<S>, lambda: Real) -> Option<ScatterRecord<S>> {
+        self.scatter(r_in, rec)
+    }
+    /// Whether the scattered direction depends on the wavelength. Paths that
+    /// carry several wavelengths only keep the first of them here.
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+    /// Energy balance of the re-radiation, for materials that re-radiate.
+    fn rerad_report(&self) -> Option<&ReRadReport> {
+        None
     }
 }
 
-pub struct ScatterRecord {
+pub struct ScatterRecord<S: Spectrum> {
     pub specular_ray: Option<Ray>,
-    pub attenuation: Spectrum,
+    pub attenuation: S,
     pub pdf: Option<Box<dyn PDF>>,
 }
 
-pub struct Lambertian {
-    pub albedo: Spectrum,
-    pub emit: Spectrum,
+pub struct Lambertian<S: Spectrum> {
+    pub albedo: Arc<dyn Texture<S>>,
+    pub emit: Arc<dyn Texture<S>>,
 }
-impl Material for Lambertian {
-    fn scatter(&self, r_in: &Ray, rec: &Hit) -> Option<ScatterRecord> {
+impl<S: Spectrum> Material<S> for Lambertian<S> {
+    fn scatter(&self, r_in: &Ray, rec: &Hit<S>) -> Option<ScatterRecord<S>> {
         Some(ScatterRecord {
             specular_ray: None,
-            attenuation: self.albedo,
+            attenuation: self.albedo.value(rec.u, rec.v, rec.p),
             pdf: Some(Box::new(CosinePDF::new(rec.normal))),
         })
     }
-    fn scattering_pdf(&self, r_in: &Ray, rec: &Hit, scattered: &Ray) -> Real {
+    fn scattering_pdf(&self, r_in: &Ray, rec: &Hit<S>, scattered: &Ray) -> Real {
         let cosine = (rec.normal.make_unit_vector())
             .dot(scattered.direction.make_unit_vector())
             .max(0.0);
         cosine / PI
     }
-    fn emitted(&self, r_in: &Ray, rec: &Hit, u: Real, v: Real, p: Vec3) -> Spectrum {
+    fn emitted(&self, r_in: &Ray, rec: &Hit<S>, u: Real, v: Real, p: Vec3) -> S {
         if rec.normal.dot(r_in.direction) < 0.0 {
-            return self.emit.clone();
+            return self.emit.value(u, v, p);
         }
-        return Spectrum::default();
+        return S::default();
+    }
+    fn average_emission(&self) -> S {
+        self.emit.average()
     }
-    fn apply_diffuse(&self, spec_in: &Spectrum) -> Spectrum {
-        self.albedo * spec_in
+    fn apply_diffuse(&self, rec: &Hit<S>, spec_in: &S) -> S {
+        self.albedo.value(rec.u, rec.v, rec.p) * *spec_in
+    }
+    fn sample_diffuse_lambda(&self, rec: &Hit<S>, lambda_out: Real, u: Real) -> (Real, Real) {
+        let albedo = self.albedo.value(rec.u, rec.v, rec.p);
+        (lambda_out, albedo.eval(lambda_out))
     }
 }
 
 // This can be implemented with a normal matrix.
 // Or perhaps a list of paramters for gaussian functions that describe similar.
+#[derive(Debug)]
 pub struct GaussRecord {
     pub lambda_in: Real,
     pub lambda_out: Real,
@@ -62,109 +113,1108 @@ pub struct GaussRecord {
     pub sigma_out: Real,
 }
 impl GaussRecord {
-    fn eval(&self, lambda_i: Real, lambda_o: Real) -> Real {
-        // Multivariate gaussian.
-        // Need: Sigma_inv, pos
-        let x = [lambda_i, lambda_o];
-        let mu = [self.lambda_in, self.lambda_out];
+    /// Value of the Gaussian, `amplitude_out` at its peak. It is not
+    /// normalised, the amplitude is the re-radiated fraction at the peak.
+    pub fn eval(&self, lambda_i: Real, lambda_o: Real) -> Real {
+        let d_i = (lambda_i - self.lambda_in) / self.sigma_in;
+        let d_o = (lambda_o - self.lambda_out) / self.sigma_out;
+        (-0.5 * (d_i * d_i + d_o * d_o)).exp() * self.amplitude_out
+    }
+}
 
-        // cov = [
-        //      sigma_in, 0,
-        //      0, sigma_out,
-        // ]
-        // cov_inv = [
-        //     1.0/sigma_in, 0,
-        //     0, 1.0/sigma_out
-        // ]
+/// Values above 1 by less than this are left to rounding.
+const REPORT_TOLERANCE: Real = 1e-3;
 
-        let s_i = self.sigma_in * self.sigma_in;
-        let s_o = self.sigma_out * self.sigma_out;
+/// Energy balance of a re-radiation matrix on the bins of the spectral grid,
+/// per excitation bin.
+#[derive(Clone, Debug)]
+pub struct ReRadReport {
+    /// Name of the material, if it has one.
+    pub name: Option<String>,
+    /// Centre of every bin in nanometers.
+    pub lambda: Vec<Real>,
+    /// Fraction reflected at the same wavelength.
+    pub reflectance: Vec<Real>,
+    /// Fraction of the energy re-radiated at the same or a longer
+    /// wavelength, including the reflectance.
+    pub albedo: Vec<Real>,
+    /// Photons re-radiated at longer wavelengths per photon absorbed.
+    pub quantum_yield: Vec<Real>,
+    /// Fraction of the energy the matrix re-radiates at shorter wavelengths,
+    /// which is left out when rendering.
+    pub anti_stokes: Vec<Real>,
+}
+
+impl ReRadReport {
+    /// Report for a matrix on the bins of the grid, with one row per
+    /// outgoing bin.
+    pub fn new(sampled_matrix: &[Real]) -> ReRadReport {
+        let grid = spectral_grid();
+        let n = grid.n;
+        let lambda: Vec<Real> = (0..n)
+            .map(|i| grid.lambda[i] + 0.5 * grid.bin_width())
+            .collect();
+        let m = |i_o: usize, i_i: usize| sampled_matrix[i_o * n + i_i];
+
+        let reflectance: Vec<Real> = (0..n).map(|i| m(i, i)).collect();
+        let fluorescence: Vec<Real> = (0..n)
+            .map(|i| (i + 1..n).fold(0.0, |sum, o| sum + m(o, i)))
+            .collect();
+        let albedo = (0..n).map(|i| reflectance[i] + fluorescence[i]).collect();
+        // Photon counts go with energy times wavelength.
+        let quantum_yield = (0..n)
+            .map(|i| {
+                let emitted = (i + 1..n).fold(0.0, |sum, o| sum + m(o, i) * lambda[o]);
+                let absorbed = (1.0 - reflectance[i]) * lambda[i];
+                if emitted <= 0.0 {
+                    0.0
+                } else if absorbed <= 0.0 {
+                    Real::INFINITY
+                } else {
+                    emitted / absorbed
+                }
+            })
+            .collect();
+        let anti_stokes = (0..n)
+            .map(|i| (0..i).fold(0.0, |sum, o| sum + m(o, i)))
+            .collect();
+        ReRadReport {
+            name: None,
+            lambda,
+            reflectance,
+            albedo,
+            quantum_yield,
+            anti_stokes,
+        }
+    }
 
-        let cov_det = s_i * s_o;
-        let n = ((2.0 * PI).powf(2.0) * cov_det).sqrt();
+    /// Largest value and its wavelength.
+    fn max(&self, values: &[Real]) -> (Real, Real) {
+        values
+            .iter()
+            .zip(self.lambda.iter())
+            .fold((0.0, 0.0), |a, (v, l)| if *v > a.0 { (*v, *l) } else { a })
+    }
 
-        // N = (x-mu).T * cov_inv * (x-mu)
-        // fac = (x-mu).T * cov_inv * (x-mu)
-        let fac = 1.0 / s_i * (x[0] - mu[0]) * (x[0] - mu[0])
-            + 1.0 / s_o * (x[1] - mu[1]) * (x[1] - mu[1]);
+    /// Descriptions of what is not physically plausible, empty if nothing
+    /// is.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let (v, l) = self.max(&self.albedo);
+        if v > 1.0 + REPORT_TOLERANCE {
+            problems.push(format!(
+                "re-radiates {:.3} times the incoming energy at {:.1} nm",
+                v, l
+            ));
+        }
+        let (v, l) = self.max(&self.quantum_yield);
+        if v > 1.0 + REPORT_TOLERANCE {
+            problems.push(format!("has a quantum yield of {:.3} at {:.1} nm", v, l));
+        }
+        let (v, l) = self.max(&self.anti_stokes);
+        if v > REPORT_TOLERANCE {
+            problems.push(format!(
+                "re-radiates {:.3} of the light at {:.1} nm at shorter wavelengths, which is left out",
+                v, l
+            ));
+        }
+        problems
+    }
 
-        return ((-fac / 2.0).exp()) * self.amplitude_out;
+    /// One line per excitation bin.
+    pub fn table(&self) -> String {
+        let mut out = String::from("lambda_nm reflectance albedo quantum_yield anti_stokes\n");
+        for i in 0..self.lambda.len() {
+            out += &format!(
+                "{:9.1} {:11.4} {:6.4} {:13.4} {:11.4}\n",
+                self.lambda[i],
+                self.reflectance[i],
+                self.albedo[i],
+                self.quantum_yield[i],
+                self.anti_stokes[i]
+            );
+        }
+        out
     }
 }
 
-pub struct LambertianReRad {
+/// Bispectral re-radiation, light absorbed at one wavelength and
+/// re-radiated at a longer one, as a matrix on the coefficients of `S`.
+/// Used by the diffuse materials in place of an albedo.
+pub struct ReRadiation<S: Spectrum> {
+    /// Empty if the re-radiation was given as a matrix.
+    rerad_list: Vec<GaussRecord>,
+    /// The re-radiation for the spectral representation, one row per
+    /// outgoing coefficient.
+    rerad_matrix: Vec<Real>,
+    /// The rows of `rerad_matrix` as distributions over the incoming
+    /// coefficients, for sampling wavelengths.
+    rerad_rows: Vec<Distribution1D>,
+    report: ReRadReport,
+    spectrum: PhantomData<S>,
+}
+impl<S: Spectrum> ReRadiation<S> {
+    pub fn new(rerad_list: Vec<GaussRecord>) -> ReRadiation<S> {
+        let rerad_matrix = SampledSpectrum::full_rerad_matrix(&|lambda_i, lambda_o| {
+            rerad_list
+                .iter()
+                .map(|gauss_record| gauss_record.eval(lambda_i, lambda_o))
+                .sum()
+        });
+        ReRadiation::with_matrix(rerad_list, rerad_matrix)
+    }
+
+    /// Re-radiation given as a matrix on the bins of the spectral grid, with
+    /// one row per outgoing bin. See `DonaldsonMatrix::sampled_matrix`.
+    pub fn from_matrix(sampled_matrix: &[Real]) -> ReRadiation<S> {
+        ReRadiation::with_matrix(Vec::new(), sampled_matrix.to_vec())
+    }
+
+    fn with_matrix(rerad_list: Vec<GaussRecord>, mut sampled_matrix: Vec<Real>) -> ReRadiation<S> {
+        let report = ReRadReport::new(&sampled_matrix);
+        remove_anti_stokes(&mut sampled_matrix);
+        let rerad_matrix = S::rerad_matrix_from_sampled(&sampled_matrix);
+        let n = S::default().values().len();
+        let rerad_rows = rerad_matrix.chunks(n).map(Distribution1D::new).collect();
+        ReRadiation {
+            rerad_list,
+            rerad_matrix,
+            rerad_rows,
+            report,
+            spectrum: PhantomData,
+        }
+    }
+
+    /// Name the material in its `ReRadReport`.
+    pub fn named(mut self, name: &str) -> ReRadiation<S> {
+        self.report.name = Some(name.to_string());
+        self
+    }
+
+    pub fn rerad_list(&self) -> &[GaussRecord] {
+        &self.rerad_list
+    }
+
+    pub fn report(&self) -> &ReRadReport {
+        &self.report
+    }
+
+    pub fn apply(&self, spec_in: &S) -> S {
+        // For each sample in spec_in, the re-radiation matrix gives the
+        // contribution to every outgoing sample.
+        spec_in.apply_matrix(&self.rerad_matrix)
+    }
+
+    /// Switch to the wavelength of the incoming light, picked in proportion
+    /// to its contribution to `lambda_out`.
+    pub fn sample_lambda(&self, lambda_out: Real, u: Real) -> (Real, Real) {
+        let row = &self.rerad_rows[S::coefficient_index(lambda_out)];
+        if row.func_sum <= 0.0 {
+            return (lambda_out, 0.0);
+        }
+        let (i, _, t) = row.sample_discrete(u);
+        let (start, end) = S::coefficient_range(i);
+        (lerp(t, start, end), row.func_sum)
+    }
+}
+
+pub struct LambertianReRad<S: Spectrum> {
     //pub albedo: Spectrum,
-    pub emit: Spectrum,
-    pub rerad_list: Vec<GaussRecord>,
+    pub emit: Arc<dyn Texture<S>>,
+    pub rerad: ReRadiation<S>,
 }
-impl Material for LambertianReRad {
-    fn scatter(&self, r_in: &Ray, rec: &Hit) -> Option<ScatterRecord> {
+impl<S: Spectrum> LambertianReRad<S> {
+    pub fn new(emit: Arc<dyn Texture<S>>, rerad_list: Vec<GaussRecord>) -> LambertianReRad<S> {
+        LambertianReRad {
+            emit,
+            rerad: ReRadiation::new(rerad_list),
+        }
+    }
+
+    /// Re-radiation given as a matrix on the bins of the spectral grid, with
+    /// one row per outgoing bin. See `DonaldsonMatrix::sampled_matrix`.
+    pub fn from_matrix(emit: Arc<dyn Texture<S>>, sampled_matrix: &[Real]) -> LambertianReRad<S> {
+        LambertianReRad {
+            emit,
+            rerad: ReRadiation::from_matrix(sampled_matrix),
+        }
+    }
+
+    /// Name the material in its `ReRadReport`.
+    pub fn named(mut self, name: &str) -> LambertianReRad<S> {
+        self.rerad = self.rerad.named(name);
+        self
+    }
+
+    pub fn rerad_list(&self) -> &[GaussRecord] {
+        self.rerad.rerad_list()
+    }
+}
+impl<S: Spectrum> Material<S> for LambertianReRad<S> {
+    fn scatter(&self, r_in: &Ray, rec: &Hit<S>) -> Option<ScatterRecord<S>> {
         Some(ScatterRecord {
             specular_ray: None,
-            attenuation: Spectrum::default(),
+            attenuation: S::default(),
             pdf: Some(Box::new(CosinePDF::new(rec.normal))),
         })
     }
-    fn scattering_pdf(&self, r_in: &Ray, rec: &Hit, scattered: &Ray) -> Real {
+    fn scattering_pdf(&self, r_in: &Ray, rec: &Hit<S>, scattered: &Ray) -> Real {
         let cosine = (rec.normal.make_unit_vector())
             .dot(scattered.direction.make_unit_vector())
             .max(0.0);
         cosine / PI
     }
-    fn emitted(&self, r_in: &Ray, rec: &Hit, u: Real, v: Real, p: Vec3) -> Spectrum {
+    fn emitted(&self, r_in: &Ray, rec: &Hit<S>, u: Real, v: Real, p: Vec3) -> S {
         if rec.normal.dot(r_in.direction) < 0.0 {
-            return self.emit.clone();
-        }
-        return Spectrum::default();
-    }
-    fn apply_diffuse(&self, spec_in: &Spectrum) -> Spectrum {
-        // NOTE: Need incomming light in order to give a correct spectrum back...
-        //
-        // For each sample in spec_in. Evaluate the rerad and make new spectrum.
-        //self.albedo * spec_in
-
-        #[cfg(feature = "use_sampled_spectrum")]
-        {
-            let mut spec_out: Spectrum = Spectrum::default();
-            for i_i in 0..crate::spectrum::N_SPECTRAL_SAMPLES {
-                let lambda_i = crate::spectrum::SAMPLED_LAMBDA[i_i];
-                let value_i = spec_in.c[i_i];
-
-                // Looping from i_i, since we can only reradiate at wavlength
-                // at or longer than lambda_i.
-                for i_o in i_i..crate::spectrum::N_SPECTRAL_SAMPLES {
-                    let lambda_o = crate::spectrum::SAMPLED_LAMBDA[i_o];
-
-                    // Evaluate GaussRecords...
-                    let mut value_o = 0.0;
-                    for gauss_record in self.rerad_list.iter() {
-                        value_o += gauss_record.eval(lambda_i, lambda_o);
-                    }
-
-                    spec_out.c[i_o] += (value_o * value_i);
-                    //println!("[lambda_i: {:.2}, lambda_o: {:.2}]:value_o: {}", lambda_i, lambda_o, value_o);
-                }
+            return self.emit.value(u, v, p);
+        }
+        return S::default();
+    }
+    fn average_emission(&self) -> S {
+        self.emit.average()
+    }
+    fn apply_diffuse(&self, rec: &Hit<S>, spec_in: &S) -> S {
+        self.rerad.apply(spec_in)
+    }
+    fn sample_diffuse_lambda(&self, rec: &Hit<S>, lambda_out: Real, u: Real) -> (Real, Real) {
+        self.rerad.sample_lambda(lambda_out, u)
+    }
+    fn rerad_report(&self) -> Option<&ReRadReport> {
+        Some(self.rerad.report())
+    }
+}
+
+/// Rough diffuse reflection after Oren and Nayar, "Generalization of
+/// Lambert's reflectance model" (1994), in the qualitative form of their
+/// paper. The surface is made of V-shaped Lambertian facets whose slopes
+/// have the standard deviation `sigma`, which brightens the reflection
+/// towards the light and at grazing angles. With `sigma` 0 it is
+/// `Lambertian`.
+///
+/// The spectrum is given either by `albedo` or, for fluorescent materials,
+/// by a re-radiation as in `LambertianReRad`.
+pub struct OrenNayar<S: Spectrum> {
+    pub albedo: Arc<dyn Texture<S>>,
+    pub emit: Arc<dyn Texture<S>>,
+    a: Real,
+    b: Real,
+    rerad: Option<ReRadiation<S>>,
+}
+impl<S: Spectrum> OrenNayar<S> {
+    /// `sigma` is the standard deviation of the facet slopes in degrees.
+    pub fn new(
+        albedo: Arc<dyn Texture<S>>,
+        emit: Arc<dyn Texture<S>>,
+        sigma: Real,
+    ) -> OrenNayar<S> {
+        let sigma = sigma.to_radians();
+        let sigma2 = sigma * sigma;
+        OrenNayar {
+            albedo,
+            emit,
+            a: 1.0 - sigma2 / (2.0 * (sigma2 + 0.33)),
+            b: 0.45 * sigma2 / (sigma2 + 0.09),
+            rerad: None,
+        }
+    }
+
+    /// Re-radiate with `rerad` instead of reflecting with the albedo.
+    pub fn with_rerad(mut self, rerad: ReRadiation<S>) -> OrenNayar<S> {
+        self.rerad = Some(rerad);
+        self
+    }
+
+    /// The reflectance without its spectrum, times the cosine of `wi`. Both
+    /// directions point away from the surface.
+    fn eval(&self, normal: Vec3, wo: Vec3, wi: Vec3) -> Real {
+        let uvw = UVW::onb_from_w(normal.make_unit_vector());
+        let wo = uvw.to_local(wo.make_unit_vector());
+        let wi = uvw.to_local(wi.make_unit_vector());
+        let cos_i = wi.z();
+        if cos_i <= 0.0 {
+            return 0.0;
+        }
+        let cos_o = wo.z().abs();
+        let sin_i = (1.0 - cos_i * cos_i).max(0.0).sqrt();
+        let sin_o = (1.0 - cos_o * cos_o).max(0.0).sqrt();
+
+        // Cosine of the azimuth between the directions.
+        let max_cos = if sin_i > 1e-4 && sin_o > 1e-4 {
+            ((wi.x() * wo.x() + wi.y() * wo.y()) / (sin_i * sin_o)).max(0.0)
+        } else {
+            0.0
+        };
+        let (sin_alpha, tan_beta) = if cos_i > cos_o {
+            (sin_o, sin_i / cos_i)
+        } else {
+            (sin_i, sin_o / cos_o)
+        };
+        (self.a + self.b * max_cos * sin_alpha * tan_beta) * cos_i / PI
+    }
+}
+impl<S: Spectrum> Material<S> for OrenNayar<S> {
+    fn scatter(&self, r_in: &Ray, rec: &Hit<S>) -> Option<ScatterRecord<S>> {
+        Some(ScatterRecord {
+            specular_ray: None,
+            attenuation: self.albedo.value(rec.u, rec.v, rec.p),
+            pdf: Some(Box::new(CosinePDF::new(rec.normal))),
+        })
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &Hit<S>, scattered: &Ray) -> Real {
+        self.eval(rec.normal, -1.0 * r_in.direction, scattered.direction)
+    }
+    fn emitted(&self, r_in: &Ray, rec: &Hit<S>, u: Real, v: Real, p: Vec3) -> S {
+        if rec.normal.dot(r_in.direction) < 0.0 {
+            self.emit.value(u, v, p)
+        } else {
+            S::default()
+        }
+    }
+    fn average_emission(&self) -> S {
+        self.emit.average()
+    }
+    fn apply_diffuse(&self, rec: &Hit<S>, spec_in: &S) -> S {
+        match &self.rerad {
+            Some(rerad) => rerad.apply(spec_in),
+            None => self.albedo.value(rec.u, rec.v, rec.p) * *spec_in,
+        }
+    }
+    fn sample_diffuse_lambda(&self, rec: &Hit<S>, lambda_out: Real, u: Real) -> (Real, Real) {
+        match &self.rerad {
+            Some(rerad) => rerad.sample_lambda(lambda_out, u),
+            None => {
+                let albedo = self.albedo.value(rec.u, rec.v, rec.p);
+                (lambda_out, albedo.eval(lambda_out))
             }
+        }
+    }
+    fn rerad_report(&self) -> Option<&ReRadReport> {
+        self.rerad.as_ref().map(|rerad| rerad.report())
+    }
+}
 
-            return spec_out;
+/// A conductor, reflecting with the Fresnel equations for its complex index
+/// of refraction `eta + i k`. With `roughness` 0 it is a perfect mirror,
+/// otherwise the reflection is a GGX microfacet lobe of that roughness.
+/// Every scattering samples a visible microfacet normal and reflects on it
+/// with the reflectance of every coefficient at that angle, so the colour
+/// changes towards grazing angles as for the mirror.
+pub struct Metal<S: Spectrum> {
+    pub eta: S,
+    pub k: S,
+    pub roughness: Arc<dyn Texture<Real>>,
+}
+impl<S: Spectrum> Metal<S> {
+    pub fn from_preset(preset: MetalPreset, roughness: Arc<dyn Texture<Real>>) -> Metal<S> {
+        let (eta, k) = preset.ior();
+        Metal { eta, k, roughness }
+    }
+
+    /// Reflectance for light arriving at the angle with cosine `cos_i`.
+    fn fresnel(&self, cos_i: Real) -> S {
+        let mut f = S::default();
+        let ior = self.eta.values().iter().zip(self.k.values());
+        for (f, (eta, k)) in f.values_mut().iter_mut().zip(ior) {
+            *f = fresnel_conductor(cos_i, *eta, *k);
+        }
+        f
+    }
+}
+impl<S: Spectrum> Material<S> for Metal<S> {
+    fn scatter(&self, r_in: &Ray, rec: &Hit<S>) -> Option<ScatterRecord<S>> {
+        let unit_direction = r_in.direction.make_unit_vector();
+        let roughness = self.roughness.value(rec.u, rec.v, rec.p).clamp(0.0, 1.0);
+        if roughness <= 0.0 {
+            let cos_i = (-unit_direction.dot(rec.normal)).clamp(0.0, 1.0);
+            return Some(ScatterRecord {
+                specular_ray: Some(Ray {
+                    origin: rec.p,
+                    direction: reflect(unit_direction, rec.normal),
+                }),
+                attenuation: self.fresnel(cos_i),
+                pdf: None,
+            });
         }
 
-        #[cfg(not(feature = "use_sampled_spectrum"))]
-        Spectrum::default()
+        let microfacet = Microfacet::from_roughness(Distribution::Ggx, roughness);
+        let uvw = UVW::onb_from_w(rec.normal);
+        let wo = uvw.to_local(-1.0 * unit_direction);
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let h = microfacet.sample_visible(wo, rand::random::<Real>(), rand::random::<Real>());
+        let cos_h = wo.dot(h);
+        let wi = 2.0 * cos_h * h - wo;
+        if wi.z() <= 0.0 {
+            return None;
+        }
+        // With the visible normals sampled, f cos / pdf is the Fresnel
+        // reflectance times the masking of `wi` given that of `wo`.
+        let masking = microfacet.g(wo, wi) / microfacet.g1(wo);
+        Some(ScatterRecord {
+            specular_ray: Some(Ray {
+                origin: rec.p,
+                direction: uvw.local(wi),
+            }),
+            attenuation: self.fresnel(cos_h) * masking,
+            pdf: None,
+        })
+    }
+    fn emitted(&self, r_in: &Ray, rec: &Hit<S>, u: Real, v: Real, p: Vec3) -> S {
+        S::default()
     }
 }
 
-/*
-pub struct Metal { pub albedo: Vec3, pub fuzz: f64 }
-impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &Hit) -> Option<ScatterRecord> {
-        let reflected = reflect(r_in.direction.make_unit_vector(), rec.normal);
-        Some(
-            ScatterRecord{
-                specular_ray: Some(Ray{origin: rec.p, direction: reflected + self.fuzz*random_in_unit_sphere()}),
-                attenuation: self.albedo,
-                pdf: None
+/// A transparent material that refracts and reflects light, with Schlick's
+/// approximation of the Fresnel reflectance. If the index of refraction
+/// depends on the wavelength, every scattering follows a single coefficient
+/// of the spectrum, which splits white light into its colours. The
+/// integrators keep that coefficient for the rest of the path.
+pub struct Dielectric {
+    pub dispersion: Dispersion,
+}
+impl Dielectric {
+    /// Refract or reflect `r_in` for the index of refraction `eta`, picking
+    /// reflection with the probability of the reflectance.
+    fn scatter_ray<S: Spectrum>(&self, r_in: &Ray, rec: &Hit<S>, eta: Real) -> Ray {
+        let unit_direction = r_in.direction.make_unit_vector();
+        let d = unit_direction.dot(rec.normal);
+        let (outward_normal, ni_over_nt, cosine) = if d > 0.0 {
+            // Leaving the material, the cosine is on the outside.
+            let cosine = (1.0 - eta * eta * (1.0 - d * d)).max(0.0).sqrt();
+            (-1.0 * rec.normal, eta, cosine)
+        } else {
+            (rec.normal, 1.0 / eta, -d)
+        };
+        let direction = match refract(unit_direction, outward_normal, ni_over_nt) {
+            Some(refracted) if rand::random::<Real>() >= schlick(cosine, eta) => refracted,
+            _ => reflect(unit_direction, rec.normal),
+        };
+        Ray {
+            origin: rec.p,
+            direction,
+        }
+    }
+}
+impl<S: Spectrum> Material<S> for Dielectric {
+    fn scatter(&self, r_in: &Ray, rec: &Hit<S>) -> Option<ScatterRecord<S>> {
+        if !self.dispersion.is_dispersive() {
+            return self.scatter_lambda(r_in, rec, 550.0);
+        }
+        // Follow one coefficient, at a wavelength within it. The others are
+        // left out, so the picked one is scaled by their number.
+        let mut attenuation = S::default();
+        let n = attenuation.values().len();
+        let i = ((rand::random::<Real>() * n as Real) as usize).min(n - 1);
+        let (start, end) = S::coefficient_range(i);
+        let lambda = lerp(rand::random::<Real>(), start, end);
+        attenuation.values_mut()[i] = n as Real;
+        Some(ScatterRecord {
+            specular_ray: Some(self.scatter_ray(r_in, rec, self.dispersion.ior(lambda))),
+            attenuation,
+            pdf: None,
+        })
+    }
+    fn scatter_lambda(&self, r_in: &Ray, rec: &Hit<S>, lambda: Real) -> Option<ScatterRecord<S>> {
+        Some(ScatterRecord {
+            specular_ray: Some(self.scatter_ray(r_in, rec, self.dispersion.ior(lambda))),
+            attenuation: S::new(1.0),
+            pdf: None,
+        })
+    }
+    fn is_dispersive(&self) -> bool {
+        self.dispersion.is_dispersive()
+    }
+    fn emitted(&self, r_in: &Ray, rec: &Hit<S>, u: Real, v: Real, p: Vec3) -> S {
+        S::default()
+    }
+}
+
+/// A clear coat over another material, like the binder of a paint over its
+/// pigment. The coat is a dielectric with the index of refraction `ior` on
+/// the side the normal points to. It is smooth and reflects with the Fresnel
+/// reflectance, and the rest of the light reaches `base`. On its way through
+/// the coat the light is tinted by `transmittance` once in and once out, at
+/// the wavelength it has there, so the highlight keeps the colour of the
+/// light while a re-radiating base shifts the body colour. Light reflected
+/// back at the underside of the coat is absorbed.
+pub struct Layered<S: Spectrum> {
+    pub base: Arc<dyn Material<S> + Send>,
+    pub ior: Real,
+    pub transmittance: S,
+}
+impl<S: Spectrum> Layered<S> {
+    /// Reflect off the coat, or scatter with `base_scatter` under it.
+    fn scatter_coat(
+        &self,
+        r_in: &Ray,
+        rec: &Hit<S>,
+        base_scatter: impl FnOnce() -> Option<ScatterRecord<S>>,
+    ) -> Option<ScatterRecord<S>> {
+        let unit_direction = r_in.direction.make_unit_vector();
+        let cos_i = -unit_direction.dot(rec.normal);
+        if cos_i <= 0.0 {
+            return base_scatter();
+        }
+        // Reflection is picked with the probability of the reflectance, so
+        // neither choice is weighted.
+        if rand::random::<Real>() < fresnel_dielectric(cos_i, self.ior) {
+            return Some(ScatterRecord {
+                specular_ray: Some(Ray {
+                    origin: rec.p,
+                    direction: reflect(unit_direction, rec.normal),
+                }),
+                attenuation: S::new(1.0),
+                pdf: None,
+            });
+        }
+        let mut srec = base_scatter()?;
+        if let Some(specular_ray) = &srec.specular_ray {
+            let t = self.transmittance * self.exit_transmission(specular_ray.direction, rec);
+            srec.attenuation = t * srec.attenuation * self.transmittance;
+        }
+        Some(srec)
+    }
+
+    /// Fraction of the light leaving the coat in `direction`.
+    fn exit_transmission(&self, direction: Vec3, rec: &Hit<S>) -> Real {
+        let cos_o = direction.make_unit_vector().dot(rec.normal);
+        if cos_o <= 0.0 {
+            return 1.0;
+        }
+        1.0 - fresnel_dielectric(cos_o, self.ior)
+    }
+}
+impl<S: Spectrum> Material<S> for Layered<S> {
+    fn scatter(&self, r_in: &Ray, rec: &Hit<S>) -> Option<ScatterRecord<S>> {
+        self.scatter_coat(r_in, rec, || self.base.scatter(r_in, rec))
+    }
+    fn scatter_lambda(&self, r_in: &Ray, rec: &Hit<S>, lambda: Real) -> Option<ScatterRecord<S>> {
+        self.scatter_coat(r_in, rec, || self.base.scatter_lambda(r_in, rec, lambda))
+    }
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+    /// Only called for light scattered by the base.
+    fn scattering_pdf(&self, r_in: &Ray, rec: &Hit<S>, scattered: &Ray) -> Real {
+        self.base.scattering_pdf(r_in, rec, scattered)
+            * self.exit_transmission(scattered.direction, rec)
+    }
+    fn emitted(&self, r_in: &Ray, rec: &Hit<S>, u: Real, v: Real, p: Vec3) -> S {
+        self.transmittance * self.base.emitted(r_in, rec, u, v, p)
+    }
+    fn average_emission(&self) -> S {
+        self.transmittance * self.base.average_emission()
+    }
+    fn apply_diffuse(&self, rec: &Hit<S>, spec_in: &S) -> S {
+        self.transmittance
+            * self
+                .base
+                .apply_diffuse(rec, &(self.transmittance * *spec_in))
+    }
+    fn sample_diffuse_lambda(&self, rec: &Hit<S>, lambda_out: Real, u: Real) -> (Real, Real) {
+        let (lambda_in, transfer) = self.base.sample_diffuse_lambda(rec, lambda_out, u);
+        let t = self.transmittance.eval(lambda_out) * self.transmittance.eval(lambda_in);
+        (lambda_in, transfer * t)
+    }
+    fn rerad_report(&self) -> Option<&ReRadReport> {
+        self.base.rerad_report()
+    }
+}
+
+/// A thin dielectric film on a dielectric substrate, like a soap bubble or
+/// the coating of a lens. Light reflected at the top and the bottom of the
+/// film interferes, so the reflectance depends on the wavelength and the
+/// angle. The film is `thickness` nanometers thick with the index of
+/// refraction `film_ior`, on the side the normal points to. Light that is
+/// not reflected refracts into the substrate with `substrate_ior`, which is
+/// 1 for a film in the air. A thickness that varies over the surface gives
+/// the bands of colour of a soap bubble.
+pub struct ThinFilm {
+    pub thickness: Arc<dyn Texture<Real>>,
+    pub film_ior: Real,
+    pub substrate_ior: Real,
+}
+impl ThinFilm {
+    /// Coefficients are sampled at most this many nanometers apart.
+    const LAMBDA_STEP: Real = 5.0;
+
+    /// Reflectance of a film `thickness` nanometers thick for light arriving
+    /// from the medium with index `n_in` at the angle with cosine `cos_i`,
+    /// averaged over every coefficient.
+    fn reflectance<S: Spectrum>(&self, thickness: Real, cos_i: Real, n_in: Real, n_out: Real) -> S {
+        let mut r = S::default();
+        for (i, v) in r.values_mut().iter_mut().enumerate() {
+            let (start, end) = S::coefficient_range(i);
+            let n = ((end - start) / ThinFilm::LAMBDA_STEP).ceil().max(1.0) as usize;
+            *v = (0..n)
+                .map(|j| {
+                    let lambda = lerp((j as Real + 0.5) / n as Real, start, end);
+                    thin_film_reflectance(cos_i, n_in, self.film_ior, n_out, thickness, lambda)
+                })
+                .sum::<Real>()
+                / n as Real;
+        }
+        r
+    }
+}
+impl<S: Spectrum> Material<S> for ThinFilm {
+    fn scatter(&self, r_in: &Ray, rec: &Hit<S>) -> Option<ScatterRecord<S>> {
+        let unit_direction = r_in.direction.make_unit_vector();
+        let d = unit_direction.dot(rec.normal);
+        let (outward_normal, n_in, n_out) = if d > 0.0 {
+            (-1.0 * rec.normal, self.substrate_ior, 1.0)
+        } else {
+            (rec.normal, 1.0, self.substrate_ior)
+        };
+        let thickness = self.thickness.value(rec.u, rec.v, rec.p).max(0.0);
+        let r: S = self.reflectance(thickness, d.abs(), n_in, n_out);
+
+        // Reflection is picked with the mean reflectance, and the spectrum
+        // is weighted by its probability.
+        let p = r.values().iter().sum::<Real>() / r.values().len() as Real;
+        let refracted = refract(unit_direction, outward_normal, n_in / n_out);
+        let (direction, attenuation) = match refracted {
+            Some(refracted) if rand::random::<Real>() >= p => {
+                (refracted, (S::new(1.0) - r) * (1.0 / (1.0 - p)))
             }
+            _ => (reflect(unit_direction, rec.normal), r * (1.0 / p.max(EPS))),
+        };
+        Some(ScatterRecord {
+            specular_ray: Some(Ray {
+                origin: rec.p,
+                direction,
+            }),
+            attenuation,
+            pdf: None,
+        })
+    }
+    fn emitted(&self, r_in: &Ray, rec: &Hit<S>, u: Real, v: Real, p: Vec3) -> S {
+        S::default()
+    }
+}
+
+/// Glossy reflection from a rough surface, with the Fresnel reflectance of a
+/// dielectric with the index of refraction `eta` and tinted by `albedo`.
+/// The microfacet `distribution` has the `roughness` from 0 to 1. Both sides
+/// of the surface reflect.
+pub struct MicrofacetReflection<S: Spectrum> {
+    pub albedo: Arc<dyn Texture<S>>,
+    pub distribution: Distribution,
+    pub roughness: Arc<dyn Texture<Real>>,
+    pub eta: Real,
+}
+impl<S: Spectrum> MicrofacetReflection<S> {
+    /// Sampling of the reflection of `r_in`, on the side it arrives from.
+    fn pdf(&self, r_in: &Ray, rec: &Hit<S>) -> MicrofacetPDF {
+        let wo = -1.0 * r_in.direction;
+        let normal = if wo.dot(rec.normal) < 0.0 {
+            -1.0 * rec.normal
+        } else {
+            rec.normal
+        };
+        let bsdf = microfacet_bsdf(self.distribution, &self.roughness, self.eta, false, rec);
+        MicrofacetPDF::new(bsdf, normal, wo)
+    }
+}
+impl<S: Spectrum> Material<S> for MicrofacetReflection<S> {
+    fn scatter(&self, r_in: &Ray, rec: &Hit<S>) -> Option<ScatterRecord<S>> {
+        Some(ScatterRecord {
+            specular_ray: None,
+            attenuation: self.albedo.value(rec.u, rec.v, rec.p),
+            pdf: Some(Box::new(self.pdf(r_in, rec))),
+        })
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &Hit<S>, scattered: &Ray) -> Real {
+        let pdf = self.pdf(r_in, rec);
+        let wi = pdf.uvw.to_local(scattered.direction.make_unit_vector());
+        pdf.bsdf.eval(pdf.wo, wi)
+    }
+    fn emitted(&self, r_in: &Ray, rec: &Hit<S>, u: Real, v: Real, p: Vec3) -> S {
+        S::default()
+    }
+    fn apply_diffuse(&self, rec: &Hit<S>, spec_in: &S) -> S {
+        self.albedo.value(rec.u, rec.v, rec.p) * *spec_in
+    }
+}
+
+/// A rough dielectric that reflects and transmits, with the index of
+/// refraction `eta` inside. All scattered light is tinted by
+/// `transmittance`, as the tint can not tell reflection from transmission.
+pub struct MicrofacetTransmission<S: Spectrum> {
+    pub transmittance: Arc<dyn Texture<S>>,
+    pub distribution: Distribution,
+    pub roughness: Arc<dyn Texture<Real>>,
+    pub eta: Real,
+}
+impl<S: Spectrum> MicrofacetTransmission<S> {
+    fn pdf(&self, r_in: &Ray, rec: &Hit<S>) -> MicrofacetPDF {
+        let bsdf = microfacet_bsdf(self.distribution, &self.roughness, self.eta, true, rec);
+        MicrofacetPDF::new(bsdf, rec.normal, -1.0 * r_in.direction)
+    }
+}
+impl<S: Spectrum> Material<S> for MicrofacetTransmission<S> {
+    fn scatter(&self, r_in: &Ray, rec: &Hit<S>) -> Option<ScatterRecord<S>> {
+        Some(ScatterRecord {
+            specular_ray: None,
+            attenuation: self.transmittance.value(rec.u, rec.v, rec.p),
+            pdf: Some(Box::new(self.pdf(r_in, rec))),
+        })
+    }
+    fn scattering_pdf(&self, r_in: &Ray, rec: &Hit<S>, scattered: &Ray) -> Real {
+        let pdf = self.pdf(r_in, rec);
+        let wi = pdf.uvw.to_local(scattered.direction.make_unit_vector());
+        pdf.bsdf.eval(pdf.wo, wi)
+    }
+    fn emitted(&self, r_in: &Ray, rec: &Hit<S>, u: Real, v: Real, p: Vec3) -> S {
+        S::default()
+    }
+    fn apply_diffuse(&self, rec: &Hit<S>, spec_in: &S) -> S {
+        self.transmittance.value(rec.u, rec.v, rec.p) * *spec_in
+    }
+}
+
+/// The microfacet BSDF with the roughness at the hit `rec`.
+fn microfacet_bsdf<S: Spectrum>(
+    distribution: Distribution,
+    roughness: &Arc<dyn Texture<Real>>,
+    eta: Real,
+    transmission: bool,
+    rec: &Hit<S>,
+) -> MicrofacetBSDF {
+    let roughness = roughness.value(rec.u, rec.v, rec.p).clamp(0.0, 1.0);
+    MicrofacetBSDF {
+        microfacet: Microfacet::from_roughness(distribution, roughness),
+        eta,
+        transmission,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dispersion::GlassPreset;
+    use crate::metals::{GOLD_ETA, GOLD_K, METAL_LAMBDA};
+    use crate::ray::random_cosine_direction;
+
+    fn records() -> Vec<GaussRecord> {
+        vec![
+            GaussRecord {
+                lambda_in: 420.0,
+                lambda_out: 540.0,
+                amplitude_out: 0.05,
+                sigma_in: 15.0,
+                sigma_out: 20.0,
+            },
+            GaussRecord {
+                lambda_in: 550.0,
+                lambda_out: 550.0,
+                amplitude_out: 0.5,
+                sigma_in: 30.0,
+                sigma_out: 30.0,
+            },
+        ]
+    }
+
+    /// Incoming light rising over the grid.
+    fn ramp() -> SampledSpectrum {
+        let grid = spectral_grid();
+        let lambda = [grid.lambda_start, grid.lambda_end];
+        SampledSpectrum::from_sampled(&lambda, &[0.5, 2.0], 2)
+    }
+
+    #[test]
+    fn sampled_wavelengths_average_to_the_matrix() {
+        let rerad = ReRadiation::<SampledSpectrum>::new(records());
+        let light = ramp();
+        let expected = rerad.apply(&light);
+        let n = 4000;
+        for lambda_out in [460.0, 540.0, 560.0, 620.0] {
+            let mut sum = 0.0;
+            for k in 0..n {
+                let u = (k as Real + 0.5) / n as Real;
+                let (lambda_in, transfer) = rerad.sample_lambda(lambda_out, u);
+                assert!(lambda_in <= lambda_out + spectral_grid().bin_width());
+                sum += transfer * light.eval(lambda_in) / n as Real;
+            }
+            let e = expected.eval(lambda_out);
+            assert!((sum - e).abs() <= 1e-3 * e + 1e-6, "{} != {}", sum, e);
+        }
+    }
+
+    /// Matrix on the grid that reflects `reflectance` and re-radiates
+    /// `shift` of the light of bin `from` in bin `to`.
+    fn matrix(reflectance: Real, from: usize, to: usize, shift: Real) -> Vec<Real> {
+        let n = spectral_grid().n;
+        let mut m = vec![0.0; n * n];
+        for i in 0..n {
+            m[i * n + i] = reflectance;
+        }
+        m[to * n + from] += shift;
+        m
+    }
+
+    #[test]
+    fn plausible_re_radiation_has_no_problems() {
+        let report = ReRadReport::new(&matrix(0.5, 10, 30, 0.2));
+        assert!(report.problems().is_empty(), "{:?}", report.problems());
+        assert!((report.albedo[10] - 0.7).abs() < 1e-6);
+        // 0.2 of the energy is re-radiated at a longer wavelength, for 0.5
+        // absorbed.
+        let lambda = &report.lambda;
+        let yield_10 = 0.2 * lambda[30] / (0.5 * lambda[10]);
+        assert!((report.quantum_yield[10] - yield_10).abs() < 1e-4);
+        assert_eq!(report.quantum_yield[11], 0.0);
+    }
+
+    #[test]
+    fn reports_energy_gain_and_quantum_yield() {
+        let problems = ReRadReport::new(&matrix(0.9, 10, 12, 0.3)).problems();
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].contains("1.200 times the incoming energy"));
+        assert!(problems[1].contains("quantum yield"));
+    }
+
+    #[test]
+    fn reports_and_removes_anti_stokes() {
+        let m = matrix(0.5, 30, 10, 0.1);
+        let problems = ReRadReport::new(&m).problems();
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(problems[0].contains("shorter wavelengths"));
+
+        // The renderer leaves the shorter wavelengths out.
+        let rerad = ReRadiation::<SampledSpectrum>::from_matrix(&m);
+        let out = rerad.apply(&SampledSpectrum::new(1.0));
+        assert!((out.values()[10] - 0.5).abs() < 1e-6);
+    }
+
+    /// Hit at the origin of a surface facing up.
+    fn hit_up<S: Spectrum>() -> Hit<S> {
+        Hit {
+            t: 1.0,
+            p: Vec3::ZEROS,
+            u: 0.0,
+            v: 0.0,
+            normal: Vec3(0.0, 0.0, 1.0),
+            material: None,
+        }
+    }
+
+    /// Reflectance of a conductor at normal incidence.
+    fn normal_reflectance(eta: Real, k: Real) -> Real {
+        ((eta - 1.0) * (eta - 1.0) + k * k) / ((eta + 1.0) * (eta + 1.0) + k * k)
+    }
+
+    #[test]
+    fn metal_at_normal_incidence_has_the_tabulated_reflectance() {
+        let metal = Metal::<SampledSpectrum>::from_preset(MetalPreset::Gold, Arc::new(0.0));
+        let down = Ray::new(Vec3(0.0, 0.0, 1.0), Vec3(0.0, 0.0, -1.0));
+        let srec = metal.scatter(&down, &hit_up()).unwrap();
+        let reflected = srec.specular_ray.unwrap().direction;
+        assert!((reflected - Vec3(0.0, 0.0, 1.0)).length() < 1e-6);
+
+        let i = SampledSpectrum::coefficient_index(650.0);
+        let j = METAL_LAMBDA.iter().position(|l| *l == 650.0).unwrap();
+        let expected = normal_reflectance(GOLD_ETA[j], GOLD_K[j]);
+        let r = srec.attenuation.values()[i];
+        assert!((r - expected).abs() < 0.01, "{} != {}", r, expected);
+        // Gold reflects red better than blue.
+        assert!(srec.attenuation.eval(450.0) < 0.5 * r);
+    }
+
+    #[test]
+    fn rough_metal_keeps_the_reflectance_of_the_microfacets() {
+        let (eta, k) = (0.2, 3.0);
+        let metal = Metal {
+            eta: SampledSpectrum::new(eta),
+            k: SampledSpectrum::new(k),
+            roughness: Arc::new(0.05),
+        };
+        let down = Ray::new(Vec3(0.0, 0.0, 1.0), Vec3(0.0, 0.0, -1.0));
+        let n = 2000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            if let Some(srec) = metal.scatter(&down, &hit_up()) {
+                assert!(srec.specular_ray.unwrap().direction.z() > 0.0);
+                sum += srec.attenuation.values()[0] / n as Real;
+            }
+        }
+        // Nearly all microfacets face the viewer, so little is lost to
+        // masking or to other angles.
+        let expected = normal_reflectance(eta, k);
+        assert!(
+            sum <= expected + 1e-4 && sum > 0.98 * expected,
+            "{} != {}",
+            sum,
+            expected
+        );
+    }
+
+    /// Direction of the light refracted into the surface of `hit_up` from
+    /// 45 degrees, at `lambda`.
+    fn refracted(glass: &Dielectric, lambda: Real) -> Vec3 {
+        let r = Ray::new(Vec3(-1.0, 0.0, 1.0), Vec3(1.0, 0.0, -1.0));
+        loop {
+            let srec = glass
+                .scatter_lambda(&r, &hit_up::<SampledSpectrum>(), lambda)
+                .unwrap();
+            let direction = srec.specular_ray.unwrap().direction;
+            // Reflection is picked with the probability of the reflectance.
+            if direction.z() < 0.0 {
+                return direction;
+            }
+        }
+    }
+
+    #[test]
+    fn dielectric_splits_white_light() {
+        let glass = Dielectric {
+            dispersion: GlassPreset::Bk7.dispersion(),
+        };
+        // Snell's law, blue bends further towards the normal than red.
+        for lambda in [400.0, 700.0] {
+            let d = refracted(&glass, lambda);
+            let sin_t = d.x() / d.length();
+            let expected = (0.5 as Real).sqrt() / glass.dispersion.ior(lambda);
+            assert!((sin_t - expected).abs() < 1e-4, "{} != {}", sin_t, expected);
+        }
+        assert!(refracted(&glass, 400.0).x() < refracted(&glass, 700.0).x());
+
+        // A scattering follows one coefficient, scaled by their number.
+        let r = Ray::new(Vec3(-1.0, 0.0, 1.0), Vec3(1.0, 0.0, -1.0));
+        let srec = Material::<SampledSpectrum>::scatter(&glass, &r, &hit_up()).unwrap();
+        let values = srec.attenuation.values();
+        let n = values.len() as Real;
+        assert_eq!(values.iter().filter(|v| **v != 0.0).count(), 1);
+        assert_eq!(values.iter().sum::<Real>(), n);
+    }
+
+    fn white_oren_nayar(sigma: Real) -> OrenNayar<SampledSpectrum> {
+        OrenNayar::new(
+            Arc::new(SampledSpectrum::new(1.0)),
+            Arc::new(SampledSpectrum::default()),
+            sigma,
         )
     }
-}*/
+
+    #[test]
+    fn smooth_oren_nayar_is_lambertian() {
+        let smooth = white_oren_nayar(0.0);
+        let lambertian = Lambertian::<SampledSpectrum> {
+            albedo: Arc::new(SampledSpectrum::new(1.0)),
+            emit: Arc::new(SampledSpectrum::default()),
+        };
+        let r = Ray::new(Vec3(-1.0, 0.0, 1.0), Vec3(1.0, 0.0, -1.0));
+        for wi in [
+            Vec3(0.0, 0.0, 1.0),
+            Vec3(0.6, 0.0, 0.8),
+            Vec3(-0.3, 0.4, 0.2),
+        ] {
+            let scattered = Ray::new(Vec3::ZEROS, wi);
+            let a = smooth.scattering_pdf(&r, &hit_up(), &scattered);
+            let b = lambertian.scattering_pdf(&r, &hit_up(), &scattered);
+            assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn rough_oren_nayar_scatters_back_towards_the_light() {
+        let rough = white_oren_nayar(30.0);
+        let r = Ray::new(Vec3(-0.8, 0.0, 0.6), Vec3(0.8, 0.0, -0.6));
+        let back = Ray::new(Vec3::ZEROS, Vec3(-0.8, 0.0, 0.6));
+        let forward = Ray::new(Vec3::ZEROS, Vec3(0.8, 0.0, 0.6));
+        let back = rough.scattering_pdf(&r, &hit_up(), &back);
+        let forward = rough.scattering_pdf(&r, &hit_up(), &forward);
+        assert!(
+            back > 0.6 / PI && forward < 0.6 / PI,
+            "{} {}",
+            back,
+            forward
+        );
+
+        // Some light is lost between the facets, none is made.
+        let n = 20000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let d = random_cosine_direction();
+            let scattered = Ray::new(Vec3::ZEROS, d);
+            sum += rough.scattering_pdf(&r, &hit_up(), &scattered) / (d.z() / PI) / n as Real;
+        }
+        assert!(sum > 0.8 && sum < 1.0, "{}", sum);
+    }
+
+    fn coated(albedo: Real, transmittance: Real) -> Layered<SampledSpectrum> {
+        Layered {
+            base: Arc::new(Lambertian {
+                albedo: Arc::new(SampledSpectrum::new(albedo)),
+                emit: Arc::new(SampledSpectrum::default()),
+            }),
+            ior: 1.5,
+            transmittance: SampledSpectrum::new(transmittance),
+        }
+    }
+
+    #[test]
+    fn coat_reflects_with_the_fresnel_reflectance() {
+        let layered = coated(0.0, 0.5);
+        let down = Ray::new(Vec3(0.0, 0.0, 1.0), Vec3(0.0, 0.0, -1.0));
+        let n = 20000;
+        let mut reflected = 0;
+        for _ in 0..n {
+            let srec = layered.scatter(&down, &hit_up()).unwrap();
+            if let Some(ray) = srec.specular_ray {
+                assert!((ray.direction - Vec3(0.0, 0.0, 1.0)).length() < 1e-6);
+                // The highlight is not tinted by the coat.
+                assert_eq!(srec.attenuation.values()[0], 1.0);
+                reflected += 1;
+            }
+        }
+        let fraction = reflected as Real / n as Real;
+        assert!((fraction - 0.04).abs() < 0.01, "{}", fraction);
+    }
+
+    #[test]
+    fn light_under_the_coat_is_tinted_twice() {
+        let layered = coated(0.8, 0.5);
+        let out = layered.apply_diffuse(&hit_up(), &SampledSpectrum::new(1.0));
+        assert!((out.values()[0] - 0.8 * 0.25).abs() < 1e-6);
+
+        // Leaving along the normal, 4% is reflected back by the coat.
+        let down = Ray::new(Vec3(0.0, 0.0, 1.0), Vec3(0.0, 0.0, -1.0));
+        let up = Ray::new(Vec3::ZEROS, Vec3(0.0, 0.0, 1.0));
+        let pdf = layered.scattering_pdf(&down, &hit_up(), &up);
+        assert!((pdf - 0.96 / PI).abs() < 1e-4, "{}", pdf);
+    }
+
+    #[test]
+    fn thin_film_keeps_the_energy() {
+        let film = ThinFilm {
+            thickness: Arc::new(300.0),
+            film_ior: 1.33,
+            substrate_ior: 1.0,
+        };
+        let r = Ray::new(Vec3(-1.0, 0.0, 1.0), Vec3(1.0, 0.0, -1.0));
+        let n = 20000;
+        let mut reflected = SampledSpectrum::default();
+        let mut transmitted = SampledSpectrum::default();
+        for _ in 0..n {
+            let srec = Material::<SampledSpectrum>::scatter(&film, &r, &hit_up()).unwrap();
+            let attenuation = srec.attenuation / n as Real;
+            if srec.specular_ray.unwrap().direction.z() > 0.0 {
+                reflected += attenuation;
+            } else {
+                transmitted += attenuation;
+            }
+        }
+        // The soap film reflects some colours more than others, and what it
+        // does not reflect goes through.
+        let values = reflected.values();
+        let (min, max) = values.iter().fold((1.0 as Real, 0.0 as Real), |(a, b), v| {
+            (a.min(*v), b.max(*v))
+        });
+        assert!(max > 2.0 * min, "{} {}", min, max);
+        for (r, t) in values.iter().zip(transmitted.values()) {
+            assert!((r + t - 1.0).abs() < 0.05, "{} + {}", r, t);
+        }
+    }
+}
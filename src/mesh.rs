@@ -0,0 +1,308 @@
+use crate::aabb::{BVHTree, AABB};
+use crate::core::{Quaternion, Real, Spectrum, EPS, HIT_EPS};
+use crate::hitable::{Hit, Hitable};
+use crate::materials::Material;
+use crate::mesh_io::MeshError;
+use crate::ray::Ray;
+use crate::sampling::Distribution1D;
+use crate::Vec3;
+
+use std::sync::Arc;
+
+/// A mesh of triangles sharing vertex buffers. Normals and texture
+/// coordinates are optional, but if given there must be one per vertex.
+///
+/// The triangles are stored in their own bounding volume hierarchy, so the
+/// mesh is a single object in the world no matter how many triangles it has.
+pub struct TriangleMesh<S: Spectrum> {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<[Real; 2]>,
+    pub indices: Vec<[u32; 3]>,
+    /// Index into `materials` for every triangle. Empty if all triangles use
+    /// the first material.
+    pub material_ids: Vec<u32>,
+    pub materials: Vec<Option<Arc<dyn Material<S> + Send>>>,
+    tree: BVHTree,
+    /// Picks triangles in proportion to their area, for sampling the mesh
+    /// as a light.
+    areas: Distribution1D,
+}
+
+impl<S: Spectrum> TriangleMesh<S> {
+    /// A mesh with one material for all triangles. Fails if the normals or
+    /// texture coordinates are not given per vertex, or if a triangle uses a
+    /// vertex that does not exist.
+    pub fn new(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<[Real; 2]>,
+        indices: Vec<[u32; 3]>,
+    ) -> Result<TriangleMesh<S>, MeshError> {
+        if !normals.is_empty() && normals.len() != positions.len() {
+            return Err(MeshError::Parse(format!(
+                "{} normals for {} vertices",
+                normals.len(),
+                positions.len()
+            )));
+        }
+        if !uvs.is_empty() && uvs.len() != positions.len() {
+            return Err(MeshError::Parse(format!(
+                "{} texture coordinates for {} vertices",
+                uvs.len(),
+                positions.len()
+            )));
+        }
+        if let Some(i) = indices
+            .iter()
+            .flatten()
+            .find(|&&i| i as usize >= positions.len())
+        {
+            return Err(MeshError::Parse(format!(
+                "face index {} out of range ({} vertices)",
+                i,
+                positions.len()
+            )));
+        }
+        Ok(TriangleMesh::build(positions, normals, uvs, indices))
+    }
+
+    /// Build the hierarchy and the area distribution of already checked
+    /// buffers.
+    fn build(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<[Real; 2]>,
+        indices: Vec<[u32; 3]>,
+    ) -> TriangleMesh<S> {
+        let boxes: Vec<AABB> = indices
+            .iter()
+            .map(|tri| AABB::from_points(&triangle_positions(&positions, *tri)).pad(EPS))
+            .collect();
+
+        let areas: Vec<Real> = (0..indices.len())
+            .map(|i| {
+                let [p0, p1, p2] = triangle_positions(&positions, indices[i]);
+                0.5 * (p1 - p0).cross(p2 - p0).length()
+            })
+            .collect();
+
+        TriangleMesh {
+            tree: BVHTree::new(&boxes),
+            areas: Distribution1D::new(&areas),
+            positions,
+            normals,
+            uvs,
+            indices,
+            material_ids: Vec::new(),
+            materials: vec![None],
+        }
+    }
+
+    pub fn n_triangles(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Use the same material for every triangle.
+    pub fn material(mut self, material: Arc<dyn Material<S> + Send>) -> Self {
+        self.materials = vec![Some(material)];
+        self.material_ids = Vec::new();
+        self
+    }
+
+    /// Scale, then rotate, then translate the mesh.
+    pub fn transform(mut self, scale: Vec3, rot: Quaternion, translate: Vec3) -> Self {
+        for p in self.positions.iter_mut() {
+            *p = rot.transform_vec(*p * scale) + translate;
+        }
+        // Normals transform with the inverse transpose, which for a scaling
+        // is dividing by the scale.
+        let inv_scale = Vec3(1.0 / scale.x(), 1.0 / scale.y(), 1.0 / scale.z());
+        for n in self.normals.iter_mut() {
+            *n = rot.transform_vec(*n * inv_scale).make_unit_vector();
+        }
+        let mut mesh = TriangleMesh::build(self.positions, self.normals, self.uvs, self.indices);
+        mesh.materials = self.materials;
+        mesh.material_ids = self.material_ids;
+        mesh
+    }
+
+    /// Use a material per triangle, given as indices into `materials`. An
+    /// empty `material_ids` uses the first material for every triangle.
+    pub fn with_materials(
+        mut self,
+        materials: Vec<Option<Arc<dyn Material<S> + Send>>>,
+        material_ids: Vec<u32>,
+    ) -> Result<Self, MeshError> {
+        if materials.is_empty() {
+            return Err(MeshError::Parse("no materials".to_string()));
+        }
+        if !material_ids.is_empty() && material_ids.len() != self.indices.len() {
+            return Err(MeshError::Parse(format!(
+                "{} material ids for {} triangles",
+                material_ids.len(),
+                self.indices.len()
+            )));
+        }
+        if let Some(id) = material_ids
+            .iter()
+            .find(|&&id| id as usize >= materials.len())
+        {
+            return Err(MeshError::Parse(format!(
+                "material id {} out of range ({} materials)",
+                id,
+                materials.len()
+            )));
+        }
+        self.materials = materials;
+        self.material_ids = material_ids;
+        Ok(self)
+    }
+
+    fn triangle_material(&self, i: usize) -> Option<Arc<dyn Material<S> + Send>> {
+        let id = if self.material_ids.is_empty() {
+            0
+        } else {
+            self.material_ids[i] as usize
+        };
+        self.materials[id].clone()
+    }
+
+    /// Möller–Trumbore ray/triangle intersection.
+    fn hit_triangle(&self, i: usize, r: &Ray) -> Option<Hit<S>> {
+        let [p0, p1, p2] = triangle_positions(&self.positions, self.indices[i]);
+
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let pvec = r.direction.cross(e2);
+        let det = e1.dot(pvec);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let tvec = r.origin - p0;
+        let b1 = tvec.dot(pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = tvec.cross(e1);
+        let b2 = r.direction.dot(qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = e2.dot(qvec) * inv_det;
+        if t * r.direction.length() <= HIT_EPS {
+            return None;
+        }
+        Some(self.triangle_hit(i, t, r.point_at_paramter(t), b1, b2))
+    }
+
+    /// Hit at the point `p` of triangle `i` with the barycentric coordinates
+    /// `b1` and `b2`.
+    fn triangle_hit(&self, i: usize, t: Real, p: Vec3, b1: Real, b2: Real) -> Hit<S> {
+        let tri = self.indices[i];
+        let b0 = 1.0 - b1 - b2;
+
+        let normal = if self.normals.is_empty() {
+            self.geometric_normal(i)
+        } else {
+            let n = b0 * self.normals[tri[0] as usize]
+                + b1 * self.normals[tri[1] as usize]
+                + b2 * self.normals[tri[2] as usize];
+            n.make_unit_vector()
+        };
+
+        let (u, v) = if self.uvs.is_empty() {
+            (b1, b2)
+        } else {
+            let uv0 = self.uvs[tri[0] as usize];
+            let uv1 = self.uvs[tri[1] as usize];
+            let uv2 = self.uvs[tri[2] as usize];
+            (
+                b0 * uv0[0] + b1 * uv1[0] + b2 * uv2[0],
+                b0 * uv0[1] + b1 * uv1[1] + b2 * uv2[1],
+            )
+        };
+
+        Hit {
+            t,
+            p,
+            u,
+            v,
+            normal,
+            material: self.triangle_material(i),
+        }
+    }
+
+    fn geometric_normal(&self, i: usize) -> Vec3 {
+        let [p0, p1, p2] = triangle_positions(&self.positions, self.indices[i]);
+        (p1 - p0).cross(p2 - p0).make_unit_vector()
+    }
+}
+
+fn triangle_positions(positions: &[Vec3], tri: [u32; 3]) -> [Vec3; 3] {
+    [
+        positions[tri[0] as usize],
+        positions[tri[1] as usize],
+        positions[tri[2] as usize],
+    ]
+}
+
+impl<S: Spectrum> Hitable<S> for TriangleMesh<S> {
+    fn hit(&self, r: &Ray) -> Option<Hit<S>> {
+        self.tree.hit(r, |i, r| self.hit_triangle(i, r))
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        self.tree.bounding_box()
+    }
+
+    /// Density of `random`, the sum over the triangles the direction passes
+    /// through.
+    fn pdf_value(&self, origin: Vec3, v: Vec3) -> Real {
+        let area = self.area();
+        if area <= 0.0 {
+            return 0.0;
+        }
+        let mut pdf = 0.0;
+        self.tree.hit(&Ray::new(origin, v), |i, r| {
+            if let Some(rec) = self.hit_triangle(i, r) {
+                let distance_squared = rec.t * rec.t * v.squared_length();
+                let cosine = v.dot(self.geometric_normal(i)).abs() / v.length();
+                pdf += distance_squared / (cosine * area);
+            }
+            None::<Hit<S>>
+        });
+        pdf
+    }
+
+    fn random(&self, origin: Vec3) -> Vec3 {
+        match self.random_point() {
+            Some(rec) => rec.p - origin,
+            None => Vec3(1.0, 0.0, 0.0),
+        }
+    }
+
+    fn area(&self) -> Real {
+        self.areas.func_sum
+    }
+
+    fn random_point(&self) -> Option<Hit<S>> {
+        if self.area() <= 0.0 {
+            return None;
+        }
+        let (i, _, _) = self.areas.sample_discrete(rand::random::<Real>());
+        // Uniform over the triangle, see pbrt's UniformSampleTriangle.
+        let su0 = rand::random::<Real>().sqrt();
+        let b1 = su0 * rand::random::<Real>();
+        let b2 = 1.0 - su0;
+        let [p0, p1, p2] = triangle_positions(&self.positions, self.indices[i]);
+        let p = (1.0 - b1 - b2) * p0 + b1 * p1 + b2 * p2;
+        Some(self.triangle_hit(i, 0.0, p, b1, b2))
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material<S> + Send>> {
+        self.materials.iter().flatten().cloned().collect()
+    }
+}
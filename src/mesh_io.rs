@@ -0,0 +1,761 @@
+//! Loading of triangle meshes from Wavefront OBJ and PLY files.
+
+use crate::core::*;
+use crate::materials::{Lambertian, Material};
+use crate::mesh::TriangleMesh;
+use crate::spectrum::SpectrumType;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum MeshError {
+    Io(std::io::Error),
+    Parse(String),
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeshError::Io(err) => write!(f, "{}", err),
+            MeshError::Parse(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+impl From<std::io::Error> for MeshError {
+    fn from(err: std::io::Error) -> Self {
+        MeshError::Io(err)
+    }
+}
+
+fn parse_error<T>(msg: String) -> Result<T, MeshError> {
+    Err(MeshError::Parse(msg))
+}
+
+/// Load a mesh, picking the format from the file extension.
+pub fn load_mesh<S: Spectrum>(path: &Path) -> Result<TriangleMesh<S>, MeshError> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase());
+    match ext.as_deref() {
+        Some("obj") => load_obj(path),
+        Some("ply") => load_ply(path),
+        _ => parse_error(format!(
+            "{}: unknown mesh format, expected .obj or .ply",
+            path.display()
+        )),
+    }
+}
+
+fn parse_reals<'a, I>(tokens: I, n: usize, line_no: usize) -> Result<Vec<Real>, MeshError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let values: Vec<Real> = tokens
+        .take(n)
+        .map(|t| t.parse::<Real>())
+        .collect::<Result<_, _>>()
+        .map_err(|err| MeshError::Parse(format!("line {}: {}", line_no, err)))?;
+    if values.len() < n {
+        return parse_error(format!("line {}: expected {} numbers", line_no, n));
+    }
+    Ok(values)
+}
+
+/// Resolve a 1-based, possibly negative (relative), OBJ index.
+fn obj_index(token: &str, count: usize, line_no: usize) -> Result<usize, MeshError> {
+    let i: i64 = token
+        .parse()
+        .map_err(|_| MeshError::Parse(format!("line {}: bad index '{}'", line_no, token)))?;
+    let resolved = if i < 0 { count as i64 + i } else { i - 1 };
+    if resolved < 0 || resolved >= count as i64 {
+        return parse_error(format!(
+            "line {}: index {} out of range ({} elements)",
+            line_no, i, count
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+/// Load a Wavefront OBJ file. Polygons are triangulated as fans. Materials
+/// from referenced MTL files are mapped to `Lambertian` using the diffuse
+/// (`Kd`) and emissive (`Ke`) colours.
+pub fn load_obj<S: Spectrum>(path: &Path) -> Result<TriangleMesh<S>, MeshError> {
+    let file = std::fs::File::open(path)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    load_obj_from(BufReader::new(file), base_dir)
+        .map_err(|err| MeshError::Parse(format!("{}: {}", path.display(), err)))
+}
+
+/// Parse an OBJ file, MTL files it refers to are relative to `base_dir`.
+fn load_obj_from<S: Spectrum, R: BufRead>(
+    reader: R,
+    base_dir: &Path,
+) -> Result<TriangleMesh<S>, MeshError> {
+    let mut obj_positions: Vec<Vec3> = Vec::new();
+    let mut obj_normals: Vec<Vec3> = Vec::new();
+    let mut obj_uvs: Vec<[Real; 2]> = Vec::new();
+
+    // OBJ indexes positions, normals and uvs separately. Unique combinations
+    // become vertices of the mesh.
+    let mut vertex_map: HashMap<(usize, Option<usize>, Option<usize>), u32> = HashMap::new();
+    let mut positions: Vec<Vec3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut uvs: Vec<[Real; 2]> = Vec::new();
+    let mut indices: Vec<[u32; 3]> = Vec::new();
+    // Vertices without a normal in the file.
+    let mut missing_normals: Vec<bool> = Vec::new();
+    let mut has_uvs = true;
+
+    let mut mtl_materials: HashMap<String, Arc<dyn Material<S> + Send>> = HashMap::new();
+    let mut materials: Vec<Option<Arc<dyn Material<S> + Send>>> = vec![None];
+    let mut material_index: HashMap<String, u32> = HashMap::new();
+    let mut current_material: u32 = 0;
+    let mut material_ids: Vec<u32> = Vec::new();
+
+    for (i, line) in reader.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("v") => {
+                let v = parse_reals(tokens, 3, line_no)?;
+                obj_positions.push(Vec3(v[0], v[1], v[2]));
+            }
+            Some("vn") => {
+                let v = parse_reals(tokens, 3, line_no)?;
+                obj_normals.push(Vec3(v[0], v[1], v[2]).make_unit_vector());
+            }
+            Some("vt") => {
+                let v = parse_reals(tokens, 2, line_no)?;
+                obj_uvs.push([v[0], v[1]]);
+            }
+            Some("f") => {
+                let mut face: Vec<u32> = Vec::with_capacity(4);
+                for token in tokens {
+                    let mut parts = token.split('/');
+                    let p = obj_index(parts.next().unwrap_or(""), obj_positions.len(), line_no)?;
+                    let t = match parts.next() {
+                        Some(s) if !s.is_empty() => Some(obj_index(s, obj_uvs.len(), line_no)?),
+                        _ => None,
+                    };
+                    let n = match parts.next() {
+                        Some(s) if !s.is_empty() => Some(obj_index(s, obj_normals.len(), line_no)?),
+                        _ => None,
+                    };
+                    has_uvs &= t.is_some();
+
+                    let vertex = *vertex_map.entry((p, t, n)).or_insert_with(|| {
+                        positions.push(obj_positions[p]);
+                        normals.push(n.map_or(Vec3::ZEROS, |n| obj_normals[n]));
+                        missing_normals.push(n.is_none());
+                        uvs.push(t.map_or([0.0, 0.0], |t| obj_uvs[t]));
+                        (positions.len() - 1) as u32
+                    });
+                    face.push(vertex);
+                }
+                if face.len() < 3 {
+                    return parse_error(format!(
+                        "line {}: face needs at least 3 vertices",
+                        line_no
+                    ));
+                }
+                for k in 1..(face.len() - 1) {
+                    indices.push([face[0], face[k], face[k + 1]]);
+                    material_ids.push(current_material);
+                }
+            }
+            Some("mtllib") => {
+                let name: Vec<&str> = tokens.collect();
+                let mtl_path = base_dir.join(name.join(" "));
+                let loaded = load_mtl(&mtl_path)
+                    .map_err(|err| MeshError::Parse(format!("{}: {}", mtl_path.display(), err)))?;
+                mtl_materials.extend(loaded);
+            }
+            Some("usemtl") => {
+                let name: Vec<&str> = tokens.collect();
+                let name = name.join(" ");
+                current_material = match material_index.get(&name) {
+                    Some(id) => *id,
+                    None => {
+                        let material = match mtl_materials.get(&name) {
+                            Some(m) => m.clone(),
+                            None => {
+                                return parse_error(format!(
+                                    "line {}: unknown material '{}'",
+                                    line_no, name
+                                ))
+                            }
+                        };
+                        materials.push(Some(material));
+                        let id = (materials.len() - 1) as u32;
+                        material_index.insert(name, id);
+                        id
+                    }
+                };
+            }
+            _ => {}
+        }
+    }
+
+    if indices.is_empty() {
+        return parse_error("no faces".to_string());
+    }
+    if missing_normals.iter().all(|&missing| missing) {
+        normals = Vec::new();
+    } else if missing_normals.iter().any(|&missing| missing) {
+        // Some faces have normals and some do not. The vertices without one
+        // get the area weighted average of the normals of their triangles.
+        for tri in indices.iter() {
+            let [p0, p1, p2] = tri.map(|i| positions[i as usize]);
+            let n = (p1 - p0).cross(p2 - p0);
+            for &i in tri.iter() {
+                if missing_normals[i as usize] {
+                    normals[i as usize] += n;
+                }
+            }
+        }
+        for (n, &missing) in normals.iter_mut().zip(missing_normals.iter()) {
+            if missing && n.squared_length() > 0.0 {
+                *n = n.make_unit_vector();
+            }
+        }
+    }
+    if !has_uvs {
+        uvs = Vec::new();
+    }
+    if materials.len() == 1 {
+        material_ids = Vec::new();
+    }
+
+    TriangleMesh::new(positions, normals, uvs, indices)?.with_materials(materials, material_ids)
+}
+
+/// Load the materials of an MTL file as `Lambertian` materials.
+pub fn load_mtl<S: Spectrum>(
+    path: &Path,
+) -> Result<HashMap<String, Arc<dyn Material<S> + Send>>, MeshError> {
+    let file = std::fs::File::open(path)?;
+
+    fn finish<S: Spectrum>(
+        materials: &mut HashMap<String, Arc<dyn Material<S> + Send>>,
+        name: Option<String>,
+        kd: [Real; 3],
+        ke: [Real; 3],
+    ) {
+        if let Some(name) = name {
+            materials.insert(
+                name,
+                Arc::new(Lambertian {
+                    albedo: Arc::new(S::from_rgb(&kd)),
+                    emit: Arc::new(S::from_rgb_typed(&ke, SpectrumType::Illuminant)),
+                }),
+            );
+        }
+    }
+
+    let mut materials = HashMap::new();
+    let mut name: Option<String> = None;
+    let mut kd: [Real; 3] = [0.8, 0.8, 0.8];
+    let mut ke: [Real; 3] = [0.0, 0.0, 0.0];
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("newmtl") => {
+                finish(&mut materials, name.take(), kd, ke);
+                let rest: Vec<&str> = tokens.collect();
+                name = Some(rest.join(" "));
+                kd = [0.8, 0.8, 0.8];
+                ke = [0.0, 0.0, 0.0];
+            }
+            Some("Kd") => {
+                let v = parse_reals(tokens, 3, i + 1)?;
+                kd = [v[0], v[1], v[2]];
+            }
+            Some("Ke") => {
+                let v = parse_reals(tokens, 3, i + 1)?;
+                ke = [v[0], v[1], v[2]];
+            }
+            _ => {}
+        }
+    }
+    finish(&mut materials, name, kd, ke);
+    Ok(materials)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(s: &str) -> Option<PlyType> {
+        match s {
+            "char" | "int8" => Some(PlyType::I8),
+            "uchar" | "uint8" => Some(PlyType::U8),
+            "short" | "int16" => Some(PlyType::I16),
+            "ushort" | "uint16" => Some(PlyType::U16),
+            "int" | "int32" => Some(PlyType::I32),
+            "uint" | "uint32" => Some(PlyType::U32),
+            "float" | "float32" => Some(PlyType::F32),
+            "double" | "float64" => Some(PlyType::F64),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8,
+        }
+    }
+}
+
+struct PlyProperty {
+    name: String,
+    ty: PlyType,
+    /// Type of the length prefix for list properties.
+    list_len_ty: Option<PlyType>,
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+/// Reads scalar values from the body of a PLY file, whatever the format.
+struct PlyReader<R: BufRead> {
+    reader: R,
+    format: PlyFormat,
+    tokens: std::vec::IntoIter<String>,
+}
+
+impl<R: BufRead> PlyReader<R> {
+    fn read(&mut self, ty: PlyType) -> Result<f64, MeshError> {
+        if self.format == PlyFormat::Ascii {
+            let token = loop {
+                if let Some(t) = self.tokens.next() {
+                    break t;
+                }
+                let mut line = String::new();
+                if self.reader.read_line(&mut line)? == 0 {
+                    return parse_error("unexpected end of file".to_string());
+                }
+                self.tokens = line
+                    .split_whitespace()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<_>>()
+                    .into_iter();
+            };
+            return token
+                .parse::<f64>()
+                .map_err(|_| MeshError::Parse(format!("bad number '{}'", token)));
+        }
+
+        let mut buf = [0u8; 8];
+        let bytes = &mut buf[..ty.size()];
+        self.reader.read_exact(bytes)?;
+        if self.format == PlyFormat::BinaryBigEndian {
+            bytes.reverse();
+        }
+        let b = buf;
+        Ok(match ty {
+            PlyType::I8 => b[0] as i8 as f64,
+            PlyType::U8 => b[0] as f64,
+            PlyType::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            PlyType::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            PlyType::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            PlyType::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            PlyType::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            PlyType::F64 => f64::from_le_bytes(b),
+        })
+    }
+}
+
+/// Load an ASCII or binary PLY file. Vertex positions (`x`, `y`, `z`),
+/// normals (`nx`, `ny`, `nz`) and texture coordinates (`u`, `v` or `s`, `t`)
+/// are read, polygonal faces are triangulated as fans.
+pub fn load_ply<S: Spectrum>(path: &Path) -> Result<TriangleMesh<S>, MeshError> {
+    let file = std::fs::File::open(path)?;
+    load_ply_from(BufReader::new(file))
+        .map_err(|err| MeshError::Parse(format!("{}: {}", path.display(), err)))
+}
+
+fn load_ply_from<S: Spectrum, R: BufRead>(mut reader: R) -> Result<TriangleMesh<S>, MeshError> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim() != "ply" {
+        return parse_error("not a PLY file".to_string());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return parse_error("missing 'end_header'".to_string());
+        }
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", f, _version] => {
+                format = Some(match *f {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return parse_error(format!("unknown format '{}'", f)),
+                })
+            }
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| MeshError::Parse(format!("bad element count '{}'", count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", len_ty, ty, name] => {
+                let property =
+                    PlyProperty {
+                        name: name.to_string(),
+                        ty: PlyType::parse(ty)
+                            .ok_or_else(|| MeshError::Parse(format!("unknown type '{}'", ty)))?,
+                        list_len_ty: Some(PlyType::parse(len_ty).ok_or_else(|| {
+                            MeshError::Parse(format!("unknown type '{}'", len_ty))
+                        })?),
+                    };
+                match elements.last_mut() {
+                    Some(element) => element.properties.push(property),
+                    None => return parse_error("property before element".to_string()),
+                }
+            }
+            ["property", ty, name] => {
+                let property = PlyProperty {
+                    name: name.to_string(),
+                    ty: PlyType::parse(ty)
+                        .ok_or_else(|| MeshError::Parse(format!("unknown type '{}'", ty)))?,
+                    list_len_ty: None,
+                };
+                match elements.last_mut() {
+                    Some(element) => element.properties.push(property),
+                    None => return parse_error("property before element".to_string()),
+                }
+            }
+            ["end_header"] => break,
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return parse_error(format!("bad header line '{}'", line.trim())),
+        }
+    }
+    let format = format.ok_or_else(|| MeshError::Parse("missing 'format'".to_string()))?;
+
+    let mut body = PlyReader {
+        reader,
+        format,
+        tokens: Vec::new().into_iter(),
+    };
+
+    let mut positions: Vec<Vec3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut uvs: Vec<[Real; 2]> = Vec::new();
+    let mut indices: Vec<[u32; 3]> = Vec::new();
+
+    for element in elements.iter() {
+        let find = |names: &[&str]| {
+            element
+                .properties
+                .iter()
+                .position(|p| names.contains(&p.name.as_str()))
+        };
+        let (px, py, pz) = (find(&["x"]), find(&["y"]), find(&["z"]));
+        let (nx, ny, nz) = (find(&["nx"]), find(&["ny"]), find(&["nz"]));
+        let (tu, tv) = (
+            find(&["u", "s", "texture_u", "texture_s"]),
+            find(&["v", "t", "texture_v", "texture_t"]),
+        );
+        let face_indices = find(&["vertex_indices", "vertex_index"]);
+        let read_normals = nx.is_some() && ny.is_some() && nz.is_some();
+        let read_uvs = tu.is_some() && tv.is_some();
+
+        let mut values: Vec<f64> = vec![0.0; element.properties.len()];
+        let mut list: Vec<u32> = Vec::new();
+        for _ in 0..element.count {
+            for (k, property) in element.properties.iter().enumerate() {
+                match property.list_len_ty {
+                    None => values[k] = body.read(property.ty)?,
+                    Some(len_ty) => {
+                        let len = body.read(len_ty)? as usize;
+                        let is_face = Some(k) == face_indices;
+                        if is_face {
+                            list.clear();
+                        }
+                        for _ in 0..len {
+                            let v = body.read(property.ty)?;
+                            if is_face {
+                                if v < 0.0 || v.fract() != 0.0 || v > u32::MAX as f64 {
+                                    return parse_error(format!("bad face index {}", v));
+                                }
+                                list.push(v as u32);
+                            }
+                        }
+                    }
+                }
+            }
+
+            if element.name == "vertex" {
+                match (px, py, pz) {
+                    (Some(x), Some(y), Some(z)) => positions.push(Vec3(
+                        values[x] as Real,
+                        values[y] as Real,
+                        values[z] as Real,
+                    )),
+                    _ => return parse_error("vertex element without x, y, z".to_string()),
+                }
+                if read_normals {
+                    let n = Vec3(
+                        values[nx.unwrap()] as Real,
+                        values[ny.unwrap()] as Real,
+                        values[nz.unwrap()] as Real,
+                    );
+                    normals.push(n.make_unit_vector());
+                }
+                if read_uvs {
+                    uvs.push([values[tu.unwrap()] as Real, values[tv.unwrap()] as Real]);
+                }
+            } else if element.name == "face" {
+                if face_indices.is_none() {
+                    return parse_error("face element without vertex_indices".to_string());
+                }
+                if list.len() < 3 {
+                    return parse_error("face with less than 3 vertices".to_string());
+                }
+                for k in 1..(list.len() - 1) {
+                    indices.push([list[0], list[k], list[k + 1]]);
+                }
+            }
+        }
+    }
+
+    if indices.is_empty() {
+        return parse_error("no faces".to_string());
+    }
+    TriangleMesh::new(positions, normals, uvs, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrum::RGBSpectrum;
+
+    fn obj(text: &str) -> Result<TriangleMesh<RGBSpectrum>, MeshError> {
+        load_obj_from(text.as_bytes(), Path::new(""))
+    }
+
+    fn ply(text: &[u8]) -> Result<TriangleMesh<RGBSpectrum>, MeshError> {
+        load_ply_from(text)
+    }
+
+    fn assert_parse_error<T>(result: Result<T, MeshError>, expected: &str) {
+        match result {
+            Err(MeshError::Parse(msg)) => assert!(
+                msg.contains(expected),
+                "'{}' does not contain '{}'",
+                msg,
+                expected
+            ),
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("no error, expected '{}'", expected),
+        }
+    }
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-6, "{:?} != {:?}", a, b);
+    }
+
+    const QUAD: &str = "\
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+";
+
+    #[test]
+    fn obj_quad_is_a_fan() {
+        let mesh = obj(&format!("{}f 1 2 3 4\n", QUAD)).unwrap();
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        assert!(mesh.normals.is_empty());
+        assert!(mesh.uvs.is_empty());
+        assert!(mesh.material_ids.is_empty());
+    }
+
+    #[test]
+    fn obj_relative_indices_uvs_and_normals() {
+        let text = format!(
+            "{}vt 0 0\nvt 1 0\nvt 1 1\nvn 0 0 2\nf -4/1/1 -3/2/1 -2/3/1\n",
+            QUAD
+        );
+        let mesh = obj(&text).unwrap();
+        assert_eq!(mesh.indices, vec![[0, 1, 2]]);
+        assert_close(mesh.positions[2], Vec3(1.0, 1.0, 0.0));
+        assert_eq!(mesh.uvs[1], [1.0, 0.0]);
+        assert_eq!(mesh.normals.len(), 3);
+        for n in mesh.normals.iter() {
+            assert_close(*n, Vec3(0.0, 0.0, 1.0));
+        }
+    }
+
+    #[test]
+    fn obj_faces_without_normals_use_the_geometric_normal() {
+        let text = format!("{}vn 0 0 1\nf 1//1 2//1 3//1\nf 1 3 4\n", QUAD);
+        let mesh = obj(&text).unwrap();
+        assert_eq!(mesh.positions.len(), 6);
+        assert_eq!(mesh.normals.len(), 6);
+        for n in mesh.normals.iter() {
+            assert_close(*n, Vec3(0.0, 0.0, 1.0));
+        }
+    }
+
+    #[test]
+    fn obj_errors() {
+        assert_parse_error(
+            obj(&format!("{}f 1 2 5\n", QUAD)),
+            "line 5: index 5 out of range",
+        );
+        assert_parse_error(obj(&format!("{}f 1 -5 3\n", QUAD)), "index -5 out of range");
+        assert_parse_error(obj(&format!("{}f 1 2\n", QUAD)), "at least 3 vertices");
+        assert_parse_error(obj(&format!("{}f 1 x 3\n", QUAD)), "bad index 'x'");
+        assert_parse_error(obj("v 0 0\n"), "expected 3 numbers");
+        assert_parse_error(
+            obj(&format!("{}usemtl red\n", QUAD)),
+            "unknown material 'red'",
+        );
+        assert_parse_error(obj(QUAD), "no faces");
+    }
+
+    const PLY_HEADER: &str = "\
+ply
+format ascii 1.0
+comment a unit square
+element vertex 4
+property float x
+property float y
+property float z
+property float u
+property float v
+element face 2
+property list uchar int vertex_indices
+end_header
+";
+
+    #[test]
+    fn ply_ascii() {
+        let text = format!(
+            "{}0 0 0 0 0\n1 0 0 1 0\n1 1 0 1 1\n0 1 0 0 1\n3 0 1 2\n4 0 1 2 3\n",
+            PLY_HEADER
+        );
+        let mesh = ply(text.as_bytes()).unwrap();
+        assert_close(mesh.positions[2], Vec3(1.0, 1.0, 0.0));
+        assert_eq!(mesh.uvs[3], [0.0, 1.0]);
+        assert!(mesh.normals.is_empty());
+        assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn ply_binary() {
+        let mut data = b"ply\nformat binary_big_endian 1.0\nelement vertex 3\n\
+property double x\nproperty double y\nproperty double z\n\
+element face 1\nproperty list uchar ushort vertex_indices\nend_header\n"
+            .to_vec();
+        for p in [[0.0f64, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 2.0, 0.0]].iter() {
+            for c in p.iter() {
+                data.extend_from_slice(&c.to_be_bytes());
+            }
+        }
+        data.push(3);
+        for i in [0u16, 1, 2].iter() {
+            data.extend_from_slice(&i.to_be_bytes());
+        }
+        let mesh = ply(&data).unwrap();
+        assert_close(mesh.positions[2], Vec3(0.0, 2.0, 0.0));
+        assert_eq!(mesh.indices, vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn ply_errors() {
+        let faces = |faces: &str| {
+            format!(
+                "{}0 0 0 0 0\n1 0 0 1 0\n1 1 0 1 1\n0 1 0 0 1\n{}",
+                PLY_HEADER, faces
+            )
+        };
+        assert_parse_error(
+            ply(faces("3 0 -1 2\n3 0 1 2\n").as_bytes()),
+            "bad face index -1",
+        );
+        assert_parse_error(
+            ply(faces("3 0 1.5 2\n3 0 1 2\n").as_bytes()),
+            "bad face index 1.5",
+        );
+        assert_parse_error(
+            ply(faces("3 0 1 4\n3 0 1 2\n").as_bytes()),
+            "face index 4 out of range",
+        );
+        assert_parse_error(
+            ply(faces("2 0 1\n3 0 1 2\n").as_bytes()),
+            "less than 3 vertices",
+        );
+        assert_parse_error(ply(faces("3 0 1 2\n").as_bytes()), "unexpected end of file");
+        assert_parse_error(ply(b"obj\n"), "not a PLY file");
+        assert_parse_error(ply(b"ply\nformat ascii 1.0\n"), "missing 'end_header'");
+        assert_parse_error(
+            ply(b"ply\nformat ascii 1.0\nproperty float x\nend_header\n"),
+            "property before element",
+        );
+        assert_parse_error(
+            ply(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty half x\nend_header\n"),
+            "unknown type 'half'",
+        );
+    }
+
+    #[test]
+    fn inconsistent_buffers_are_errors() {
+        let positions = vec![Vec3::ZEROS, Vec3(1.0, 0.0, 0.0), Vec3(0.0, 1.0, 0.0)];
+        let result = TriangleMesh::<RGBSpectrum>::new(
+            positions.clone(),
+            vec![Vec3(0.0, 0.0, 1.0)],
+            Vec::new(),
+            vec![[0, 1, 2]],
+        );
+        assert_parse_error(result, "1 normals for 3 vertices");
+        let mesh =
+            TriangleMesh::<RGBSpectrum>::new(positions, Vec::new(), Vec::new(), vec![[0, 1, 2]])
+                .unwrap();
+        assert_parse_error(
+            mesh.with_materials(vec![None], vec![0, 0]),
+            "2 material ids for 1 triangles",
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! Complex indices of refraction of metals, for `materials::Metal`.
+//!
+//! Gold, silver and copper are after Johnson and Christy, "Optical constants
+//! of the noble metals" (1972), aluminium after Rakić, "Algorithm for the
+//! determination of intrinsic optical constants of metal films" (1995). The
+//! copper table is the one shipped with pbrt, the others are resampled to
+//! every 25 nm.
+
+use crate::core::*;
+
+use serde::Deserialize;
+
+/// A metal with a tabulated index of refraction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetalPreset {
+    Gold,
+    Silver,
+    Copper,
+    #[serde(alias = "aluminum")]
+    Aluminium,
+}
+
+impl MetalPreset {
+    /// Real part `eta` and imaginary part `k` of the index of refraction.
+    pub fn ior<S: Spectrum>(self) -> (S, S) {
+        let (lambda, eta, k): (&[Real], &[Real], &[Real]) = match self {
+            MetalPreset::Gold => (&METAL_LAMBDA, &GOLD_ETA, &GOLD_K),
+            MetalPreset::Silver => (&METAL_LAMBDA, &SILVER_ETA, &SILVER_K),
+            MetalPreset::Copper => (&COPPER_LAMBDA, &COPPER_ETA, &COPPER_K),
+            MetalPreset::Aluminium => (&METAL_LAMBDA, &ALUMINIUM_ETA, &ALUMINIUM_K),
+        };
+        (
+            S::from_sampled(lambda, eta, lambda.len()),
+            S::from_sampled(lambda, k, lambda.len()),
+        )
+    }
+}
+
+pub const METAL_N_SAMPLES: usize = 21;
+pub const METAL_LAMBDA: [Real; METAL_N_SAMPLES] = [
+    300.0, 325.0, 350.0, 375.0, 400.0, 425.0, 450.0, 475.0, 500.0, 525.0, 550.0, 575.0, 600.0,
+    625.0, 650.0, 675.0, 700.0, 725.0, 750.0, 775.0, 800.0,
+];
+
+pub const GOLD_ETA: [Real; METAL_N_SAMPLES] = [
+    1.815, 1.807, 1.727, 1.637, 1.467, 1.424, 1.399, 1.285, 0.970, 0.581, 0.355, 0.264, 0.220,
+    0.190, 0.166, 0.160, 0.160, 0.163, 0.168, 0.175, 0.182,
+];
+pub const GOLD_K: [Real; METAL_N_SAMPLES] = [
+    1.883, 1.852, 1.849, 1.894, 1.948, 1.923, 1.881, 1.831, 1.870, 2.165, 2.696, 2.989, 3.082,
+    3.331, 3.581, 3.764, 3.948, 4.207, 4.445, 4.680, 4.909,
+];
+
+pub const SILVER_ETA: [Real; METAL_N_SAMPLES] = [
+    1.340, 0.923, 0.224, 0.073, 0.050, 0.046, 0.040, 0.045, 0.050, 0.054, 0.059, 0.059, 0.055,
+    0.055, 0.052, 0.048, 0.041, 0.037, 0.031, 0.035, 0.040,
+];
+pub const SILVER_K: [Real; METAL_N_SAMPLES] = [
+    0.960, 0.615, 1.339, 1.767, 2.070, 2.370, 2.657, 2.873, 3.093, 3.349, 3.586, 3.809, 4.023,
+    4.249, 4.483, 4.654, 4.838, 5.036, 5.242, 5.366, 5.490,
+];
+
+pub const ALUMINIUM_ETA: [Real; METAL_N_SAMPLES] = [
+    0.276, 0.326, 0.378, 0.433, 0.490, 0.553, 0.618, 0.690, 0.769, 0.860, 0.958, 1.071, 1.200,
+    1.339, 1.490, 1.651, 1.830, 2.040, 2.280, 2.530, 2.800,
+];
+pub const ALUMINIUM_K: [Real; METAL_N_SAMPLES] = [
+    3.610, 3.923, 4.236, 4.548, 4.860, 5.165, 5.470, 5.776, 6.080, 6.387, 6.690, 6.980, 7.260,
+    7.530, 7.790, 8.050, 8.310, 8.480, 8.620, 8.560, 8.450,
+];
+
+pub const COPPER_N_SAMPLES: usize = 56;
+pub const COPPER_LAMBDA: [Real; COPPER_N_SAMPLES] = [
+    298.76, 302.40, 306.13, 309.96, 313.88, 317.91, 322.04, 326.27, 330.62, 335.09, 339.68, 344.40,
+    349.25, 354.24, 359.37, 364.66, 370.10, 375.71, 381.49, 387.45, 393.60, 399.95, 406.51, 413.28,
+    420.29, 427.53, 435.03, 442.80, 450.85, 459.20, 467.86, 476.86, 486.21, 495.94, 506.06, 516.60,
+    527.59, 539.06, 551.04, 563.56, 576.67, 590.40, 604.80, 619.92, 635.82, 652.55, 670.18, 688.80,
+    708.48, 729.32, 751.42, 774.90, 799.90, 826.56, 855.06, 885.60,
+];
+pub const COPPER_ETA: [Real; COPPER_N_SAMPLES] = [
+    1.400313, 1.38, 1.358438, 1.34, 1.329063, 1.325, 1.3325, 1.34, 1.334375, 1.325, 1.317812, 1.31,
+    1.300313, 1.29, 1.281563, 1.27, 1.249062, 1.225, 1.2, 1.18, 1.174375, 1.175, 1.1775, 1.18,
+    1.178125, 1.175, 1.172812, 1.17, 1.165312, 1.16, 1.155312, 1.15, 1.142812, 1.135, 1.131562,
+    1.12, 1.092437, 1.04, 0.950375, 0.826, 0.645875, 0.468, 0.35125, 0.272, 0.230813, 0.214,
+    0.20925, 0.213, 0.21625, 0.223, 0.2365, 0.25, 0.254188, 0.26, 0.28, 0.3,
+];
+pub const COPPER_K: [Real; COPPER_N_SAMPLES] = [
+    1.662125, 1.687, 1.703313, 1.72, 1.744563, 1.77, 1.791625, 1.81, 1.822125, 1.834, 1.85175,
+    1.872, 1.89425, 1.916, 1.931688, 1.95, 1.972438, 2.015, 2.121562, 2.21, 2.177188, 2.13,
+    2.160063, 2.21, 2.249938, 2.289, 2.326, 2.362, 2.397625, 2.433, 2.469187, 2.504, 2.535875,
+    2.564, 2.589625, 2.605, 2.595562, 2.583, 2.5765, 2.599, 2.678062, 2.809, 3.01075, 3.24,
+    3.458187, 3.67, 3.863125, 4.05, 4.239563, 4.43, 4.619563, 4.817, 5.034125, 5.26, 5.485625,
+    5.717,
+];
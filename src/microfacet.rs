@@ -0,0 +1,525 @@
+//! Microfacet models for glossy reflection and transmission (Walter et al.
+//! 2007), with sampling of the visible normals after Heitz (2018) for GGX
+//! and Heitz and d'Eon (2014) for Beckmann.
+//!
+//! Directions are in the local frame of the surface, with the normal along
+//! z, and point away from the surface.
+
+use crate::core::*;
+use crate::ray::{PDF, UVW};
+
+use serde::Deserialize;
+
+/// Distribution of the microfacet normals.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Distribution {
+    #[default]
+    Ggx,
+    Beckmann,
+}
+
+/// An isotropic microfacet distribution with the width `alpha`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Microfacet {
+    pub distribution: Distribution,
+    pub alpha: Real,
+}
+
+fn tan2_theta(w: Vec3) -> Real {
+    let cos2 = w.z() * w.z();
+    (1.0 - cos2).max(0.0) / cos2
+}
+
+impl Microfacet {
+    /// Distribution for a perceptually linear `roughness`, `alpha` is its
+    /// square. Very small values are kept away from a perfect mirror.
+    pub fn from_roughness(distribution: Distribution, roughness: Real) -> Microfacet {
+        Microfacet {
+            distribution,
+            alpha: (roughness * roughness).max(1e-3),
+        }
+    }
+
+    /// Density of the microfacet normal `h`, per projected area.
+    pub fn d(&self, h: Vec3) -> Real {
+        let tan2 = tan2_theta(h);
+        if !tan2.is_finite() {
+            return 0.0;
+        }
+        let cos4 = h.z() * h.z() * h.z() * h.z();
+        let a2 = self.alpha * self.alpha;
+        match self.distribution {
+            Distribution::Ggx => a2 / (PI * cos4 * (a2 + tan2) * (a2 + tan2)),
+            Distribution::Beckmann => (-tan2 / a2).exp() / (PI * a2 * cos4),
+        }
+    }
+
+    /// Smith's auxiliary function, the masked microfacet area per visible
+    /// area seen from `w`.
+    fn lambda(&self, w: Vec3) -> Real {
+        let tan2 = tan2_theta(w);
+        if !tan2.is_finite() {
+            return 0.0;
+        }
+        match self.distribution {
+            Distribution::Ggx => 0.5 * ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0),
+            Distribution::Beckmann => {
+                let a = 1.0 / (self.alpha * tan2.sqrt());
+                if a >= 1.6 {
+                    0.0
+                } else {
+                    (1.0 - 1.259 * a + 0.396 * a * a) / (3.535 * a + 2.181 * a * a)
+                }
+            }
+        }
+    }
+
+    /// Fraction of the microfacets visible from `w`.
+    pub fn g1(&self, w: Vec3) -> Real {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of the microfacets visible from both `wo` and `wi`.
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> Real {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of the normal `h` among the microfacets visible from `w`.
+    pub fn visible_pdf(&self, w: Vec3, h: Vec3) -> Real {
+        self.g1(w) / w.z().abs() * self.d(h) * w.dot(h).abs()
+    }
+
+    /// Sample a normal among the microfacets visible from `w`, always on
+    /// the upper side.
+    pub fn sample_visible(&self, w: Vec3, u1: Real, u2: Real) -> Vec3 {
+        // Stretch to the distribution with alpha 1.
+        let mut wh = Vec3(self.alpha * w.x(), self.alpha * w.y(), w.z()).make_unit_vector();
+        if wh.z() < 0.0 {
+            wh = -1.0 * wh;
+        }
+        let (slope_x, slope_y) = match self.distribution {
+            Distribution::Ggx => return self.sample_visible_ggx(wh, u1, u2),
+            Distribution::Beckmann => beckmann_sample11(wh.z(), u1, u2),
+        };
+        // Rotate to the azimuth of `wh` and unstretch.
+        let sin_theta = (1.0 - wh.z() * wh.z()).max(0.0).sqrt();
+        let (cos_phi, sin_phi) = if sin_theta > 0.0 {
+            (wh.x() / sin_theta, wh.y() / sin_theta)
+        } else {
+            (1.0, 0.0)
+        };
+        let sx = cos_phi * slope_x - sin_phi * slope_y;
+        let sy = sin_phi * slope_x + cos_phi * slope_y;
+        Vec3(-self.alpha * sx, -self.alpha * sy, 1.0).make_unit_vector()
+    }
+
+    /// Heitz 2018, sampling the projected half disk seen from `wh`.
+    fn sample_visible_ggx(&self, wh: Vec3, u1: Real, u2: Real) -> Vec3 {
+        let len2 = wh.x() * wh.x() + wh.y() * wh.y();
+        let t1 = if len2 > 0.0 {
+            Vec3(-wh.y(), wh.x(), 0.0) * (1.0 / len2.sqrt())
+        } else {
+            Vec3(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(t1);
+
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + wh.z());
+        let p2 = lerp(s, (1.0 - p1 * p1).sqrt(), r * phi.sin());
+        let pz = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        let nh = p1 * t1 + p2 * t2 + pz * wh;
+        Vec3(self.alpha * nh.x(), self.alpha * nh.y(), nh.z().max(1e-6)).make_unit_vector()
+    }
+}
+
+/// Sample the slopes of the visible normals of the Beckmann distribution
+/// with alpha 1, seen at the angle with cosine `cos_theta`.
+fn beckmann_sample11(cos_theta: Real, u1: Real, u2: Real) -> (Real, Real) {
+    if cos_theta > 0.9999 {
+        let r = (-(1.0 - u1).ln()).sqrt();
+        let phi = 2.0 * PI * u2;
+        return (r * phi.cos(), r * phi.sin());
+    }
+
+    // Invert the cumulative distribution of the slope along x with Newton's
+    // method, starting from a fit.
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let tan_theta = sin_theta / cos_theta;
+    let cot_theta = 1.0 / tan_theta;
+    let mut a = -1.0;
+    let mut c = erf(cot_theta);
+    let sample_x = u1.max(1e-6);
+    let theta = cos_theta.acos();
+    let fit = 1.0 + theta * (-0.876 + theta * (0.4265 - 0.0594 * theta));
+    let mut b = c - (1.0 + c) * (1.0 - sample_x).powf(fit);
+    let sqrt_pi_inv = 1.0 / PI.sqrt();
+    let normalization = 1.0 / (1.0 + c + sqrt_pi_inv * tan_theta * (-cot_theta * cot_theta).exp());
+    for _ in 0..10 {
+        if !(b >= a && b <= c) {
+            b = 0.5 * (a + c);
+        }
+        let inv_erf = erf_inv(b);
+        let value = normalization
+            * (1.0 + b + sqrt_pi_inv * tan_theta * (-inv_erf * inv_erf).exp())
+            - sample_x;
+        if value.abs() < 1e-5 {
+            break;
+        }
+        if value > 0.0 {
+            c = b;
+        } else {
+            a = b;
+        }
+        let derivative = normalization * (1.0 - inv_erf * tan_theta);
+        b -= value / derivative;
+    }
+    (erf_inv(b), erf_inv(2.0 * u2.max(1e-6) - 1.0))
+}
+
+/// Error function, Abramowitz and Stegun 7.1.26.
+fn erf(x: Real) -> Real {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly =
+        ((((1.0614054 * t - 1.453152) * t + 1.4214138) * t - 0.28449672) * t + 0.2548296) * t;
+    (1.0 - poly * (-x * x).exp()).copysign(x)
+}
+
+/// Inverse of the error function, Giles 2010.
+fn erf_inv(x: Real) -> Real {
+    let x = x.clamp(-0.99999, 0.99999);
+    let w = -((1.0 - x) * (1.0 + x)).ln();
+    let p = if w < 5.0 {
+        let w = w - 2.5;
+        let mut p = 2.8102264e-8;
+        for c in [
+            3.4327394e-7,
+            -3.5233877e-6,
+            -4.3915065e-6,
+            0.00021858087,
+            -0.001253725,
+            -0.0041776816,
+            0.24664073,
+            1.5014094,
+        ] {
+            p = c + p * w;
+        }
+        p
+    } else {
+        let w = w.sqrt() - 3.0;
+        let mut p = -0.00020021426;
+        for c in [
+            0.00010095056,
+            0.0013493432,
+            -0.0036734284,
+            0.0057395077,
+            -0.007622461,
+            0.0094388705,
+            1.001674,
+            2.8329768,
+        ] {
+            p = c + p * w;
+        }
+        p
+    };
+    p * x
+}
+
+/// Fresnel reflectance of a dielectric for unpolarised light. `cos_i` is the
+/// cosine of the angle of incidence and `eta` the index of refraction of the
+/// inside over that of the outside. Light arriving from the inside has a
+/// negative `cos_i`.
+pub fn fresnel_dielectric(cos_i: Real, eta: Real) -> Real {
+    let (cos_i, eta) = if cos_i < 0.0 {
+        (-cos_i, 1.0 / eta)
+    } else {
+        (cos_i, eta)
+    };
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parl * r_parl + r_perp * r_perp)
+}
+
+/// Reflect `wo` about `n`.
+fn reflect(wo: Vec3, n: Vec3) -> Vec3 {
+    -1.0 * wo + 2.0 * wo.dot(n) * n
+}
+
+/// Refract `wo` through the microfacet `n`, for the index of refraction
+/// `eta` of the inside over the outside. `None` on total internal
+/// reflection.
+fn refract(wo: Vec3, n: Vec3, eta: Real) -> Option<Vec3> {
+    let mut cos_i = wo.dot(n);
+    let (n, eta) = if cos_i < 0.0 {
+        cos_i = -cos_i;
+        (-1.0 * n, 1.0 / eta)
+    } else {
+        (n, eta)
+    };
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-1.0 / eta * wo + (cos_i / eta - cos_t) * n)
+}
+
+/// Glossy scattering of a rough surface. With `eta` the surface is a
+/// dielectric with that index of refraction inside and light is both
+/// reflected and transmitted, otherwise it is only reflected.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MicrofacetBSDF {
+    pub microfacet: Microfacet,
+    pub eta: Real,
+    pub transmission: bool,
+}
+
+impl MicrofacetBSDF {
+    /// Reflection and transmission probabilities at the microfacet `h`.
+    fn split(&self, wo: Vec3, h: Vec3) -> (Real, Real) {
+        let f = fresnel_dielectric(wo.dot(h), self.eta);
+        if self.transmission {
+            (f, 1.0 - f)
+        } else {
+            (1.0, 0.0)
+        }
+    }
+
+    /// The generalized half vector of `wo` and `wi` on the upper side, and
+    /// the relative index of refraction along the way. `None` if the pair
+    /// can not be scattered.
+    fn half_vector(&self, wo: Vec3, wi: Vec3) -> Option<(Vec3, Real)> {
+        let cos_o = wo.z();
+        let cos_i = wi.z();
+        if cos_o == 0.0 || cos_i == 0.0 {
+            return None;
+        }
+        let reflect = cos_o * cos_i > 0.0;
+        if !reflect && !self.transmission {
+            return None;
+        }
+        let etap = if reflect {
+            1.0
+        } else if cos_o > 0.0 {
+            self.eta
+        } else {
+            1.0 / self.eta
+        };
+        let mut h = wi * etap + wo;
+        if h.squared_length() == 0.0 {
+            return None;
+        }
+        h = h.make_unit_vector();
+        if h.z() < 0.0 {
+            h = -1.0 * h;
+        }
+        // Microfacets seen from the back do not scatter.
+        if h.dot(wi) * cos_i < 0.0 || h.dot(wo) * cos_o < 0.0 {
+            return None;
+        }
+        Some((h, etap))
+    }
+
+    /// The BSDF times the cosine of `wi`, for light going from `wi` to `wo`.
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> Real {
+        let (h, etap) = match self.half_vector(wo, wi) {
+            Some(v) => v,
+            None => return 0.0,
+        };
+        let d_g = self.microfacet.d(h) * self.microfacet.g(wo, wi);
+        let (r, t) = if self.transmission {
+            let f = fresnel_dielectric(wo.dot(h), self.eta);
+            (f, 1.0 - f)
+        } else {
+            (fresnel_dielectric(wo.dot(h).abs(), self.eta), 0.0)
+        };
+        if etap == 1.0 {
+            d_g * r / (4.0 * wo.z().abs())
+        } else {
+            let denom = wi.dot(h) + wo.dot(h) / etap;
+            // Radiance is compressed into the smaller solid angle of the
+            // denser medium.
+            d_g * t * (wi.dot(h) * wo.dot(h) / (wo.z() * denom * denom)).abs() / (etap * etap)
+        }
+    }
+
+    /// Density of sampling `wi` from `wo`, per solid angle.
+    pub fn pdf(&self, wo: Vec3, wi: Vec3) -> Real {
+        let (h, etap) = match self.half_vector(wo, wi) {
+            Some(v) => v,
+            None => return 0.0,
+        };
+        let (r, t) = self.split(wo, h);
+        let visible = self.microfacet.visible_pdf(wo, h);
+        if etap == 1.0 {
+            visible / (4.0 * wo.dot(h).abs()) * r / (r + t)
+        } else {
+            let denom = wi.dot(h) + wo.dot(h) / etap;
+            visible * wi.dot(h).abs() / (denom * denom) * t / (r + t)
+        }
+    }
+
+    /// Sample a direction `wi` for `wo` from the visible microfacets.
+    pub fn sample(&self, wo: Vec3, u1: Real, u2: Real, u3: Real) -> Option<Vec3> {
+        if wo.z() == 0.0 {
+            return None;
+        }
+        let h = self.microfacet.sample_visible(wo, u1, u2);
+        let (r, t) = self.split(wo, h);
+        let wi = if u3 < r / (r + t) {
+            let wi = reflect(wo, h);
+            if wi.z() * wo.z() <= 0.0 {
+                return None;
+            }
+            wi
+        } else {
+            let wi = refract(wo, h, self.eta)?;
+            if wi.z() * wo.z() >= 0.0 {
+                return None;
+            }
+            wi
+        };
+        Some(wi)
+    }
+}
+
+/// Sampling of a `MicrofacetBSDF` for `MixturePDF`.
+pub struct MicrofacetPDF {
+    pub uvw: UVW,
+    /// Direction towards where the light goes, in the local frame.
+    pub wo: Vec3,
+    pub bsdf: MicrofacetBSDF,
+}
+
+impl MicrofacetPDF {
+    /// `wo` in world space, the frame is built around `normal`.
+    pub fn new(bsdf: MicrofacetBSDF, normal: Vec3, wo: Vec3) -> MicrofacetPDF {
+        let uvw = UVW::onb_from_w(normal);
+        let wo = uvw.to_local(wo.make_unit_vector());
+        MicrofacetPDF { uvw, wo, bsdf }
+    }
+}
+
+impl PDF for MicrofacetPDF {
+    fn value(&self, direction: Vec3) -> Real {
+        let wi = self.uvw.to_local(direction.make_unit_vector());
+        self.bsdf.pdf(self.wo, wi)
+    }
+
+    fn generate(&self) -> Vec3 {
+        let u = [(); 3].map(|_| rand::random::<Real>());
+        match self.bsdf.sample(self.wo, u[0], u[1], u[2]) {
+            Some(wi) => self.uvw.local(wi),
+            // A direction along the surface, that nothing is scattered to.
+            None => self.uvw.u,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Integral of `f` over the sphere of directions, on a grid in the
+    /// cosine of the polar angle and the azimuth.
+    fn integrate(f: impl Fn(Vec3) -> Real) -> Real {
+        let n = 400;
+        let mut sum = 0.0;
+        for i in 0..n {
+            let z = -1.0 + 2.0 * (i as Real + 0.5) / n as Real;
+            let r = (1.0 - z * z).sqrt();
+            for j in 0..n {
+                let phi = 2.0 * PI * (j as Real + 0.5) / n as Real;
+                sum += f(Vec3(r * phi.cos(), r * phi.sin(), z));
+            }
+        }
+        sum * 4.0 * PI / (n * n) as Real
+    }
+
+    fn wo() -> Vec3 {
+        Vec3(0.6, 0.0, 0.8)
+    }
+
+    #[test]
+    fn normals_project_to_the_surface() {
+        for distribution in [Distribution::Ggx, Distribution::Beckmann] {
+            let m = Microfacet::from_roughness(distribution, 0.6);
+            let projected = integrate(|h| if h.z() > 0.0 { m.d(h) * h.z() } else { 0.0 });
+            assert!(
+                (projected - 1.0).abs() < 0.01,
+                "{:?}: {}",
+                distribution,
+                projected
+            );
+            let visible = integrate(|h| {
+                if h.z() > 0.0 {
+                    m.visible_pdf(wo(), h)
+                } else {
+                    0.0
+                }
+            });
+            assert!(
+                (visible - 1.0).abs() < 0.02,
+                "{:?}: {}",
+                distribution,
+                visible
+            );
+        }
+    }
+
+    /// Mean of the BSDF over its density for directions sampled from `wo`.
+    fn sampled_albedo(bsdf: &MicrofacetBSDF) -> Real {
+        let n = 100000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let u = [(); 3].map(|_| rand::random::<Real>());
+            if let Some(wi) = bsdf.sample(wo(), u[0], u[1], u[2]) {
+                let pdf = bsdf.pdf(wo(), wi);
+                if pdf > 0.0 {
+                    sum += bsdf.eval(wo(), wi) / pdf;
+                }
+            }
+        }
+        sum / n as Real
+    }
+
+    #[test]
+    fn sampling_matches_the_bsdf() {
+        for distribution in [Distribution::Ggx, Distribution::Beckmann] {
+            for transmission in [false, true] {
+                let bsdf = MicrofacetBSDF {
+                    microfacet: Microfacet::from_roughness(distribution, 0.5),
+                    eta: 1.5,
+                    transmission,
+                };
+                let albedo = integrate(|wi| bsdf.eval(wo(), wi));
+                assert!(albedo > 0.0 && albedo <= 1.0, "{}", albedo);
+                let sampled = sampled_albedo(&bsdf);
+                assert!(
+                    (sampled - albedo).abs() < 0.02 * albedo,
+                    "{:?} transmission {}: {} != {}",
+                    distribution,
+                    transmission,
+                    sampled,
+                    albedo
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dielectric_fresnel() {
+        // 4% at normal incidence for glass, from either side.
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-4);
+        assert!((fresnel_dielectric(-1.0, 1.5) - 0.04).abs() < 1e-4);
+        // Total internal reflection beyond the critical angle.
+        assert_eq!(fresnel_dielectric(-0.5, 1.5), 1.0);
+    }
+}
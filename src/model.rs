@@ -1,52 +1,57 @@
-use crate::core::{Real, EPS, PI, R_MAX};
+use crate::core::{Real, Spectrum, HIT_EPS, PI, R_MAX};
 use crate::{
-    hitable::{Hit, Hitable},
+    aabb::AABB,
+    hitable::{Hit, HitList, Hitable},
     materials::Material,
-    ray::random_to_sphere,
     ray::Ray,
     ray::UVW,
+    ray::{random_in_unit_sphere, random_to_sphere},
     Quaternion, Vec3,
 };
 
 use std::sync::Arc;
 
 #[derive(Clone)]
-pub struct Sphere {
+pub struct Sphere<S: Spectrum> {
     pub center: Vec3,
     pub radius: Real,
-    pub material: Option<Arc<dyn Material + Send>>,
+    pub material: Option<Arc<dyn Material<S> + Send>>,
 }
 
-impl Hitable for Sphere {
-    fn hit(&self, r: &Ray) -> Option<Hit> {
+impl<S: Spectrum> Hitable<S> for Sphere<S> {
+    fn hit(&self, r: &Ray) -> Option<Hit<S>> {
         let oc = r.origin - self.center;
         let a = r.direction.dot(r.direction);
         let b = oc.dot(r.direction);
         let c = oc.dot(oc) - self.radius * self.radius;
         let discriminant = b * b - a * c;
+        let t_min = HIT_EPS / a.sqrt();
         if discriminant > 0.0 {
             let mut t = (-b - discriminant.sqrt()) / a;
-            if t < R_MAX && t > EPS {
+            if t < R_MAX && t > t_min {
                 let p = r.point_at_paramter(t);
+                let normal = (p - self.center) / self.radius;
+                let (u, v) = sphere_uv(normal);
                 return Some(Hit {
                     t,
                     p,
-                    u: 0.0, //TODO
-                    v: 0.0, //TODO
-                    normal: (p - self.center) / self.radius,
+                    u,
+                    v,
+                    normal,
                     material: self.material.clone(),
                 });
             }
             t = (-b + discriminant.sqrt()) / a;
-            if t < R_MAX && t > EPS {
+            if t < R_MAX && t > t_min {
                 let p = r.point_at_paramter(t);
-
+                let normal = (p - self.center) / self.radius;
+                let (u, v) = sphere_uv(normal);
                 return Some(Hit {
                     t,
                     p,
-                    u: 0.0, //TODO
-                    v: 0.0, //TODO
-                    normal: (p - self.center) / self.radius,
+                    u,
+                    v,
+                    normal,
                     material: self.material.clone(),
                 });
             }
@@ -54,11 +59,17 @@ impl Hitable for Sphere {
         None
     }
 
+    fn bounding_box(&self) -> Option<AABB> {
+        let r = Vec3(self.radius, self.radius, self.radius);
+        Some(AABB::new(self.center - r, self.center + r))
+    }
+
     fn pdf_value(&self, ray_origin: Vec3, v: Vec3) -> Real {
         match self.hit(&Ray::new(ray_origin, v)) {
             Some(rec) => {
                 let cos_theta_max = (1.0
                     - self.radius * self.radius / (self.center - ray_origin).squared_length())
+                .max(0.0)
                 .sqrt();
                 let solid_angle = 2.0 * PI * (1.0 - cos_theta_max);
 
@@ -78,18 +89,47 @@ impl Hitable for Sphere {
         //println!("{:?}", distance_squared);
         onb.local(tmp)
     }
+
+    fn area(&self) -> Real {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn random_point(&self) -> Option<Hit<S>> {
+        let normal = random_in_unit_sphere().make_unit_vector();
+        let (u, v) = sphere_uv(normal);
+        Some(Hit {
+            t: 0.0,
+            p: self.center + self.radius * normal,
+            u,
+            v,
+            normal,
+            material: self.material.clone(),
+        })
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material<S> + Send>> {
+        self.material.iter().cloned().collect()
+    }
+}
+
+/// Texture coordinates of the point with the unit normal `n` on a sphere,
+/// `u` around the y axis and `v` from the bottom to the top.
+fn sphere_uv(n: Vec3) -> (Real, Real) {
+    let theta = (-n.y()).clamp(-1.0, 1.0).acos();
+    let phi = (-n.z()).atan2(n.x()) + PI;
+    (phi / (2.0 * PI), theta / PI)
 }
 
-pub struct Plane {
+pub struct Plane<S: Spectrum> {
     pub origin: Vec3,
     pub normal: Vec3,
-    pub rot_around_normal: Real, // Axis-Angle rotation around normal
+    pub rot_around_normal: Real, // Axis-Angle rotation around normal, in radians
     pub width: Real,
     pub height: Real,
-    pub material: Option<Arc<dyn Material + Send>>,
+    pub material: Option<Arc<dyn Material<S> + Send>>,
 }
-impl Plane {
-    pub fn new() -> Plane {
+impl<S: Spectrum> Plane<S> {
+    pub fn new() -> Plane<S> {
         Plane {
             origin: Vec3::ZEROS,
             normal: Vec3(0.0, 0.0, 1.0),
@@ -99,22 +139,42 @@ impl Plane {
             material: None,
         }
     }
+
+    /// Direction `v` in the frame of the plane, where the normal is the z
+    /// axis, the width is along x and the height along y.
+    fn to_local(&self, v: Vec3) -> Vec3 {
+        let irot = Quaternion::rot_from_vecs(self.normal.make_unit_vector(), Vec3(0.0, 0.0, 1.0));
+        rotate_z(irot.transform_vec(v), -self.rot_around_normal)
+    }
+
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        let rot = Quaternion::rot_from_vecs(Vec3(0.0, 0.0, 1.0), self.normal);
+        rot.transform_vec(rotate_z(v, self.rot_around_normal))
+    }
+}
+
+/// Rotate `v` by `angle` radians around the z axis.
+fn rotate_z(v: Vec3, angle: Real) -> Vec3 {
+    if angle == 0.0 {
+        return v;
+    }
+    let (sin, cos) = angle.sin_cos();
+    Vec3(v.x() * cos - v.y() * sin, v.x() * sin + v.y() * cos, v.z())
 }
 
-impl Hitable for Plane {
-    fn hit(&self, r: &Ray) -> Option<Hit> {
+impl<S: Spectrum> Hitable<S> for Plane<S> {
+    fn hit(&self, r: &Ray) -> Option<Hit<S>> {
         let local_normal = Vec3(0.0, 0.0, 1.0);
-        let irot = Quaternion::rot_from_vecs(self.normal.make_unit_vector(), local_normal);
         let local_ray = Ray {
-            origin: irot.transform_vec(r.origin - self.origin),
-            direction: irot.transform_vec(r.direction),
+            origin: self.to_local(r.origin - self.origin),
+            direction: self.to_local(r.direction),
         };
 
         // Check if we intersect the infinite plane.
         let denom = local_normal.dot(local_ray.direction);
         let t = (-1.0 * local_ray.origin).dot(local_normal) / denom;
 
-        if t > 1e-5 {
+        if t * r.direction.length() > HIT_EPS {
             // Check if we are in bounds.
             let local_p = local_ray.point_at_paramter(t);
 
@@ -122,8 +182,8 @@ impl Hitable for Plane {
                 return Some(Hit {
                     t,
                     p: r.point_at_paramter(t),
-                    u: 0.0, //TODO
-                    v: 0.0, //TODO
+                    u: local_p.x() / self.width + 0.5,
+                    v: local_p.y() / self.height + 0.5,
                     normal: self.normal,
                     material: self.material.clone(),
                 });
@@ -132,6 +192,17 @@ impl Hitable for Plane {
         None
     }
 
+    fn bounding_box(&self) -> Option<AABB> {
+        let (w, h) = (self.width / 2.0, self.height / 2.0);
+        let corners = [
+            self.origin + self.to_world(Vec3(-w, -h, 0.0)),
+            self.origin + self.to_world(Vec3(w, -h, 0.0)),
+            self.origin + self.to_world(Vec3(-w, h, 0.0)),
+            self.origin + self.to_world(Vec3(w, h, 0.0)),
+        ];
+        Some(AABB::from_points(&corners).pad(1e-3))
+    }
+
     fn pdf_value(&self, ray_origin: Vec3, v: Vec3) -> Real {
         if let Some(rec) = self.hit(&Ray::new(ray_origin, v)) {
             let area = self.width * self.height;
@@ -149,31 +220,50 @@ impl Hitable for Plane {
             0.0,
         );
 
-        let local_normal = Vec3::new(0.0, 0.0, 1.0);
-        let rot = Quaternion::rot_from_vecs(local_normal, self.normal);
-        let tmp = rot.transform_vec(local_random_point);
-        let global_random_point = tmp + self.origin;
+        let global_random_point = self.to_world(local_random_point) + self.origin;
 
         return global_random_point - ray_origin;
     }
+
+    fn area(&self) -> Real {
+        self.width * self.height
+    }
+
+    fn random_point(&self) -> Option<Hit<S>> {
+        let u = rand::random::<Real>();
+        let v = rand::random::<Real>();
+        let local_p = Vec3((u - 0.5) * self.width, (v - 0.5) * self.height, 0.0);
+        Some(Hit {
+            t: 0.0,
+            p: self.origin + self.to_world(local_p),
+            u,
+            v,
+            normal: self.normal,
+            material: self.material.clone(),
+        })
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material<S> + Send>> {
+        self.material.iter().cloned().collect()
+    }
 }
 
-pub struct Cuboid {
+pub struct Cuboid<S: Spectrum> {
     pub origin: Vec3,
     pub rot: Quaternion,
     size: Vec3,
-    pub material: Option<Arc<dyn Material + Send>>,
-    planes_cache: Vec<Box<dyn Hitable>>,
+    pub material: Option<Arc<dyn Material<S> + Send>>,
+    planes_cache: HitList<S>,
 }
 
-impl Cuboid {
-    pub fn new() -> Cuboid {
+impl<S: Spectrum> Cuboid<S> {
+    pub fn new() -> Cuboid<S> {
         let mut c = Cuboid {
             origin: Vec3::ZEROS,
             rot: Quaternion::UNIT,
             size: Vec3::ONES,
             material: None,
-            planes_cache: Vec::<Box<dyn Hitable>>::new(),
+            planes_cache: HitList::new(),
         };
         c.generate_planes_cache();
         c
@@ -191,11 +281,11 @@ impl Cuboid {
         self.generate_planes_cache();
         self
     }
-    pub fn material(mut self, material: Arc<dyn Material + Send>) -> Self {
+    pub fn material(mut self, material: Arc<dyn Material<S> + Send>) -> Self {
         self.material = Some(material);
         self
     }
-    pub fn build(self) -> Cuboid {
+    pub fn build(self) -> Cuboid<S> {
         self
     }
 
@@ -261,8 +351,8 @@ impl Cuboid {
     }
 }
 
-impl Hitable for Cuboid {
-    fn hit(&self, r: &Ray) -> Option<Hit> {
+impl<S: Spectrum> Hitable<S> for Cuboid<S> {
+    fn hit(&self, r: &Ray) -> Option<Hit<S>> {
         //The local cuboid consits of 4 planes.
         //It has origin in (0,0,0) and size (1,1,1).
         let irot = self.rot.inv();
@@ -284,4 +374,144 @@ impl Hitable for Cuboid {
             }
         }
     }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        let half = self.size / 2.0;
+        let mut corners = [Vec3::ZEROS; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let local = Vec3(
+                if i & 1 == 0 { -half.x() } else { half.x() },
+                if i & 2 == 0 { -half.y() } else { half.y() },
+                if i & 4 == 0 { -half.z() } else { half.z() },
+            );
+            *corner = self.origin + self.rot.transform_vec(local);
+        }
+        Some(AABB::from_points(&corners))
+    }
+
+    /// Density of `random`, the sum over the faces the direction passes
+    /// through.
+    fn pdf_value(&self, ray_origin: Vec3, v: Vec3) -> Real {
+        let irot = self.rot.inv();
+        let origin = irot.transform_vec(ray_origin - self.origin);
+        let v = irot.transform_vec(v);
+        let area = self.area();
+        self.planes_cache
+            .iter()
+            .map(|face| face.area() / area * face.pdf_value(origin, v))
+            .sum()
+    }
+
+    /// Direction to a random point on the surface, on a face picked in
+    /// proportion to its area.
+    fn random(&self, ray_origin: Vec3) -> Vec3 {
+        let origin = self.rot.inv().transform_vec(ray_origin - self.origin);
+        let mut u = rand::random::<Real>() * self.area();
+        for face in self.planes_cache.iter() {
+            if u < face.area() {
+                return self.rot.transform_vec(face.random(origin));
+            }
+            u -= face.area();
+        }
+        let last = self.planes_cache.last().unwrap();
+        self.rot.transform_vec(last.random(origin))
+    }
+
+    fn area(&self) -> Real {
+        let s = self.size;
+        2.0 * (s.x() * s.y() + s.y() * s.z() + s.z() * s.x())
+    }
+
+    /// A point on a face picked in proportion to its area.
+    fn random_point(&self) -> Option<Hit<S>> {
+        let mut u = rand::random::<Real>() * self.area();
+        let face = self
+            .planes_cache
+            .iter()
+            .find(|face| {
+                u -= face.area();
+                u < 0.0
+            })
+            .unwrap_or_else(|| self.planes_cache.last().unwrap());
+        let mut rec = face.random_point()?;
+        rec.p = self.origin + self.rot.transform_vec(rec.p);
+        rec.normal = self.rot.transform_vec(rec.normal);
+        rec.material = self.material.clone();
+        Some(rec)
+    }
+
+    fn materials(&self) -> Vec<Arc<dyn Material<S> + Send>> {
+        self.material.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrum::SampledSpectrum;
+
+    /// Texture coordinates where the ray straight down through `(x, y)`
+    /// hits `object`.
+    fn uv_below(object: &dyn Hitable<SampledSpectrum>, x: Real, y: Real) -> (Real, Real) {
+        let hit = object
+            .hit(&Ray::new(Vec3(x, y, 5.0), Vec3(0.0, 0.0, -1.0)))
+            .unwrap();
+        (hit.u, hit.v)
+    }
+
+    fn assert_uv(uv: (Real, Real), expected: (Real, Real)) {
+        assert!(
+            (uv.0 - expected.0).abs() < 1e-4 && (uv.1 - expected.1).abs() < 1e-4,
+            "{:?} != {:?}",
+            uv,
+            expected
+        );
+    }
+
+    #[test]
+    fn plane_uv_spans_the_plane() {
+        let mut plane = Plane::<SampledSpectrum> {
+            width: 4.0,
+            height: 2.0,
+            ..Plane::new()
+        };
+        assert_uv(uv_below(&plane, -1.0, 0.5), (0.25, 0.75));
+        assert_uv(uv_below(&plane, 1.9, -0.9), (0.975, 0.05));
+
+        // A quarter turn around the normal turns the texture with it.
+        plane.rot_around_normal = 0.5 * PI;
+        plane.width = 2.0;
+        plane.height = 4.0;
+        assert_uv(uv_below(&plane, -1.0, 0.5), (0.75, 0.75));
+    }
+
+    #[test]
+    fn sphere_uv_covers_the_sphere() {
+        let sphere = Sphere::<SampledSpectrum> {
+            center: Vec3::ZEROS,
+            radius: 1.0,
+            material: None,
+        };
+        // The pole at the top is v = 1.
+        let top = sphere
+            .hit(&Ray::new(Vec3(0.0, 5.0, 0.0), Vec3(0.0, -1.0, 0.0)))
+            .unwrap();
+        assert!((top.v - 1.0).abs() < 1e-4, "{}", top.v);
+        for (x, y) in [(0.3, 0.2), (-0.5, 0.1), (0.1, -0.7)] {
+            let (u, v) = uv_below(&sphere, x, y);
+            assert!((0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v));
+        }
+        // Going around the equator covers u once.
+        let mut us: Vec<Real> = (0..8)
+            .map(|i| {
+                let phi = PI * i as Real / 4.0;
+                let d = Vec3(phi.cos(), 0.0, phi.sin());
+                sphere.hit(&Ray::new(5.0 * d, -1.0 * d)).unwrap().u
+            })
+            .collect();
+        us.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for pair in us.windows(2) {
+            assert!((pair[1] - pair[0] - 0.125).abs() < 1e-3, "{:?}", us);
+        }
+    }
 }
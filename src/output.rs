@@ -0,0 +1,402 @@
+//! Writing of the rendered image. The image is given as linear RGBA floats,
+//! top row first.
+
+use crate::core::*;
+use crate::spectrum;
+
+use exr::prelude::f16;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// 8-bit, gamma corrected and clamped.
+    Png,
+    /// Linear OpenEXR, with half or full float channels.
+    Exr,
+    /// Linear portable float map.
+    Pfm,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "exr" => Some(ImageFormat::Exr),
+            "pfm" => Some(ImageFormat::Pfm),
+            _ => None,
+        }
+    }
+}
+
+fn other_error<E: std::fmt::Display>(err: E) -> std::io::Error {
+    std::io::Error::other(err.to_string())
+}
+
+/// Add `-suffix` to the file name of `path`, before the extension.
+pub fn add_suffix(path: &Path, suffix: Option<&str>) -> PathBuf {
+    let suffix = match suffix {
+        Some(suffix) => suffix,
+        None => return path.to_path_buf(),
+    };
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push("-");
+    name.push(suffix);
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+/// Write `buffer` to `path`, in the format given by the file extension.
+/// `half` selects 16-bit channels for EXR output.
+pub fn write_image(
+    path: &Path,
+    buffer: &[Real],
+    nx: usize,
+    ny: usize,
+    half: bool,
+) -> std::io::Result<()> {
+    match ImageFormat::from_path(path) {
+        Some(ImageFormat::Png) => write_png(path, buffer, nx, ny),
+        Some(ImageFormat::Exr) => write_exr(path, buffer, nx, ny, half),
+        Some(ImageFormat::Pfm) => write_pfm(path, buffer, nx, ny),
+        None => Err(other_error(format!(
+            "{}: unknown image format, expected .png, .exr or .pfm",
+            path.display()
+        ))),
+    }
+}
+
+pub fn write_png(path: &Path, buffer: &[Real], nx: usize, ny: usize) -> std::io::Result<()> {
+    let mut final_buffer = vec![0_u8; nx * ny * 4];
+    let iter = final_buffer.iter_mut().zip(buffer.iter());
+    for (final_pixel, float_pixel) in iter {
+        let gamma_corrected = spectrum::gamma_correct(*float_pixel).clamp(0.0, 1.0);
+        *final_pixel = (255.99 * gamma_corrected) as u8;
+    }
+    lodepng::encode32_file(path, &final_buffer, nx, ny).map_err(other_error)
+}
+
+pub fn write_exr(
+    path: &Path,
+    buffer: &[Real],
+    nx: usize,
+    ny: usize,
+    half: bool,
+) -> std::io::Result<()> {
+    let pixel = |x: usize, y: usize| {
+        let offset = (y * nx + x) * 4;
+        (
+            buffer[offset],
+            buffer[offset + 1],
+            buffer[offset + 2],
+            buffer[offset + 3],
+        )
+    };
+    let result = if half {
+        exr::prelude::write_rgba_file(path, nx, ny, |x, y| {
+            let (r, g, b, a) = pixel(x, y);
+            (
+                f16::from_f32(r),
+                f16::from_f32(g),
+                f16::from_f32(b),
+                f16::from_f32(a),
+            )
+        })
+    } else {
+        exr::prelude::write_rgba_file(path, nx, ny, pixel)
+    };
+    result.map_err(other_error)
+}
+
+/// Portable float map, stored bottom row first. `Real` is written as is, so
+/// this assumes it is `f32`.
+pub fn write_pfm(path: &Path, buffer: &[Real], nx: usize, ny: usize) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    // A negative scale means little endian.
+    write!(file, "PF\n{} {}\n-1.0\n", nx, ny)?;
+    for y in (0..ny).rev() {
+        for x in 0..nx {
+            let offset = (y * nx + x) * 4;
+            for c in 0..3 {
+                file.write_all(&buffer[offset + c].to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SpectralFormat {
+    /// ENVI header and raw band interleaved by pixel data.
+    Envi,
+    /// OpenEXR with one channel per wavelength, following the spectral
+    /// OpenEXR layout (Fichet et al. 2021).
+    Exr,
+}
+
+impl SpectralFormat {
+    pub fn from_path(path: &Path) -> Option<SpectralFormat> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "hdr" => Some(SpectralFormat::Envi),
+            "exr" => Some(SpectralFormat::Exr),
+            _ => None,
+        }
+    }
+}
+
+/// Write a spectral image cube to `path`. `buffer` holds `lambdas.len()`
+/// values per pixel, top row first, and `lambdas` is the centre wavelength of
+/// every band in nanometers.
+pub fn write_spectral_image(
+    path: &Path,
+    buffer: &[Real],
+    nx: usize,
+    ny: usize,
+    lambdas: &[Real],
+    half: bool,
+) -> std::io::Result<()> {
+    match SpectralFormat::from_path(path) {
+        Some(SpectralFormat::Envi) => write_envi(path, buffer, nx, ny, lambdas),
+        Some(SpectralFormat::Exr) => write_spectral_exr(path, buffer, nx, ny, lambdas, half),
+        None => Err(other_error(format!(
+            "{}: unknown spectral image format, expected .hdr or .exr",
+            path.display()
+        ))),
+    }
+}
+
+/// ENVI image, with the header at `path` and 32-bit little endian floats in
+/// a .raw file next to it.
+pub fn write_envi(
+    path: &Path,
+    buffer: &[Real],
+    nx: usize,
+    ny: usize,
+    lambdas: &[Real],
+) -> std::io::Result<()> {
+    let n_bands = lambdas.len();
+    let step = if n_bands > 1 {
+        lambdas[1] - lambdas[0]
+    } else {
+        0.0
+    };
+    let list = |values: &mut dyn Iterator<Item = Real>| -> String {
+        values
+            .map(|v| format!("{:.3}", v))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let raw_path = path.with_extension("raw");
+    let mut header = BufWriter::new(File::create(path)?);
+    writeln!(header, "ENVI")?;
+    writeln!(header, "description = {{rcpt spectral render}}")?;
+    writeln!(header, "samples = {}", nx)?;
+    writeln!(header, "lines = {}", ny)?;
+    writeln!(header, "bands = {}", n_bands)?;
+    writeln!(header, "header offset = 0")?;
+    writeln!(header, "file type = ENVI Standard")?;
+    // 4 is 32-bit float, byte order 0 is little endian.
+    writeln!(header, "data type = 4")?;
+    writeln!(header, "interleave = bip")?;
+    writeln!(header, "byte order = 0")?;
+    writeln!(header, "wavelength units = Nanometers")?;
+    writeln!(
+        header,
+        "wavelength = {{{}}}",
+        list(&mut lambdas.iter().copied())
+    )?;
+    writeln!(
+        header,
+        "fwhm = {{{}}}",
+        list(&mut lambdas.iter().map(|_| step))
+    )?;
+    header.flush()?;
+
+    let mut data = BufWriter::new(File::create(raw_path)?);
+    for v in buffer[..(nx * ny * n_bands)].iter() {
+        data.write_all(&v.to_le_bytes())?;
+    }
+    data.flush()
+}
+
+/// Spectral OpenEXR, with emissive channels named `S0.<wavelength>nm` where
+/// the decimal separator of the wavelength is a comma.
+pub fn write_spectral_exr(
+    path: &Path,
+    buffer: &[Real],
+    nx: usize,
+    ny: usize,
+    lambdas: &[Real],
+    half: bool,
+) -> std::io::Result<()> {
+    use exr::prelude::*;
+
+    let n_bands = lambdas.len();
+    let channels: SmallVec<[AnyChannel<FlatSamples>; 4]> = lambdas
+        .iter()
+        .enumerate()
+        .map(|(band, lambda)| {
+            let values = (0..(nx * ny)).map(|i| buffer[i * n_bands + band]);
+            let samples = if half {
+                FlatSamples::F16(values.map(f16::from_f32).collect())
+            } else {
+                FlatSamples::F32(values.collect())
+            };
+            let lambda = format!("{:.2}", lambda).replace('.', ",");
+            AnyChannel::new(format!("S0.{}nm", lambda).as_str(), samples)
+        })
+        .collect();
+
+    let mut image = Image::from_channels((nx, ny), AnyChannels::sort(channels));
+    image.attributes.other.insert(
+        Text::from("spectralLayoutVersion"),
+        AttributeValue::Text(Text::from("1.0")),
+    );
+    image.attributes.other.insert(
+        Text::from("emissiveUnits"),
+        AttributeValue::Text(Text::from("W.m^-2.sr^-1")),
+    );
+    image.write().to_file(path).map_err(other_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rcpt-{}-{}", std::process::id(), name))
+    }
+
+    /// A 3 by 2 image where every channel of every pixel differs.
+    fn image() -> (Vec<Real>, usize, usize) {
+        let (nx, ny) = (3, 2);
+        let buffer = (0..(nx * ny * 4)).map(|i| 0.25 * i as Real).collect();
+        (buffer, nx, ny)
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let (buffer, nx, ny) = image();
+        let path = temp_path("round-trip.pfm");
+        write_image(&path, &buffer, nx, ny, false).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&data[..header.len()], header);
+        let values: Vec<Real> = data[header.len()..]
+            .chunks(4)
+            .map(|b| Real::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(values.len(), nx * ny * 3);
+        // The file starts with the bottom row and has no alpha.
+        for (i, v) in values.iter().enumerate() {
+            let (y, x, c) = (ny - 1 - i / (3 * nx), (i / 3) % nx, i % 3);
+            assert_eq!(*v, buffer[(y * nx + x) * 4 + c]);
+        }
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        let (buffer, nx, ny) = image();
+        for half in [false, true] {
+            let path = temp_path(&format!("round-trip-{}.exr", half));
+            write_image(&path, &buffer, nx, ny, half).unwrap();
+            let image = exr::prelude::read_first_rgba_layer_from_file(
+                &path,
+                |resolution, _| vec![0.0; resolution.width() * resolution.height() * 4],
+                move |pixels: &mut Vec<f32>, pos, (r, g, b, a): (f32, f32, f32, f32)| {
+                    let offset = (pos.y() * nx + pos.x()) * 4;
+                    pixels[offset..(offset + 4)].copy_from_slice(&[r, g, b, a]);
+                },
+            )
+            .unwrap();
+            std::fs::remove_file(&path).unwrap();
+            // Quarters up to 5.75 are exact in half floats too.
+            assert_eq!(image.layer_data.channel_data.pixels, buffer);
+        }
+    }
+
+    /// A 2 by 2 cube of 3 bands.
+    fn cube() -> (Vec<Real>, usize, usize, Vec<Real>) {
+        let (nx, ny) = (2, 2);
+        let lambdas = vec![405.5, 500.0, 650.25];
+        let buffer = (0..(nx * ny * 3)).map(|i| 0.5 * i as Real).collect();
+        (buffer, nx, ny, lambdas)
+    }
+
+    #[test]
+    fn envi_cube() {
+        let (buffer, nx, ny, lambdas) = cube();
+        let path = temp_path("cube.hdr");
+        write_spectral_image(&path, &buffer, nx, ny, &lambdas, false).unwrap();
+        let header = std::fs::read_to_string(&path).unwrap();
+        let raw = std::fs::read(path.with_extension("raw")).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("raw")).unwrap();
+
+        assert!(header.starts_with("ENVI\n"));
+        for line in [
+            "samples = 2",
+            "lines = 2",
+            "bands = 3",
+            "interleave = bip",
+            "wavelength = {405.500, 500.000, 650.250}",
+        ] {
+            assert!(
+                header.lines().any(|l| l == line),
+                "no '{}' in\n{}",
+                line,
+                header
+            );
+        }
+        let values: Vec<Real> = raw
+            .chunks(4)
+            .map(|b| Real::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(values, buffer);
+    }
+
+    #[test]
+    fn spectral_exr_cube() {
+        use exr::prelude::*;
+
+        let (buffer, nx, ny, lambdas) = cube();
+        let path = temp_path("cube.exr");
+        write_spectral_image(&path, &buffer, nx, ny, &lambdas, false).unwrap();
+        let image = read_all_flat_layers_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let channels = &image.layer_data[0].channel_data.list;
+        assert_eq!(channels.len(), lambdas.len());
+        for (band, name) in ["S0.405,50nm", "S0.500,00nm", "S0.650,25nm"]
+            .iter()
+            .enumerate()
+        {
+            let channel = channels
+                .iter()
+                .find(|c| c.name == Text::from(*name))
+                .unwrap_or_else(|| panic!("no channel {}", name));
+            let values: Vec<f32> = channel.sample_data.values_as_f32().collect();
+            let expected: Vec<Real> = (0..(nx * ny)).map(|i| buffer[i * 3 + band]).collect();
+            assert_eq!(values, expected);
+        }
+        let attributes = &image.layer_data[0].attributes.other;
+        assert!(attributes.contains_key(&Text::from("spectralLayoutVersion")));
+    }
+
+    #[test]
+    fn unknown_extension_is_an_error() {
+        let (buffer, nx, ny) = image();
+        let path = temp_path("image.bmp");
+        assert!(write_image(&path, &buffer, nx, ny, false).is_err());
+        assert!(!path.exists());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::core::{Real, PI};
+use crate::core::{Real, Spectrum, PI};
 use crate::{Hitable, Quaternion, Vec3};
 use rand::prelude::*;
 
@@ -43,8 +43,8 @@ pub fn random_cosine_direction() -> Vec3 {
     let r2 = rand::random::<Real>();
     let z = (1.0 - r2).sqrt();
     let phi = 2.0 * PI * r1;
-    let x = phi.cos() * 2.0 * (r2.sqrt());
-    let y = phi.sin() * 2.0 * (r2.sqrt());
+    let x = phi.cos() * r2.sqrt();
+    let y = phi.sin() * r2.sqrt();
     Vec3(x, y, z)
 }
 
@@ -52,7 +52,9 @@ pub fn random_to_sphere(radius: Real, distance_squared: Real) -> Vec3 {
     let r1 = rand::random::<Real>();
     let r2 = rand::random::<Real>();
     //let z = 1.0 + r2*((1.0 - radius*radius/(distance_squared + 1e-5)).sqrt() - 1.0);
-    let z = 1.0 + r2 * ((1.0 - radius * radius / (distance_squared)).sqrt() - 1.0);
+    // A point on the sphere sees half of the directions.
+    let cos_theta_max = (1.0 - radius * radius / distance_squared).max(0.0).sqrt();
+    let z = 1.0 + r2 * (cos_theta_max - 1.0);
     let phi = 2.0 * PI * r1;
     let x = phi.cos() * (1.0 - z * z).sqrt();
     let y = phi.sin() * (1.0 - z * z).sqrt();
@@ -84,6 +86,11 @@ impl UVW {
     pub fn local(self, a: Vec3) -> Vec3 {
         return 1.0 * a.0 * self.u + a.1 * self.v + a.2 * self.w;
     }
+
+    /// Inverse of `local`, the coordinates of `a` in the basis.
+    pub fn to_local(self, a: Vec3) -> Vec3 {
+        Vec3(a.dot(self.u), a.dot(self.v), a.dot(self.w))
+    }
 }
 #[inline]
 pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
@@ -109,6 +116,64 @@ pub fn schlick(cosine: Real, ref_idx: Real) -> Real {
     return r0 + (1.0 - r0) * (1.0 - cosine).powi(5);
 }
 
+/// Fresnel reflectance of a conductor with the complex index of refraction
+/// `eta + i k`, relative to the outside, for unpolarised light. `cos_i` is
+/// the cosine of the angle of incidence.
+pub fn fresnel_conductor(cos_i: Real, eta: Real, k: Real) -> Real {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+
+/// Reflectance of a thin film between two dielectrics for unpolarised
+/// light, from the Airy summation of the waves reflected inside the film.
+/// Light arrives from the medium with index `n1` at the angle with cosine
+/// `cos_i`, the film has the index `n2` and is `thickness` nanometers thick,
+/// and the medium behind it has the index `n3`. `lambda` is in nanometers.
+pub fn thin_film_reflectance(
+    cos_i: Real,
+    n1: Real,
+    n2: Real,
+    n3: Real,
+    thickness: Real,
+    lambda: Real,
+) -> Real {
+    let sin2_i = 1.0 - cos_i * cos_i;
+    let sin2_2 = sin2_i * (n1 / n2) * (n1 / n2);
+    let sin2_3 = sin2_i * (n1 / n3) * (n1 / n3);
+    if sin2_2 >= 1.0 || sin2_3 >= 1.0 {
+        // Nothing gets through, whatever the film does.
+        return 1.0;
+    }
+    let cos_2 = (1.0 - sin2_2).sqrt();
+    let cos_3 = (1.0 - sin2_3).sqrt();
+
+    // Phase difference between successive reflections in the film.
+    let delta = 4.0 * PI * n2 * thickness * cos_2 / lambda;
+    let airy = |r12: Real, r23: Real| {
+        let c = 2.0 * r12 * r23 * delta.cos();
+        (r12 * r12 + r23 * r23 + c) / (1.0 + r12 * r12 * r23 * r23 + c)
+    };
+    let rs12 = (n1 * cos_i - n2 * cos_2) / (n1 * cos_i + n2 * cos_2);
+    let rs23 = (n2 * cos_2 - n3 * cos_3) / (n2 * cos_2 + n3 * cos_3);
+    let rp12 = (n2 * cos_i - n1 * cos_2) / (n2 * cos_i + n1 * cos_2);
+    let rp23 = (n3 * cos_2 - n2 * cos_3) / (n3 * cos_2 + n2 * cos_3);
+    0.5 * (airy(rs12, rs23) + airy(rp12, rp23))
+}
+
 pub trait PDF {
     fn value(&self, direction: Vec3) -> Real;
     fn generate(&self) -> Vec3;
@@ -142,16 +207,16 @@ impl PDF for CosinePDF {
     }
 }
 
-pub struct HitablePDF<'a> {
+pub struct HitablePDF<'a, S: Spectrum> {
     pub origin: Vec3,
-    pub hitable: &'a dyn Hitable,
+    pub hitable: &'a dyn Hitable<S>,
 }
-impl<'a> HitablePDF<'a> {
-    pub fn new(hitable: &'a dyn Hitable, origin: Vec3) -> HitablePDF {
+impl<'a, S: Spectrum> HitablePDF<'a, S> {
+    pub fn new(hitable: &'a dyn Hitable<S>, origin: Vec3) -> HitablePDF<'a, S> {
         HitablePDF { origin, hitable }
     }
 }
-impl<'a> PDF for HitablePDF<'a> {
+impl<'a, S: Spectrum> PDF for HitablePDF<'a, S> {
     fn value(&self, direction: Vec3) -> Real {
         return self.hitable.pdf_value(self.origin, direction);
     }
@@ -182,3 +247,42 @@ impl<'a> PDF for MixturePDF<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reflectance at normal incidence of the interface from `n1` to `n2`.
+    fn bare(n1: Real, n2: Real) -> Real {
+        ((n1 - n2) / (n1 + n2)).powi(2)
+    }
+
+    #[test]
+    fn thin_film_limits() {
+        // Without a film, or with a film of half a wavelength, only the
+        // interface to the substrate reflects.
+        let r = thin_film_reflectance(1.0, 1.0, 1.33, 1.5, 0.0, 550.0);
+        assert!((r - bare(1.0, 1.5)).abs() < 1e-5, "{}", r);
+        let half_wave = 550.0 / (2.0 * 1.33);
+        let r = thin_film_reflectance(1.0, 1.0, 1.33, 1.5, half_wave, 550.0);
+        assert!((r - bare(1.0, 1.5)).abs() < 1e-5, "{}", r);
+    }
+
+    #[test]
+    fn quarter_wave_coating_does_not_reflect() {
+        let n2 = Real::sqrt(1.5);
+        let quarter_wave = 550.0 / (4.0 * n2);
+        let r = thin_film_reflectance(1.0, 1.0, n2, 1.5, quarter_wave, 550.0);
+        assert!(r < 1e-5, "{}", r);
+        // Away from its wavelength the coating reflects again.
+        assert!(thin_film_reflectance(1.0, 1.0, n2, 1.5, quarter_wave, 400.0) > 1e-3);
+    }
+
+    #[test]
+    fn thin_film_total_internal_reflection() {
+        assert_eq!(
+            thin_film_reflectance(0.3, 1.5, 1.33, 1.0, 100.0, 550.0),
+            1.0
+        );
+    }
+}
@@ -0,0 +1,101 @@
+// see sampling.h
+
+use crate::core::*;
+
+/// Piecewise constant distribution over `func.len()` values, for picking
+/// one of them in proportion to its value.
+#[derive(Clone, Debug)]
+pub struct Distribution1D {
+    pub func: Vec<Real>,
+    /// Cumulative distribution, `func.len() + 1` values from 0 to 1.
+    pub cdf: Vec<Real>,
+    /// Sum of `func`.
+    pub func_sum: Real,
+}
+
+impl Distribution1D {
+    pub fn new(func: &[Real]) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].max(0.0);
+        }
+        let func_sum = cdf[n];
+        if func_sum > 0.0 {
+            cdf.iter_mut().for_each(|c| *c /= func_sum);
+        } else {
+            // Nothing to prefer, fall back to picking uniformly.
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as Real / n as Real;
+            }
+        }
+        Distribution1D {
+            func: func.to_vec(),
+            cdf,
+            func_sum,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Pick an index for the uniform sample `u`. Returns the index, its
+    /// probability and `u` remapped to [0, 1) within the picked index.
+    pub fn sample_discrete(&self, u: Real) -> (usize, Real, Real) {
+        let offset = find_interval(self.cdf.len() as i32, |i| self.cdf[i as usize] <= u) as usize;
+        let pdf = self.discrete_pdf(offset);
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let u_remapped = if width > 0.0 {
+            ((u - self.cdf[offset]) / width).clamp(0.0, 1.0 - EPS)
+        } else {
+            0.0
+        };
+        (offset, pdf, u_remapped)
+    }
+
+    /// Probability of picking index `i`.
+    pub fn discrete_pdf(&self, i: usize) -> Real {
+        self.cdf[i + 1] - self.cdf[i]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_in_proportion_to_the_values() {
+        let d = Distribution1D::new(&[1.0, 0.0, 3.0, 4.0]);
+        assert_eq!(d.func_sum, 8.0);
+        let expected = [0.125, 0.0, 0.375, 0.5];
+        let n = 8000;
+        let mut counts = [0; 4];
+        for k in 0..n {
+            let u = (k as Real + 0.5) / n as Real;
+            let (i, pdf, u_remapped) = d.sample_discrete(u);
+            assert_eq!(pdf, expected[i]);
+            assert!((0.0..1.0).contains(&u_remapped));
+            counts[i] += 1;
+        }
+        for (count, p) in counts.iter().zip(expected.iter()) {
+            assert!((*count as Real / n as Real - p).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn remaps_the_sample_within_the_pick() {
+        let d = Distribution1D::new(&[1.0, 1.0]);
+        let (i, _, u) = d.sample_discrete(0.75);
+        assert_eq!(i, 1);
+        assert!((u - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn zero_values_pick_uniformly() {
+        let d = Distribution1D::new(&[0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(d.func_sum, 0.0);
+        assert_eq!(d.sample_discrete(0.6).0, 2);
+        assert_eq!(d.discrete_pdf(1), 0.25);
+    }
+}
@@ -0,0 +1,1194 @@
+//! Loading of scenes from TOML scene description files.
+//!
+//! A scene file has a `[camera]` table, optional named `[spectra.<name>]`,
+//! named `[materials.<name>]` and a list of `[[objects]]` that refer to the
+//! materials by name. Wherever a spectrum is expected it can be given as a
+//! constant, the name of a spectrum (user defined or one of the built in
+//! illuminants `D50`, `D65` and `halogen`) or an inline table:
+//!
+//! ```toml
+//! [camera]
+//! lookfrom = [278.0, 278.0, -800.0]
+//! lookat = [278.0, 278.0, 0.0]
+//! fov = 40.0
+//! exposure = 1.0
+//!
+//! [spectra.white]
+//! lambda = [400.0, 700.0]
+//! values = [0.73, 0.73]
+//!
+//! [materials.light]
+//! type = "lambertian"
+//! emit = { spectrum = "D65", scale = 0.1 }
+//!
+//! [materials.white]
+//! type = "lambertian"
+//! albedo = "white"
+//!
+//! [[objects]]
+//! type = "plane"
+//! origin = [278.0, 554.0, 279.5]
+//! normal = [0.0, -1.0, 0.0]
+//! width = 130.0
+//! height = 105.0
+//! material = "light"
+//! ```
+//!
+//! A spectrum can also be a colour, see [`SpectrumTable`]:
+//!
+//! ```toml
+//! [spectra.red]
+//! srgb = [0.8, 0.1, 0.1]
+//! ```
+//!
+//! Triangle meshes are loaded from OBJ or PLY files with an object of type
+//! `mesh`, see [`ObjectDesc::Mesh`].
+//!
+//! The albedo, emission and transmittance of materials, their roughness and
+//! the thickness of thin films can be textures instead, see [`TextureDesc`].
+//! Numbers and spectra in a texture are given as for the material:
+//!
+//! ```toml
+//! [materials.floor]
+//! type = "lambertian"
+//! albedo = { texture = "checker", odd = "white", even = 0.1, scale = 8.0 }
+//!
+//! [materials.brushed]
+//! type = "metal"
+//! metal = "gold"
+//! roughness = { texture = "noise", low = 0.0, high = 0.3, scale = 0.05 }
+//! ```
+//!
+//! Fluorescent materials of type `lambertian_rerad` take their re-radiation
+//! either from a list of Gaussians or from a measured matrix file:
+//!
+//! ```toml
+//! [materials.paper]
+//! type = "lambertian_rerad"
+//! donaldson = { path = "paper.csv", unit = "nm" }
+//! ```
+//!
+//! Rough diffuse materials of type `oren_nayar` take the standard deviation
+//! `sigma` of the facet slopes in degrees, and either an `albedo` or a
+//! re-radiation given like for `lambertian_rerad`:
+//!
+//! ```toml
+//! [materials.cloth]
+//! type = "oren_nayar"
+//! sigma = 20.0
+//! donaldson = { path = "cloth.csv", unit = "nm" }
+//! ```
+//!
+//! Metals of type `metal` use the index of refraction of `gold`, `silver`,
+//! `copper` or `aluminium`, or spectra given as `eta` and `k`:
+//!
+//! ```toml
+//! [materials.gilding]
+//! type = "metal"
+//! metal = "gold"
+//! roughness = 0.05
+//! ```
+//!
+//! Transparent materials of type `dielectric` take the index of refraction
+//! of a `glass` (`bk7`, `fused_silica` or `water`), a constant `ior`, or the
+//! coefficients of the `cauchy` or `sellmeier` equation:
+//!
+//! ```toml
+//! [materials.prism]
+//! type = "dielectric"
+//! glass = "bk7"
+//! ```
+//!
+//! A `layered` material puts a clear coat over the material named `base`,
+//! for example a glossy binder over a fluorescent pigment:
+//!
+//! ```toml
+//! [materials.highlighter]
+//! type = "layered"
+//! base = "paper"
+//! ior = 1.5
+//! transmittance = 0.95
+//! ```
+//!
+//! Soap bubbles and coated lenses use a `thin_film`, with the `thickness`
+//! of the film in nanometers and the index of refraction of the film and of
+//! the substrate under it, 1 by default:
+//!
+//! ```toml
+//! [materials.bubble]
+//! type = "thin_film"
+//! thickness = 400.0
+//! film_ior = 1.33
+//! ```
+//!
+//! Rough surfaces use microfacets with a `ggx` or `beckmann` distribution,
+//! either reflecting only (`microfacet_reflection`, a glossy coating) or
+//! reflecting and transmitting (`microfacet_transmission`, frosted glass):
+//!
+//! ```toml
+//! [materials.holder]
+//! type = "microfacet_reflection"
+//! albedo = 0.9
+//! distribution = "ggx"
+//! roughness = 0.3
+//! ior = 1.5
+//! ```
+
+use crate::camera::Camera;
+use crate::core::*;
+use crate::dispersion::{Dispersion, GlassPreset};
+use crate::donaldson::{self, WavelengthUnit};
+use crate::hitable::{HitList, Hitable};
+use crate::materials::{
+    Dielectric, GaussRecord, Lambertian, LambertianReRad, Layered, Material, Metal,
+    MicrofacetReflection, MicrofacetTransmission, OrenNayar, ReRadiation, ThinFilm,
+};
+use crate::mesh_io;
+use crate::metals::MetalPreset;
+use crate::microfacet::Distribution;
+use crate::model::{Cuboid, Plane, Sphere};
+use crate::spectrum::{self, SpectrumType};
+use crate::texture::{CheckerTexture, ImageTexture, NoiseTexture, Texture, TextureValue};
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(err) => write!(f, "could not read scene file: {}", err),
+            SceneError::Parse(err) => write!(f, "malformed scene file: {}", err),
+            SceneError::Invalid(msg) => write!(f, "invalid scene: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+fn invalid<T>(msg: String) -> Result<T, SceneError> {
+    Err(SceneError::Invalid(msg))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    camera: CameraDesc,
+    #[serde(default)]
+    spectra: BTreeMap<String, SpectrumTable>,
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDesc>,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    lookfrom: [Real; 3],
+    lookat: [Real; 3],
+    #[serde(default = "default_up")]
+    up: [Real; 3],
+    fov: Real,
+    #[serde(default)]
+    aperture: Real,
+    focus_dist: Option<Real>,
+    #[serde(default = "default_one")]
+    exposure: Real,
+}
+
+fn default_up() -> [Real; 3] {
+    [0.0, 1.0, 0.0]
+}
+
+fn default_one() -> Real {
+    1.0
+}
+
+fn default_ior() -> Real {
+    1.5
+}
+
+/// A spectrum is given either as a number, a name or a [`SpectrumTable`].
+/// It is kept as a raw value so that we can give a sensible error message.
+type SpectrumDesc = toml::Value;
+
+/// A number, or a [`TextureDesc`] giving numbers.
+type ScalarDesc = toml::Value;
+
+/// A texture is a table with the kind of texture in `texture`. It can be
+/// given wherever a material takes a texture instead of a spectrum or a
+/// number.
+#[derive(Deserialize)]
+#[serde(tag = "texture", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    /// Squares of the textures `odd` and `even`, `scale` squares along each
+    /// texture coordinate.
+    Checker {
+        odd: toml::Value,
+        even: toml::Value,
+        #[serde(default = "default_one")]
+        scale: Real,
+    },
+    /// An sRGB PNG file, relative to the scene file.
+    Image { path: String },
+    /// Noise in space between `low` and `high`, with `scale` features per
+    /// unit length and `octaves` layers of finer detail.
+    Noise {
+        low: toml::Value,
+        high: toml::Value,
+        #[serde(default = "default_one")]
+        scale: Real,
+        #[serde(default = "default_octaves")]
+        octaves: usize,
+    },
+}
+
+fn default_octaves() -> usize {
+    4
+}
+
+/// Either sampled values, a reference to another spectrum or a colour,
+/// optionally scaled. Colours are given as linear `rgb` or gamma encoded
+/// `srgb`, with components between 0 and 1 for a reflectance, and are
+/// turned into smooth spectra. As an emission they are light with the
+/// white of D65.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpectrumTable {
+    lambda: Option<Vec<Real>>,
+    values: Option<Vec<Real>>,
+    spectrum: Option<String>,
+    rgb: Option<[Real; 3]>,
+    srgb: Option<[Real; 3]>,
+    scale: Option<Real>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian {
+        albedo: Option<SpectrumDesc>,
+        emit: Option<SpectrumDesc>,
+    },
+    /// Re-radiation given either as a list of Gaussians in `rerad` or as a
+    /// measured matrix file in `donaldson`.
+    LambertianRerad {
+        emit: Option<SpectrumDesc>,
+        #[serde(default)]
+        rerad: Vec<GaussRecordDesc>,
+        donaldson: Option<DonaldsonDesc>,
+    },
+    /// Rough diffuse reflection with the facet slope deviation `sigma` in
+    /// degrees, reflecting with `albedo` or re-radiating like
+    /// `lambertian_rerad`.
+    OrenNayar {
+        albedo: Option<SpectrumDesc>,
+        emit: Option<SpectrumDesc>,
+        sigma: Real,
+        #[serde(default)]
+        rerad: Vec<GaussRecordDesc>,
+        donaldson: Option<DonaldsonDesc>,
+    },
+    /// A conductor, either one of the built in `metal`s or with the complex
+    /// index of refraction given as the spectra `eta` and `k`.
+    Metal {
+        metal: Option<MetalPreset>,
+        eta: Option<SpectrumDesc>,
+        k: Option<SpectrumDesc>,
+        roughness: Option<ScalarDesc>,
+    },
+    /// Glass or another transparent material, with the index of refraction
+    /// given by one of `glass`, `ior`, `cauchy` or `sellmeier`.
+    Dielectric {
+        glass: Option<GlassPreset>,
+        ior: Option<Real>,
+        /// Coefficients a, b and optionally c of Cauchy's equation.
+        cauchy: Option<Vec<Real>>,
+        sellmeier: Option<SellmeierDesc>,
+    },
+    /// A clear coat with the index of refraction `ior` over the material
+    /// named `base`, tinted by `transmittance` on every pass through it.
+    Layered {
+        base: String,
+        #[serde(default = "default_ior")]
+        ior: Real,
+        transmittance: Option<SpectrumDesc>,
+    },
+    /// A film `thickness` nanometers thick with the index of refraction
+    /// `film_ior`, on a substrate with `substrate_ior`.
+    ThinFilm {
+        thickness: ScalarDesc,
+        film_ior: Real,
+        #[serde(default = "default_one")]
+        substrate_ior: Real,
+    },
+    /// Glossy reflection with the microfacet `distribution` `ggx` or
+    /// `beckmann`, reflecting with the Fresnel reflectance for `ior`.
+    MicrofacetReflection {
+        albedo: Option<SpectrumDesc>,
+        #[serde(default)]
+        distribution: Distribution,
+        roughness: ScalarDesc,
+        #[serde(default = "default_ior")]
+        ior: Real,
+    },
+    /// A rough dielectric with the index of refraction `ior`.
+    MicrofacetTransmission {
+        transmittance: Option<SpectrumDesc>,
+        #[serde(default)]
+        distribution: Distribution,
+        roughness: ScalarDesc,
+        #[serde(default = "default_ior")]
+        ior: Real,
+    },
+}
+
+/// Coefficients of the Sellmeier equation, see [`Dispersion::Sellmeier`].
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SellmeierDesc {
+    b: [Real; 3],
+    c: [Real; 3],
+}
+
+/// A measured excitation–emission matrix, see `donaldson.rs` for the file
+/// format. The path is relative to the scene file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DonaldsonDesc {
+    path: String,
+    #[serde(default)]
+    unit: WavelengthUnit,
+    /// The file has the excitation wavelengths in the first row.
+    #[serde(default)]
+    transpose: bool,
+    #[serde(default = "default_one")]
+    scale: Real,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GaussRecordDesc {
+    lambda_in: Real,
+    lambda_out: Real,
+    amplitude_out: Real,
+    sigma_in: Real,
+    sigma_out: Real,
+    #[serde(default = "default_one")]
+    scale: Real,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ObjectDesc {
+    Sphere {
+        center: [Real; 3],
+        radius: Real,
+        material: Option<String>,
+    },
+    Plane {
+        origin: [Real; 3],
+        normal: [Real; 3],
+        /// Rotation of the plane and its texture around the normal, in
+        /// degrees.
+        #[serde(default)]
+        rot_around_normal: Real,
+        width: Real,
+        height: Real,
+        material: Option<String>,
+    },
+    Cuboid {
+        origin: [Real; 3],
+        size: [Real; 3],
+        /// Euler angles (roll, pitch, yaw) in degrees.
+        #[serde(default)]
+        rotation: [Real; 3],
+        material: Option<String>,
+    },
+    /// An OBJ or PLY file, relative to the scene file. Materials from an OBJ
+    /// file are used unless `material` is given.
+    Mesh {
+        path: String,
+        #[serde(default)]
+        translate: [Real; 3],
+        #[serde(default = "default_scale")]
+        scale: [Real; 3],
+        #[serde(default)]
+        rotation: [Real; 3],
+        material: Option<String>,
+    },
+}
+
+fn default_scale() -> [Real; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn vec3(v: [Real; 3]) -> Vec3 {
+    Vec3(v[0], v[1], v[2])
+}
+
+/// Load a scene description file, returning the world and a camera set up
+/// for the given image aspect ratio.
+pub fn load_scene<S: Spectrum>(
+    path: &Path,
+    aspect: Real,
+) -> Result<(HitList<S>, Camera), SceneError> {
+    let text = std::fs::read_to_string(path).map_err(SceneError::Io)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_scene(&text, base_dir, aspect)
+}
+
+/// Parse a scene description, files it refers to are relative to `base_dir`.
+pub fn parse_scene<S: Spectrum>(
+    text: &str,
+    base_dir: &Path,
+    aspect: Real,
+) -> Result<(HitList<S>, Camera), SceneError> {
+    let desc: SceneDesc = toml::from_str(text).map_err(SceneError::Parse)?;
+
+    let camera = build_camera(&desc.camera, aspect)?;
+
+    // Layered materials are built after their base.
+    let mut materials: BTreeMap<&str, Arc<dyn Material<S> + Send>> = BTreeMap::new();
+    let mut pending: Vec<(&String, &MaterialDesc)> = desc.materials.iter().collect();
+    while !pending.is_empty() {
+        let mut waiting = Vec::new();
+        for (name, mat) in pending.iter() {
+            if let MaterialDesc::Layered { base, .. } = mat {
+                if desc.materials.contains_key(base) && !materials.contains_key(base.as_str()) {
+                    waiting.push((*name, *mat));
+                    continue;
+                }
+            }
+            let material = build_material(name, mat, &desc.spectra, &materials, base_dir)
+                .map_err(|err| SceneError::Invalid(format!("material '{}': {}", name, msg(err))))?;
+            materials.insert(name, material);
+        }
+        if waiting.len() == pending.len() {
+            return invalid(format!(
+                "material '{}': its base material refers back to it",
+                waiting[0].0
+            ));
+        }
+        pending = waiting;
+    }
+
+    let mut world: HitList<S> = Vec::with_capacity(desc.objects.len());
+    for (i, obj) in desc.objects.iter().enumerate() {
+        let object = build_object(obj, &materials, base_dir)
+            .map_err(|err| SceneError::Invalid(format!("object {}: {}", i, msg(err))))?;
+        world.push(object);
+    }
+
+    Ok((world, camera))
+}
+
+fn msg(err: SceneError) -> String {
+    match err {
+        SceneError::Invalid(msg) => msg,
+        SceneError::Parse(err) => err.message().trim().to_string(),
+        err => err.to_string(),
+    }
+}
+
+fn build_camera(desc: &CameraDesc, aspect: Real) -> Result<Camera, SceneError> {
+    let lookfrom = vec3(desc.lookfrom);
+    let lookat = vec3(desc.lookat);
+    if (lookfrom - lookat).length() <= EPS {
+        return invalid("camera: 'lookfrom' and 'lookat' must differ".to_string());
+    }
+    if desc.fov <= 0.0 || desc.fov >= 180.0 {
+        return invalid(format!(
+            "camera: 'fov' must be between 0 and 180 degrees, got {}",
+            desc.fov
+        ));
+    }
+    let focus_dist = desc.focus_dist.unwrap_or((lookfrom - lookat).length());
+    let mut camera = Camera::new(
+        lookfrom,
+        lookat,
+        vec3(desc.up),
+        desc.fov,
+        aspect,
+        desc.aperture,
+        focus_dist,
+    );
+    camera.exposure = desc.exposure;
+    Ok(camera)
+}
+
+fn builtin_spectrum<S: Spectrum>(name: &str) -> Option<S> {
+    match name.to_lowercase().as_str() {
+        "d50" => Some(spectrum::illumination_d50()),
+        "d65" => Some(spectrum::illumination_d65()),
+        "halogen" => Some(spectrum::illumination_halogen()),
+        _ => None,
+    }
+}
+
+fn named_spectrum<S: Spectrum>(
+    name: &str,
+    spectra: &BTreeMap<String, SpectrumTable>,
+    depth: usize,
+    kind: SpectrumType,
+) -> Result<S, SceneError> {
+    if let Some(table) = spectra.get(name) {
+        if depth > spectra.len() {
+            return invalid(format!("spectrum '{}' refers to itself", name));
+        }
+        return build_spectrum_table(table, spectra, depth + 1, kind)
+            .map_err(|err| SceneError::Invalid(format!("spectrum '{}': {}", name, msg(err))));
+    }
+    match builtin_spectrum(name) {
+        Some(s) => Ok(s),
+        None => invalid(format!(
+            "unknown spectrum '{}', expected one defined in [spectra] or one of D50, D65, halogen",
+            name
+        )),
+    }
+}
+
+/// Spectrum of a table, a colour in it stands for a spectrum of type `kind`.
+fn build_spectrum_table<S: Spectrum>(
+    table: &SpectrumTable,
+    spectra: &BTreeMap<String, SpectrumTable>,
+    depth: usize,
+    kind: SpectrumType,
+) -> Result<S, SceneError> {
+    let colour = match (&table.rgb, &table.srgb) {
+        (Some(rgb), None) => Some(*rgb),
+        (None, Some(srgb)) => Some(srgb.map(spectrum::inverse_gamma_convert_float)),
+        (None, None) => None,
+        _ => return invalid("give either 'rgb' or 'srgb', not both".to_string()),
+    };
+    let s =
+        match (&table.lambda, &table.values, &table.spectrum, colour) {
+            (Some(lambda), Some(values), None, None) => {
+                if lambda.len() != values.len() {
+                    return invalid(format!(
+                        "'lambda' has {} entries but 'values' has {}",
+                        lambda.len(),
+                        values.len()
+                    ));
+                }
+                if lambda.is_empty() {
+                    return invalid("'lambda' and 'values' must not be empty".to_string());
+                }
+                if lambda.windows(2).any(|w| w[0] >= w[1]) {
+                    return invalid("'lambda' must be strictly increasing".to_string());
+                }
+                S::from_sampled(lambda, values, lambda.len())
+            }
+            (None, None, Some(name), None) => named_spectrum(name, spectra, depth, kind)?,
+            (None, None, None, Some(rgb)) => {
+                if rgb.iter().any(|c| *c < 0.0 || c.is_nan()) {
+                    return invalid(format!(
+                        "colour components must not be negative, got {:?}",
+                        rgb
+                    ));
+                }
+                S::from_rgb_typed(&rgb, kind)
+            }
+            _ => return invalid(
+                "a spectrum table needs either 'lambda' and 'values', 'spectrum', 'rgb' or 'srgb'"
+                    .to_string(),
+            ),
+        };
+    Ok(s * table.scale.unwrap_or(1.0))
+}
+
+fn build_spectrum<S: Spectrum>(
+    desc: &Option<SpectrumDesc>,
+    spectra: &BTreeMap<String, SpectrumTable>,
+    kind: SpectrumType,
+) -> Result<S, SceneError> {
+    match desc {
+        None => Ok(S::default()),
+        Some(toml::Value::Float(v)) => Ok(S::new(*v as Real)),
+        Some(toml::Value::Integer(v)) => Ok(S::new(*v as Real)),
+        Some(toml::Value::String(name)) => named_spectrum(name, spectra, 0, kind),
+        Some(value @ toml::Value::Table(_)) => {
+            let table: SpectrumTable = value.clone().try_into().map_err(SceneError::Parse)?;
+            build_spectrum_table(&table, spectra, 0, kind)
+        }
+        Some(value) => invalid(format!(
+            "expected a spectrum as a number, a name or a table, got {} '{}'",
+            value.type_str(),
+            value
+        )),
+    }
+}
+
+/// A texture, or a constant built by `constant` from anything else. The
+/// colours of images stand for spectra of type `kind`.
+fn build_texture<T: TextureValue>(
+    desc: &toml::Value,
+    base_dir: &Path,
+    kind: SpectrumType,
+    constant: &dyn Fn(&toml::Value) -> Result<T, SceneError>,
+) -> Result<Arc<dyn Texture<T>>, SceneError> {
+    let is_texture = match desc {
+        toml::Value::Table(table) => table.contains_key("texture"),
+        _ => false,
+    };
+    if !is_texture {
+        return Ok(Arc::new(constant(desc)?));
+    }
+    let texture: TextureDesc = desc.clone().try_into().map_err(SceneError::Parse)?;
+    match texture {
+        TextureDesc::Checker { odd, even, scale } => Ok(Arc::new(CheckerTexture {
+            odd: build_texture(&odd, base_dir, kind, constant)?,
+            even: build_texture(&even, base_dir, kind, constant)?,
+            scale,
+        })),
+        TextureDesc::Image { path } => {
+            let full_path = base_dir.join(&path);
+            let image = ImageTexture::load(&full_path, kind).map_err(|err| {
+                SceneError::Invalid(format!("image '{}': {}", full_path.display(), err))
+            })?;
+            Ok(Arc::new(image))
+        }
+        TextureDesc::Noise {
+            low,
+            high,
+            scale,
+            octaves,
+        } => Ok(Arc::new(NoiseTexture {
+            low: constant(&low)?,
+            high: constant(&high)?,
+            scale,
+            octaves,
+        })),
+    }
+}
+
+/// A spectrum or a texture of spectra of type `kind`, black if it is not
+/// given.
+fn build_spectrum_texture<S: Spectrum>(
+    desc: &Option<SpectrumDesc>,
+    spectra: &BTreeMap<String, SpectrumTable>,
+    base_dir: &Path,
+    kind: SpectrumType,
+) -> Result<Arc<dyn Texture<S>>, SceneError> {
+    match desc {
+        None => Ok(Arc::new(S::default())),
+        Some(desc) => build_texture(desc, base_dir, kind, &|value| {
+            build_spectrum(&Some(value.clone()), spectra, kind)
+        }),
+    }
+}
+
+/// A number or a texture of numbers. `check` is called on every number.
+fn build_scalar_texture(
+    desc: &ScalarDesc,
+    base_dir: &Path,
+    check: &dyn Fn(Real) -> Result<(), SceneError>,
+) -> Result<Arc<dyn Texture<Real>>, SceneError> {
+    build_texture(desc, base_dir, SpectrumType::Unbounded, &|value| {
+        let v = match value {
+            toml::Value::Float(v) => *v as Real,
+            toml::Value::Integer(v) => *v as Real,
+            value => {
+                return invalid(format!(
+                    "expected a number or a texture, got {} '{}'",
+                    value.type_str(),
+                    value
+                ))
+            }
+        };
+        check(v)?;
+        Ok(v)
+    })
+}
+
+fn build_material<S: Spectrum>(
+    name: &str,
+    desc: &MaterialDesc,
+    spectra: &BTreeMap<String, SpectrumTable>,
+    materials: &BTreeMap<&str, Arc<dyn Material<S> + Send>>,
+    base_dir: &Path,
+) -> Result<Arc<dyn Material<S> + Send>, SceneError> {
+    match desc {
+        MaterialDesc::Lambertian { albedo, emit } => Ok(Arc::new(Lambertian {
+            albedo: build_spectrum_texture(albedo, spectra, base_dir, SpectrumType::Reflectance)?,
+            emit: build_spectrum_texture(emit, spectra, base_dir, SpectrumType::Illuminant)?,
+        })),
+        MaterialDesc::LambertianRerad {
+            emit,
+            rerad,
+            donaldson,
+        } => Ok(Arc::new(LambertianReRad {
+            emit: build_spectrum_texture(emit, spectra, base_dir, SpectrumType::Illuminant)?,
+            rerad: build_rerad(rerad, donaldson, base_dir)?.named(name),
+        })),
+        MaterialDesc::OrenNayar {
+            albedo,
+            emit,
+            sigma,
+            rerad,
+            donaldson,
+        } => {
+            if *sigma < 0.0 || *sigma >= 90.0 {
+                return invalid(format!("sigma must be in [0, 90) degrees, got {}", sigma));
+            }
+            let material = OrenNayar::new(
+                build_spectrum_texture(albedo, spectra, base_dir, SpectrumType::Reflectance)?,
+                build_spectrum_texture(emit, spectra, base_dir, SpectrumType::Illuminant)?,
+                *sigma,
+            );
+            if rerad.is_empty() && donaldson.is_none() {
+                return Ok(Arc::new(material));
+            }
+            if albedo.is_some() {
+                return invalid("give either 'albedo' or a re-radiation, not both".to_string());
+            }
+            let rerad = build_rerad(rerad, donaldson, base_dir)?.named(name);
+            Ok(Arc::new(material.with_rerad(rerad)))
+        }
+        MaterialDesc::Metal {
+            metal,
+            eta,
+            k,
+            roughness,
+        } => {
+            let roughness = build_roughness(roughness, base_dir)?;
+            match (metal, eta, k) {
+                (Some(metal), None, None) => Ok(Arc::new(Metal::from_preset(*metal, roughness))),
+                (None, Some(_), Some(_)) => Ok(Arc::new(Metal {
+                    eta: build_spectrum(eta, spectra, SpectrumType::Unbounded)?,
+                    k: build_spectrum(k, spectra, SpectrumType::Unbounded)?,
+                    roughness,
+                })),
+                _ => invalid("give either 'metal' or both 'eta' and 'k'".to_string()),
+            }
+        }
+        MaterialDesc::Dielectric {
+            glass,
+            ior,
+            cauchy,
+            sellmeier,
+        } => {
+            let dispersion = match (glass, ior, cauchy, sellmeier) {
+                (Some(glass), None, None, None) => glass.dispersion(),
+                (None, Some(ior), None, None) => Dispersion::Constant(*ior),
+                (None, None, Some(cauchy), None) => match cauchy[..] {
+                    [a, b] => Dispersion::Cauchy { a, b, c: 0.0 },
+                    [a, b, c] => Dispersion::Cauchy { a, b, c },
+                    _ => return invalid("'cauchy' takes two or three coefficients".to_string()),
+                },
+                (None, None, None, Some(s)) => Dispersion::Sellmeier { b: s.b, c: s.c },
+                _ => {
+                    return invalid(
+                        "give one of 'glass', 'ior', 'cauchy' or 'sellmeier'".to_string(),
+                    )
+                }
+            };
+            let grid = spectrum::spectral_grid();
+            for lambda in [grid.lambda_start, grid.lambda_end] {
+                let n = dispersion.ior(lambda);
+                if !n.is_finite() || n <= 0.0 {
+                    return invalid(format!("the index of refraction is {} at {} nm", n, lambda));
+                }
+            }
+            Ok(Arc::new(Dielectric { dispersion }))
+        }
+        MaterialDesc::Layered {
+            base,
+            ior,
+            transmittance,
+        } => {
+            if *ior <= 0.0 || ior.is_nan() {
+                return invalid(format!("ior must be positive, got {}", ior));
+            }
+            let base = match materials.get(base.as_str()) {
+                Some(m) => m.clone(),
+                None => return invalid(format!("unknown base material '{}'", base)),
+            };
+            let transmittance = match transmittance {
+                Some(_) => build_spectrum(transmittance, spectra, SpectrumType::Reflectance)?,
+                None => S::new(1.0),
+            };
+            Ok(Arc::new(Layered {
+                base,
+                ior: *ior,
+                transmittance,
+            }))
+        }
+        MaterialDesc::ThinFilm {
+            thickness,
+            film_ior,
+            substrate_ior,
+        } => {
+            let thickness = build_scalar_texture(thickness, base_dir, &|t| {
+                if t < 0.0 || t.is_nan() {
+                    return invalid(format!("thickness must not be negative, got {}", t));
+                }
+                Ok(())
+            })?;
+            if *film_ior <= 0.0
+                || *substrate_ior <= 0.0
+                || film_ior.is_nan()
+                || substrate_ior.is_nan()
+            {
+                return invalid("'film_ior' and 'substrate_ior' must be positive".to_string());
+            }
+            Ok(Arc::new(ThinFilm {
+                thickness,
+                film_ior: *film_ior,
+                substrate_ior: *substrate_ior,
+            }))
+        }
+        MaterialDesc::MicrofacetReflection {
+            albedo,
+            distribution,
+            roughness,
+            ior,
+        } => {
+            let albedo = match albedo {
+                Some(_) => {
+                    build_spectrum_texture(albedo, spectra, base_dir, SpectrumType::Reflectance)?
+                }
+                None => Arc::new(S::new(1.0)),
+            };
+            Ok(Arc::new(MicrofacetReflection {
+                albedo,
+                distribution: *distribution,
+                roughness: build_microfacet_roughness(roughness, *ior, base_dir)?,
+                eta: *ior,
+            }))
+        }
+        MaterialDesc::MicrofacetTransmission {
+            transmittance,
+            distribution,
+            roughness,
+            ior,
+        } => {
+            let transmittance = match transmittance {
+                Some(_) => build_spectrum_texture(
+                    transmittance,
+                    spectra,
+                    base_dir,
+                    SpectrumType::Reflectance,
+                )?,
+                None => Arc::new(S::new(1.0)),
+            };
+            Ok(Arc::new(MicrofacetTransmission {
+                transmittance,
+                distribution: *distribution,
+                roughness: build_microfacet_roughness(roughness, *ior, base_dir)?,
+                eta: *ior,
+            }))
+        }
+    }
+}
+
+/// Re-radiation from a list of Gaussians or a measured matrix file.
+fn build_rerad<S: Spectrum>(
+    rerad: &[GaussRecordDesc],
+    donaldson: &Option<DonaldsonDesc>,
+    base_dir: &Path,
+) -> Result<ReRadiation<S>, SceneError> {
+    let donaldson = match donaldson {
+        Some(donaldson) => donaldson,
+        None => {
+            let mut rerad_list = Vec::with_capacity(rerad.len());
+            for (i, g) in rerad.iter().enumerate() {
+                if g.sigma_in <= 0.0 || g.sigma_out <= 0.0 {
+                    return invalid(format!("rerad entry {}: sigmas must be positive", i));
+                }
+                rerad_list.push(GaussRecord {
+                    lambda_in: g.lambda_in,
+                    lambda_out: g.lambda_out,
+                    amplitude_out: g.scale * g.amplitude_out,
+                    sigma_in: g.sigma_in,
+                    sigma_out: g.sigma_out,
+                });
+            }
+            return Ok(ReRadiation::new(rerad_list));
+        }
+    };
+    if !rerad.is_empty() {
+        return invalid("give either 'rerad' or 'donaldson', not both".to_string());
+    }
+    let full_path = base_dir.join(&donaldson.path);
+    let matrix = donaldson::load_donaldson(&full_path, donaldson.unit)
+        .and_then(|m| {
+            let m = if donaldson.transpose {
+                m.transposed()
+            } else {
+                m
+            };
+            m.check_grid(spectrum::spectral_grid())?;
+            Ok(m)
+        })
+        .map_err(|err| SceneError::Invalid(format!("matrix '{}': {}", full_path.display(), err)))?;
+    let sampled: Vec<Real> = matrix
+        .sampled_matrix()
+        .iter()
+        .map(|v| v * donaldson.scale)
+        .collect();
+    Ok(ReRadiation::from_matrix(&sampled))
+}
+
+/// Roughness of a microfacet distribution, with the index of refraction
+/// `ior` of its material.
+fn build_microfacet_roughness(
+    desc: &ScalarDesc,
+    ior: Real,
+    base_dir: &Path,
+) -> Result<Arc<dyn Texture<Real>>, SceneError> {
+    if ior <= 0.0 || ior.is_nan() {
+        return invalid(format!("ior must be positive, got {}", ior));
+    }
+    build_scalar_texture(desc, base_dir, &|r| {
+        if r <= 0.0 || r > 1.0 || r.is_nan() {
+            return invalid(format!("roughness must be in (0, 1], got {}", r));
+        }
+        Ok(())
+    })
+}
+
+/// Roughness of a metal, which is a mirror at 0, the default, and a
+/// microfacet lobe up to 1.
+fn build_roughness(
+    desc: &Option<ScalarDesc>,
+    base_dir: &Path,
+) -> Result<Arc<dyn Texture<Real>>, SceneError> {
+    match desc {
+        None => Ok(Arc::new(0.0)),
+        Some(desc) => build_scalar_texture(desc, base_dir, &|r| {
+            if !(0.0..=1.0).contains(&r) {
+                return invalid(format!("roughness must be in [0, 1], got {}", r));
+            }
+            Ok(())
+        }),
+    }
+}
+
+fn find_material<S: Spectrum>(
+    name: &Option<String>,
+    materials: &BTreeMap<&str, Arc<dyn Material<S> + Send>>,
+) -> Result<Option<Arc<dyn Material<S> + Send>>, SceneError> {
+    match name {
+        None => Ok(None),
+        Some(name) => match materials.get(name.as_str()) {
+            Some(m) => Ok(Some(m.clone())),
+            None => invalid(format!("unknown material '{}'", name)),
+        },
+    }
+}
+
+fn build_object<S: Spectrum>(
+    desc: &ObjectDesc,
+    materials: &BTreeMap<&str, Arc<dyn Material<S> + Send>>,
+    base_dir: &Path,
+) -> Result<Box<dyn Hitable<S>>, SceneError> {
+    match desc {
+        ObjectDesc::Sphere {
+            center,
+            radius,
+            material,
+        } => {
+            if *radius <= 0.0 {
+                return invalid(format!("sphere radius must be positive, got {}", radius));
+            }
+            Ok(Box::new(Sphere {
+                center: vec3(*center),
+                radius: *radius,
+                material: find_material(material, materials)?,
+            }))
+        }
+        ObjectDesc::Plane {
+            origin,
+            normal,
+            rot_around_normal,
+            width,
+            height,
+            material,
+        } => {
+            if vec3(*normal).length() <= EPS {
+                return invalid("plane normal must not be zero".to_string());
+            }
+            if *width <= 0.0 || *height <= 0.0 {
+                return invalid(format!(
+                    "plane width and height must be positive, got {} x {}",
+                    width, height
+                ));
+            }
+            Ok(Box::new(Plane {
+                origin: vec3(*origin),
+                normal: vec3(*normal).make_unit_vector(),
+                rot_around_normal: rot_around_normal.to_radians(),
+                width: *width,
+                height: *height,
+                material: find_material(material, materials)?,
+            }))
+        }
+        ObjectDesc::Cuboid {
+            origin,
+            size,
+            rotation,
+            material,
+        } => {
+            if size.iter().any(|s| *s <= 0.0) {
+                return invalid(format!("cuboid size must be positive, got {:?}", size));
+            }
+            let rot = vec3(*rotation) * (PI / 180.0);
+            let mut cuboid = Cuboid::new()
+                .origin(vec3(*origin))
+                .size(vec3(*size))
+                .rot(Quaternion::from_eulerangles(rot));
+            if let Some(m) = find_material(material, materials)? {
+                cuboid = cuboid.material(m);
+            }
+            Ok(Box::new(cuboid.build()))
+        }
+        ObjectDesc::Mesh {
+            path,
+            translate,
+            scale,
+            rotation,
+            material,
+        } => {
+            if scale.iter().any(|s| *s <= 0.0) {
+                return invalid(format!("mesh scale must be positive, got {:?}", scale));
+            }
+            let full_path = base_dir.join(path);
+            let mut mesh = mesh_io::load_mesh(&full_path).map_err(|err| {
+                SceneError::Invalid(format!("mesh '{}': {}", full_path.display(), err))
+            })?;
+            if let Some(m) = find_material(material, materials)? {
+                mesh = mesh.material(m);
+            }
+            let rot = Quaternion::from_eulerangles(vec3(*rotation) * (PI / 180.0));
+            Ok(Box::new(mesh.transform(
+                vec3(*scale),
+                rot,
+                vec3(*translate),
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+    use crate::spectrum::SampledSpectrum;
+
+    const SCENE: &str = r#"
+[camera]
+lookfrom = [278.0, 278.0, -800.0]
+lookat = [278.0, 278.0, 0.0]
+fov = 40.0
+exposure = 2.0
+
+[spectra.white]
+lambda = [400.0, 700.0]
+values = [0.73, 0.73]
+
+[materials.light]
+type = "lambertian"
+emit = { spectrum = "D65", scale = 0.1 }
+
+[materials.white]
+type = "lambertian"
+albedo = "white"
+
+[[objects]]
+type = "plane"
+origin = [278.0, 554.0, 279.5]
+normal = [0.0, -1.0, 0.0]
+width = 130.0
+height = 105.0
+material = "light"
+
+[[objects]]
+type = "sphere"
+center = [278.0, 278.0, 0.0]
+radius = 100.0
+material = "white"
+"#;
+
+    fn parse(text: &str) -> Result<(HitList<SampledSpectrum>, Camera), SceneError> {
+        parse_scene(text, Path::new(""), 1.0)
+    }
+
+    fn assert_close(a: &SampledSpectrum, b: &SampledSpectrum) {
+        for (a, b) in a.values().iter().zip(b.values().iter()) {
+            assert!((a - b).abs() <= 1e-4 * b.abs() + 1e-6, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let (world, camera) = parse(SCENE).unwrap();
+        assert_eq!(world.len(), 2);
+        assert!((camera.origin - Vec3(278.0, 278.0, -800.0)).length() < 1e-3);
+        assert_eq!(camera.exposure, 2.0);
+
+        // Straight ahead is the sphere, 100 in front of its center.
+        let r = Ray::new(camera.origin, Vec3(0.0, 0.0, 1.0));
+        let hit = world.hit(&r).unwrap();
+        assert!((hit.t - 700.0).abs() < 1e-2, "t = {}", hit.t);
+        let white = hit.material.as_ref().unwrap();
+        let srec = white.scatter(&r, &hit).unwrap();
+        assert_close(&srec.attenuation, &SampledSpectrum::new(0.73));
+
+        // Straight up from the middle of the room is the light.
+        let r = Ray::new(Vec3(278.0, 400.0, 279.5), Vec3(0.0, 1.0, 0.0));
+        let hit = world.hit(&r).unwrap();
+        assert!((hit.t - 154.0).abs() < 1e-2, "t = {}", hit.t);
+        let light = hit.material.as_ref().unwrap();
+        assert_close(
+            &light.average_emission(),
+            &(spectrum::illumination_d65::<SampledSpectrum>() * 0.1),
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let unknown_material = SCENE.replace("material = \"white\"", "material = \"black\"");
+        match parse(&unknown_material) {
+            Err(SceneError::Invalid(msg)) => assert!(msg.contains("black"), "{}", msg),
+            _ => panic!("unknown material was not rejected"),
+        }
+        let unknown_field = SCENE.replace("fov = 40.0", "fov = 40.0\nzoom = 2.0");
+        assert!(matches!(parse(&unknown_field), Err(SceneError::Parse(_))));
+        let bad_fov = SCENE.replace("fov = 40.0", "fov = 180.0");
+        assert!(matches!(parse(&bad_fov), Err(SceneError::Invalid(_))));
+        let flat_plane = SCENE.replace("height = 105.0", "height = 0.0");
+        match parse(&flat_plane) {
+            Err(SceneError::Invalid(msg)) => assert!(msg.contains("plane"), "{}", msg),
+            _ => panic!("plane without height was not rejected"),
+        }
+        let flat_cuboid = format!(
+            "{}\n[[objects]]\ntype = \"cuboid\"\norigin = [0.0, 0.0, 0.0]\n\
+             size = [1.0, -1.0, 1.0]\n",
+            SCENE
+        );
+        match parse(&flat_cuboid) {
+            Err(SceneError::Invalid(msg)) => assert!(msg.contains("cuboid"), "{}", msg),
+            _ => panic!("cuboid with negative size was not rejected"),
+        }
+        let mirrored_mesh = format!(
+            "{}\n[[objects]]\ntype = \"mesh\"\npath = \"missing.obj\"\n\
+             scale = [1.0, 1.0, -1.0]\n",
+            SCENE
+        );
+        match parse(&mirrored_mesh) {
+            Err(SceneError::Invalid(msg)) => assert!(msg.contains("mesh scale"), "{}", msg),
+            _ => panic!("mesh with negative scale was not rejected"),
+        }
+    }
+}
@@ -1,13 +1,22 @@
 use crate::core::*;
+use crate::settings::SceneName;
 use crate::*;
 
-pub fn make_colour_checker(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
+/// Build the named scene, setting up `camera` for the given image aspect ratio.
+pub fn make_scene<S: Spectrum>(name: SceneName, camera: &mut Camera, aspect: Real) -> HitList<S> {
+    match name {
+        SceneName::Cornell => make_cornell(camera, aspect),
+        SceneName::ColourChecker => make_colour_checker(camera, aspect),
+        SceneName::Dev => make_dev_scene(camera, aspect),
+    }
+}
+
+pub fn make_colour_checker<S: Spectrum>(camera: &mut Camera, aspect: Real) -> HitList<S> {
     {
         let lookfrom = Vec3::new(0.0, 0.0, 10.0);
         let lookat = Vec3::new(0.0, 0.0, 0.0);
         let up = Vec3::new(0.0, 1.0, 0.0);
         let fov = 40.0;
-        let aspect = (NX as Real) / (NY as Real);
         let aperture = 0.0;
         let focus_dist = 10.0; //(lookfrom-lookat).length();
         *camera = Camera::new(lookfrom, lookat, up, fov, aspect, aperture, focus_dist);
@@ -16,11 +25,10 @@ pub fn make_colour_checker(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
         //camera.exposure = 0.001; // underexpose for fluorescent.
     }
 
-    let spec_zero = spectrum::Spectrum::default();
+    let spec_zero = S::default();
 
     const N_PATCHES: usize = 24;
-    let mut colour_cheker_patch_spec: [Spectrum; N_PATCHES] =
-        [spectrum::Spectrum::default(); N_PATCHES];
+    let mut colour_cheker_patch_spec: [S; N_PATCHES] = [S::default(); N_PATCHES];
     {
         const N_SAMPLES_PER_PATCH: usize = 36;
         const PATCH_LAMBDAS: [Real; N_SAMPLES_PER_PATCH] = [
@@ -176,18 +184,15 @@ pub fn make_colour_checker(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
         ];
 
         for i in 0..N_PATCHES {
-            colour_cheker_patch_spec[i] = spectrum::Spectrum::from_sampled(
-                &PATCH_LAMBDAS,
-                &PATCH_VALUES[i],
-                N_SAMPLES_PER_PATCH,
-            );
+            colour_cheker_patch_spec[i] =
+                S::from_sampled(&PATCH_LAMBDAS, &PATCH_VALUES[i], N_SAMPLES_PER_PATCH);
         }
     }
 
     const N_PATCHES_PER_ROW: usize = 6;
     const N_PATCHES_PER_COL: usize = N_PATCHES / N_PATCHES_PER_ROW;
 
-    let mut scene: Vec<Box<dyn Hitable>> = Vec::with_capacity(N_PATCHES + 1);
+    let mut scene: HitList<S> = Vec::with_capacity(N_PATCHES + 1);
     for i in 0..N_PATCHES {
         let i_row = N_PATCHES_PER_COL - i / N_PATCHES_PER_ROW;
         let i_col = i % N_PATCHES_PER_ROW;
@@ -207,16 +212,16 @@ pub fn make_colour_checker(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
             width: 1.0 * SCALE,
             height: 1.0 * SCALE,
             material: Some(Arc::new(materials::Lambertian {
-                emit: spec_zero,
-                albedo: colour_cheker_patch_spec[i],
+                emit: Arc::new(spec_zero),
+                albedo: Arc::new(colour_cheker_patch_spec[i]),
             })),
         }));
     }
 
     let s = 0.25;
-    let pink_rerad = materials::LambertianReRad {
-        emit: Spectrum::default(),
-        rerad_list: vec![
+    let pink_rerad = materials::LambertianReRad::new(
+        Arc::new(S::default()),
+        vec![
             materials::GaussRecord {
                 lambda_in: 350.0,
                 lambda_out: 350.0,
@@ -337,7 +342,8 @@ pub fn make_colour_checker(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
                 sigma_out: 15.0,
             },
         ],
-    };
+    )
+    .named("pink_rerad");
 
     scene.push(Box::new(
         //Small sphere
@@ -352,13 +358,12 @@ pub fn make_colour_checker(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
 
     return scene;
 }
-pub fn make_cornell(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
+pub fn make_cornell<S: Spectrum>(camera: &mut Camera, aspect: Real) -> HitList<S> {
     {
         let lookfrom = Vec3::new(278.0, 278.0, -800.0);
         let lookat = Vec3::new(278.0, 278.0, 0.0);
         let up = Vec3::new(0.0, 1.0, 0.0);
         let fov = 40.0;
-        let aspect = (NX as Real) / (NY as Real);
         let aperture = 0.0;
         let focus_dist = 10.0; //(lookfrom-lookat).length();
         *camera = Camera::new(lookfrom, lookat, up, fov, aspect, aperture, focus_dist);
@@ -366,12 +371,12 @@ pub fn make_cornell(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
     }
 
     // Make spectrums
-    //let sampled_light = *spectrum::ILLUMINATION_HALOGEN;
-    let sampled_light = 0.1 * (*spectrum::ILLUMINATION_D65);
+    //let sampled_light = spectrum::illumination_halogen::<S>();
+    let sampled_light = spectrum::illumination_d65::<S>() * 0.1;
     //let spectrum_light_lambdas  = vec![ 400.0,  500.0,  600.0,  700.0 ];
     //let spectrum_light_values   = vec![ 0.0,    8.0,    15.6,   18.4 ];
     //print!("--> Sampled Light:  ");
-    //let sampled_light = spectrum::Spectrum::from_sampled(
+    //let sampled_light = S::from_sampled(
     //    &spectrum_light_lambdas,
     //    &spectrum_light_values,
     //    4);
@@ -393,8 +398,7 @@ pub fn make_cornell(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
         0.745, 0.755, 0.751, 0.744, 0.731, 0.733, 0.744, 0.731, 0.712, 0.708, 0.729, 0.730, 0.727,
         0.707, 0.703, 0.729, 0.750, 0.760, 0.751, 0.739, 0.724, 0.730, 0.740, 0.737,
     ];
-    let sampled_refl_white =
-        spectrum::Spectrum::from_sampled(&spectrum_refl_lambdas, &spectrum_refl_white, 75);
+    let sampled_refl_white = S::from_sampled(&spectrum_refl_lambdas, &spectrum_refl_white, 75);
 
     let spectrum_refl_red = vec![
         0.040, 0.046, 0.048, 0.053, 0.049, 0.050, 0.053, 0.055, 0.057, 0.056, 0.059, 0.057, 0.061,
@@ -404,8 +408,7 @@ pub fn make_cornell(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
         0.402, 0.443, 0.487, 0.513, 0.558, 0.584, 0.620, 0.606, 0.609, 0.651, 0.612, 0.610, 0.650,
         0.638, 0.627, 0.620, 0.630, 0.628, 0.642, 0.639, 0.657, 0.639, 0.635, 0.642,
     ];
-    let sampled_refl_red =
-        spectrum::Spectrum::from_sampled(&spectrum_refl_lambdas, &spectrum_refl_red, 75);
+    let sampled_refl_red = S::from_sampled(&spectrum_refl_lambdas, &spectrum_refl_red, 75);
 
     let spectrum_refl_green = vec![
         0.092, 0.096, 0.098, 0.097, 0.098, 0.095, 0.095, 0.097, 0.095, 0.094, 0.097, 0.098, 0.096,
@@ -415,16 +418,15 @@ pub fn make_cornell(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
         0.141, 0.136, 0.130, 0.126, 0.123, 0.121, 0.122, 0.119, 0.114, 0.115, 0.117, 0.117, 0.118,
         0.120, 0.122, 0.128, 0.132, 0.139, 0.144, 0.146, 0.150, 0.152, 0.157, 0.159,
     ];
-    let sampled_refl_green =
-        spectrum::Spectrum::from_sampled(&spectrum_refl_lambdas, &spectrum_refl_green, 75);
+    let sampled_refl_green = S::from_sampled(&spectrum_refl_lambdas, &spectrum_refl_green, 75);
 
     let rgb_zero = spectrum::RGBSpectrum::new(0.0 as Real);
-    let sampled_zero = spectrum::Spectrum::default();
+    let sampled_zero = S::default();
 
     // Make rerad material.
-    let yellow_rerad = materials::LambertianReRad {
-        emit: sampled_zero,
-        rerad_list: vec![
+    let yellow_rerad = materials::LambertianReRad::new(
+        Arc::new(sampled_zero),
+        vec![
             //materials::GaussRecord {lambda_in: 600.0, lambda_out: 600.0, amplitude_out: 480.0, sigma_in: 20.0, sigma_out: 20.0},
             //materials::GaussRecord {lambda_in: 500.0, lambda_out: 600.0, amplitude_out: 800.0, sigma_in: 12.0, sigma_out: 12.0},
             //materials::GaussRecord {lambda_in: 450.0, lambda_out: 650.0, amplitude_out: 0.05, sigma_in: 10.0, sigma_out: 10.0},
@@ -457,12 +459,13 @@ pub fn make_cornell(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
                 sigma_out: 12.0,
             },
         ],
-    };
+    )
+    .named("yellow_rerad");
 
     let s = 0.25;
-    let pink_rerad = materials::LambertianReRad {
-        emit: Spectrum::default(),
-        rerad_list: vec![
+    let pink_rerad = materials::LambertianReRad::new(
+        Arc::new(S::default()),
+        vec![
             materials::GaussRecord {
                 lambda_in: 350.0,
                 lambda_out: 350.0,
@@ -583,9 +586,10 @@ pub fn make_cornell(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
                 sigma_out: 15.0,
             },
         ],
-    };
+    )
+    .named("pink_rerad");
 
-    let scene: Vec<Box<dyn Hitable>> = vec![
+    let scene: HitList<S> = vec![
         Box::new(
             //Red
             Plane {
@@ -595,8 +599,8 @@ pub fn make_cornell(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
                 width: 555.0,
                 height: 555.0,
                 material: Some(Arc::new(materials::Lambertian {
-                    emit: sampled_zero,
-                    albedo: sampled_refl_red,
+                    emit: Arc::new(sampled_zero),
+                    albedo: Arc::new(sampled_refl_red),
                 })),
             },
         ),
@@ -609,8 +613,8 @@ pub fn make_cornell(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
                 width: 555.0,
                 height: 555.0,
                 material: Some(Arc::new(materials::Lambertian {
-                    emit: sampled_zero,
-                    albedo: sampled_refl_green,
+                    emit: Arc::new(sampled_zero),
+                    albedo: Arc::new(sampled_refl_green),
                 })),
             },
         ),
@@ -623,8 +627,8 @@ pub fn make_cornell(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
                 width: 130.0,
                 height: 105.0,
                 material: Some(Arc::new(materials::Lambertian {
-                    emit: sampled_light,
-                    albedo: sampled_zero,
+                    emit: Arc::new(sampled_light),
+                    albedo: Arc::new(sampled_zero),
                 })), //material: Some(Arc::new( materials::Lambertian{ emit: Vec3(0.0, 0.0, 0.0), albedo: Vec3::ZEROS } ) )
             },
         ),
@@ -637,8 +641,8 @@ pub fn make_cornell(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
                 width: 555.0,
                 height: 555.0,
                 material: Some(Arc::new(materials::Lambertian {
-                    emit: sampled_zero,
-                    albedo: sampled_refl_white,
+                    emit: Arc::new(sampled_zero),
+                    albedo: Arc::new(sampled_refl_white),
                 })),
             },
         ),
@@ -651,8 +655,8 @@ pub fn make_cornell(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
                 width: 555.0,
                 height: 555.0,
                 material: Some(Arc::new(materials::Lambertian {
-                    emit: sampled_zero,
-                    albedo: sampled_refl_white,
+                    emit: Arc::new(sampled_zero),
+                    albedo: Arc::new(sampled_refl_white),
                 })),
             },
         ),
@@ -665,8 +669,8 @@ pub fn make_cornell(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
                 width: 555.0,
                 height: 555.0,
                 material: Some(Arc::new(materials::Lambertian {
-                    emit: sampled_zero,
-                    albedo: sampled_refl_white,
+                    emit: Arc::new(sampled_zero),
+                    albedo: Arc::new(sampled_refl_white),
                 })),
             },
         ),
@@ -703,8 +707,8 @@ pub fn make_cornell(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
                 )))
                 //.material( Arc::new( materials::Lambertian{ emit: rgb_zero, albedo: rgb_refl_white } ) )
                 .material(Arc::new(materials::Lambertian {
-                    emit: sampled_zero,
-                    albedo: sampled_refl_white,
+                    emit: Arc::new(sampled_zero),
+                    albedo: Arc::new(sampled_refl_white),
                 }))
                 //.material( Arc::new( materials::Metal{ albedo: Vec3(0.73, 0.73, 0.73), fuzz: 0.0 } ) )
                 .build(),
@@ -713,89 +717,70 @@ pub fn make_cornell(camera: &mut Camera) -> Vec<Box<dyn Hitable>> {
     return scene;
 }
 
-/*
-pub fn make_dev_scene(camera: &mut Camera) -> Vec<Box<Hitable>> {
+pub fn make_dev_scene<S: Spectrum>(camera: &mut Camera, aspect: Real) -> HitList<S> {
     {
         let lookfrom = Vec3(0.0, 2.0, 25.0);
         let lookat = Vec3(0.0, 1.0, 0.0);
         let up = Vec3(0.0, 1.0, 0.0);
         let fov = 20.0;
-        let aspect = (NX as f64)/(NY as f64);
         let aperture = 0.3;
-        let focus_dist = (lookfrom-lookat).length();
+        let focus_dist = (lookfrom - lookat).length();
         *camera = Camera::new(lookfrom, lookat, up, fov, aspect, aperture, focus_dist);
     }
-    //{
-    //    let lookfrom = Vec3::new(13.0, 2.0, 3.0);
-    //    let lookat = Vec3::new(0.0, 0.0, 0.0);
-    //    let up = Vec3::new(0.0, 1.0, 0.0);
-    //    let fov = 20.0;
-    //    let aspect = (NX as f64)/(NY as f64);
-    //    let aperture = 0.3;
-    //    let focus_dist = (lookfrom-lookat).length();
-    //    *camera = Camera::new(lookfrom, lookat, up, fov, aspect, aperture, focus_dist);
-    //}
 
     // Make spectrums
-    let spectrum_light_lambdas  = vec![ 400.0,  500.0,  600.0,  700.0 ];
-    let spectrum_light_values   = vec![ 0.0,    8.0,    15.6,   18.4 ];
-    let rgb_light = spectrum::RGBSpectrum::from_sampled(
-        &spectrum_light_lambdas,
-        &spectrum_light_values,
-        4);
-
-    let spectrum_refl_lambdas = vec![ 400.0, 404.0, 408.0, 412.0, 416.0, 420.0, 424.0, 428.0, 432.0, 436.0, 440.0, 444.0, 448.0, 452.0, 456.0, 460.0, 464.0, 468.0, 472.0, 476.0, 480.0, 484.0, 488.0, 492.0, 496.0, 500.0, 504.0, 508.0, 512.0, 516.0, 520.0, 524.0, 528.0, 532.0, 536.0, 540.0, 544.0, 548.0, 552.0, 556.0, 560.0, 564.0, 568.0, 572.0, 576.0, 580.0, 584.0, 588.0, 592.0, 596.0, 600.0, 604.0, 608.0, 612.0, 616.0, 620.0, 624.0, 628.0, 632.0, 636.0, 640.0, 644.0, 648.0, 652.0, 656.0, 660.0, 664.0, 668.0, 672.0, 676.0, 680.0, 684.0, 688.0, 692.0, 696.0, 700.0 ];
+    let spectrum_light_lambdas = vec![400.0, 500.0, 600.0, 700.0];
+    let spectrum_light_values = vec![0.0, 8.0, 15.6, 18.4];
+    let light = S::from_sampled(&spectrum_light_lambdas, &spectrum_light_values, 4);
 
-    let spectrum_refl_white = vec![ 0.343, 0.445, 0.551, 0.624, 0.665, 0.687, 0.708, 0.723, 0.715, 0.710, 0.745, 0.758, 0.739, 0.767, 0.777, 0.765, 0.751, 0.745, 0.748, 0.729, 0.745, 0.757, 0.753, 0.750, 0.746, 0.747, 0.735, 0.732, 0.739, 0.734, 0.725, 0.721, 0.733, 0.725, 0.732, 0.743, 0.744, 0.748, 0.728, 0.716, 0.733, 0.726, 0.713, 0.740, 0.754, 0.764, 0.752, 0.736, 0.734, 0.741, 0.740, 0.732, 0.745, 0.755, 0.751, 0.744, 0.731, 0.733, 0.744, 0.731, 0.712, 0.708, 0.729, 0.730, 0.727, 0.707, 0.703, 0.729, 0.750, 0.760, 0.751, 0.739, 0.724, 0.730, 0.740, 0.737 ];
-    let rgb_refl_white = spectrum::RGBSpectrum::from_sampled(
-        &spectrum_refl_lambdas,
-        &spectrum_refl_white,
-        75);
-
-    let spectrum_refl_red = vec![ 0.040, 0.046, 0.048, 0.053, 0.049, 0.050, 0.053, 0.055, 0.057, 0.056, 0.059, 0.057, 0.061, 0.061, 0.060, 0.062, 0.062, 0.062, 0.061, 0.062, 0.060, 0.059, 0.057, 0.058, 0.058, 0.058, 0.056, 0.055, 0.056, 0.059, 0.057, 0.055, 0.059, 0.059, 0.058, 0.059, 0.061, 0.061, 0.063, 0.063, 0.067, 0.068, 0.072, 0.080, 0.090, 0.099, 0.124, 0.154, 0.192, 0.255, 0.287, 0.349, 0.402, 0.443, 0.487, 0.513, 0.558, 0.584, 0.620, 0.606, 0.609, 0.651, 0.612, 0.610, 0.650, 0.638, 0.627, 0.620, 0.630, 0.628, 0.642, 0.639, 0.657, 0.639, 0.635, 0.642 ];
-    let rgb_refl_red = spectrum::RGBSpectrum::from_sampled(
-        &spectrum_refl_lambdas,
-        &spectrum_refl_red,
-        75);
+    let spectrum_refl_lambdas = vec![
+        400.0, 404.0, 408.0, 412.0, 416.0, 420.0, 424.0, 428.0, 432.0, 436.0, 440.0, 444.0, 448.0,
+        452.0, 456.0, 460.0, 464.0, 468.0, 472.0, 476.0, 480.0, 484.0, 488.0, 492.0, 496.0, 500.0,
+        504.0, 508.0, 512.0, 516.0, 520.0, 524.0, 528.0, 532.0, 536.0, 540.0, 544.0, 548.0, 552.0,
+        556.0, 560.0, 564.0, 568.0, 572.0, 576.0, 580.0, 584.0, 588.0, 592.0, 596.0, 600.0, 604.0,
+        608.0, 612.0, 616.0, 620.0, 624.0, 628.0, 632.0, 636.0, 640.0, 644.0, 648.0, 652.0, 656.0,
+        660.0, 664.0, 668.0, 672.0, 676.0, 680.0, 684.0, 688.0, 692.0, 696.0, 700.0,
+    ];
+    let spectrum_refl_red = vec![
+        0.040, 0.046, 0.048, 0.053, 0.049, 0.050, 0.053, 0.055, 0.057, 0.056, 0.059, 0.057, 0.061,
+        0.061, 0.060, 0.062, 0.062, 0.062, 0.061, 0.062, 0.060, 0.059, 0.057, 0.058, 0.058, 0.058,
+        0.056, 0.055, 0.056, 0.059, 0.057, 0.055, 0.059, 0.059, 0.058, 0.059, 0.061, 0.061, 0.063,
+        0.063, 0.067, 0.068, 0.072, 0.080, 0.090, 0.099, 0.124, 0.154, 0.192, 0.255, 0.287, 0.349,
+        0.402, 0.443, 0.487, 0.513, 0.558, 0.584, 0.620, 0.606, 0.609, 0.651, 0.612, 0.610, 0.650,
+        0.638, 0.627, 0.620, 0.630, 0.628, 0.642, 0.639, 0.657, 0.639, 0.635, 0.642,
+    ];
+    let refl_red = S::from_sampled(&spectrum_refl_lambdas, &spectrum_refl_red, 75);
 
-    let spectrum_refl_green = vec![ 0.092, 0.096, 0.098, 0.097, 0.098, 0.095, 0.095, 0.097, 0.095, 0.094, 0.097, 0.098, 0.096, 0.101, 0.103, 0.104, 0.107, 0.109, 0.112, 0.115, 0.125, 0.140, 0.160, 0.187, 0.229, 0.285, 0.343, 0.390, 0.435, 0.464, 0.472, 0.476, 0.481, 0.462, 0.447, 0.441, 0.426, 0.406, 0.373, 0.347, 0.337, 0.314, 0.285, 0.277, 0.266, 0.250, 0.230, 0.207, 0.186, 0.171, 0.160, 0.148, 0.141, 0.136, 0.130, 0.126, 0.123, 0.121, 0.122, 0.119, 0.114, 0.115, 0.117, 0.117, 0.118, 0.120, 0.122, 0.128, 0.132, 0.139, 0.144, 0.146, 0.150, 0.152, 0.157, 0.159 ];
-    let rgb_refl_green = spectrum::RGBSpectrum::from_sampled(
-        &spectrum_refl_lambdas,
-        &spectrum_refl_green,
-        75);
+    let spec_zero = S::default();
 
-    let list: Vec<Box<Hitable>> = vec![
-        Box::new(Sphere{
+    let list: HitList<S> = vec![
+        Box::new(Sphere {
             center: Vec3::new(0.0, -1000.0, 0.0),
             radius: 1000.0,
-            material: Some(Arc::new( materials::Lambertian{ emit: spectrum::RGBSpectrum::new(0.0 as Real), albedo: rgb_refl_red } )),
+            material: Some(Arc::new(materials::Lambertian {
+                emit: Arc::new(spec_zero),
+                albedo: Arc::new(refl_red),
+            })),
         }),
-        Box::new(Plane{
+        Box::new(Sphere {
+            center: Vec3::new(0.0, 1.0, 0.0),
+            radius: 1.0,
+            material: Some(Arc::new(materials::Lambertian {
+                emit: Arc::new(spec_zero),
+                albedo: Arc::new(S::from_sampled(&[400.0, 700.0], &[1.0, 1.0], 2) * 0.9),
+            })),
+        }),
+        Box::new(Plane {
             origin: Vec3::new(0.0, 3.0, 0.0),
             normal: Vec3::new(0.0, -1.0, 0.0),
             rot_around_normal: 0.0,
             width: 4.0,
             height: 8.0,
-            material: Some(Arc::new( materials::Lambertian{ emit: rgb_light, albedo: spectrum::RGBSpectrum::new(0.0 as Real) } )),
+            material: Some(Arc::new(materials::Lambertian {
+                emit: Arc::new(light),
+                albedo: Arc::new(spec_zero),
+            })),
         }),
-        //Box::new(Plane{
-        //    origin: Vec3::new(0.0, 3.0, 0.0),
-        //    normal: Vec3::new(0.0, -1.0, 0.0),
-        //    rot_around_normal: 0.0,
-        //    width: 4.0,
-        //    height: 8.0,
-        //    material: Some(Arc::new( materials::Lambertian{ emit: 2.0*Vec3::ONES, albedo: Vec3::ZEROS } )),
-        //}),
-        //Box::new(Sphere{
-        //    center: Vec3::new(0.0, 1.0, 0.0),
-        //    radius: 1.0,
-        //    material: Some(Arc::new( materials::Lambertian{ emit: Vec3::ZEROS, albedo: 0.9*Vec3::ONES } )),
-        //}),
-        //Box::new(Sphere{
-        //    center: Vec3::new(5.0, 1.0, -10.0),
-        //    radius: 1.0,
-        //    material: None,
-        //}),
         //Box::new(
         //    Cuboid::new()
         //    .origin(Vec3(4.0, 1.0, 3.0))
@@ -806,7 +791,6 @@ pub fn make_dev_scene(camera: &mut Camera) -> Vec<Box<Hitable>> {
     ];
     return list;
 }
-*/
 
 //fn make_random_scene() -> Vec<Box<Hitable>> {
 //    let mut spheres: Vec<Sphere> = vec![
@@ -0,0 +1,205 @@
+use crate::core::Real;
+use crate::donaldson::WavelengthUnit;
+use crate::integrator::{Bounces, MisHeuristic};
+use crate::spectrum;
+use crate::uplift::UpliftMethod;
+use clap::builder::RangedU64ValueParser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+
+/// Scenes that are built in code, see `scenes.rs`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SceneName {
+    Cornell,
+    ColourChecker,
+    Dev,
+}
+
+/// Spectral representations the renderer can use.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SpectrumKind {
+    /// Red, green and blue coefficients.
+    Rgb,
+    /// Samples of the visible spectrum, see `SampledSpectrum`.
+    Sampled,
+    /// Hero wavelength sampling of the sampled spectrum, where every path
+    /// carries a few wavelengths instead of all of them.
+    Hero,
+}
+
+/// Ways of tracing the light to the camera.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum IntegratorKind {
+    /// Path tracing that picks either a direction towards a light or one
+    /// sampled from the material at every diffuse hit.
+    Path,
+    /// Path tracing with a shadow ray to a light at every diffuse hit,
+    /// combined with the material sample by multiple importance sampling.
+    Mis,
+    /// Bidirectional path tracing, connecting camera paths to paths traced
+    /// from the lights. Needs the rgb or sampled spectrum.
+    Bdpt,
+}
+
+/// Everything that controls a single render.
+#[derive(Clone, Debug, Parser)]
+#[command(name = "rcpt", about = "A spectral ray-tracer.")]
+pub struct RenderSettings {
+    /// Scene to render.
+    #[arg(short, long, value_enum, default_value = "cornell")]
+    pub scene: SceneName,
+
+    /// Scene description file to render instead of a built in scene.
+    #[arg(short = 'f', long, conflicts_with = "scene")]
+    pub scene_file: Option<PathBuf>,
+
+    /// Spectral representation to render with. Several can be given,
+    /// separated by commas, to render the scene once with each of them. The
+    /// name of the representation is then added to the output file names.
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "sampled")]
+    pub spectrum: Vec<SpectrumKind>,
+
+    /// Start of the wavelength range of the sampled spectrum, in nanometers.
+    #[arg(long, default_value_t = spectrum::DEFAULT_LAMBDA_START)]
+    pub lambda_start: Real,
+
+    /// End of the wavelength range of the sampled spectrum, in nanometers.
+    #[arg(long, default_value_t = spectrum::DEFAULT_LAMBDA_END)]
+    pub lambda_end: Real,
+
+    /// Number of equally wide wavelength bins of the sampled spectrum, at
+    /// most 128.
+    #[arg(long, default_value_t = spectrum::DEFAULT_SPECTRAL_SAMPLES)]
+    pub spectral_bins: usize,
+
+    /// Method that turns RGB colours from images, mesh files and scene files
+    /// into spectra for the sampled spectrum.
+    #[arg(long, value_enum, default_value = "sigmoid")]
+    pub uplift: UpliftMethod,
+
+    /// Integrator that traces the light to the camera.
+    #[arg(long, value_enum, default_value = "path")]
+    pub integrator: IntegratorKind,
+
+    /// Weighting of the light and material samples of the mis integrator, and
+    /// of the connections of the bdpt integrator.
+    #[arg(long, value_enum, default_value = "power")]
+    pub mis_heuristic: MisHeuristic,
+
+    /// Number of times a path scatters before Russian roulette may end it.
+    #[arg(long, default_value_t = 3)]
+    pub min_bounces: usize,
+
+    /// Largest number of times a path scatters.
+    #[arg(long, default_value_t = 64)]
+    pub max_bounces: usize,
+
+    /// Image width in pixels.
+    #[arg(long, default_value_t = 256, value_parser = at_least_one())]
+    pub width: usize,
+
+    /// Image height in pixels.
+    #[arg(long, default_value_t = 256, value_parser = at_least_one())]
+    pub height: usize,
+
+    /// Number of samples per pixel.
+    #[arg(short = 'n', long, default_value_t = 248, value_parser = at_least_one())]
+    pub samples: usize,
+
+    /// Number of render threads. Uses all available cores if not given.
+    #[arg(short = 'j', long)]
+    pub threads: Option<usize>,
+
+    /// Output image path. The format is picked from the extension: .png is
+    /// gamma corrected 8-bit, .exr and .pfm are linear floats. Can be given
+    /// several times to write more than one image.
+    #[arg(short, long, default_value = "out.png")]
+    pub output: Vec<PathBuf>,
+
+    /// Spectral image cube path, keeping every wavelength bin. Either an ENVI
+    /// header (.hdr, the data is written next to it as .raw) or a spectral
+    /// OpenEXR (.exr). Only written when rendering with the sampled or hero
+    /// spectrum.
+    #[arg(long)]
+    pub spectral_output: Vec<PathBuf>,
+
+    /// Write EXR images with 16-bit half float channels instead of 32-bit.
+    #[arg(long)]
+    pub half: bool,
+
+    /// Camera exposure. Overrides the exposure set by the scene.
+    #[arg(short, long)]
+    pub exposure: Option<Real>,
+
+    /// Print the energy balance of every re-radiating material, per
+    /// excitation wavelength.
+    #[arg(long)]
+    pub rerad_report: bool,
+
+    /// Refuse to render a scene with a re-radiating material that is not
+    /// physically plausible: one that re-radiates more energy than it
+    /// receives, has a quantum yield above 1 or re-radiates at shorter
+    /// wavelengths.
+    #[arg(long)]
+    pub strict_rerad: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Tools that are run instead of rendering.
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// Fit Gaussians to a measured excitation-emission matrix and write them
+    /// as a `lambertian_rerad` material for a scene file. The matrix is
+    /// resampled onto the wavelength grid given by the render options.
+    FitRerad(FitSettings),
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct FitSettings {
+    /// Matrix file, see `src/donaldson.rs` for the format.
+    pub matrix: PathBuf,
+
+    /// Number of Gaussians to fit.
+    #[arg(short, long, default_value_t = 4)]
+    pub gaussians: usize,
+
+    /// Largest number of Levenberg-Marquardt iterations per fit.
+    #[arg(long, default_value_t = 200)]
+    pub iterations: usize,
+
+    /// Unit of the wavelengths in the matrix file.
+    #[arg(long, value_enum, default_value = "nm")]
+    pub unit: WavelengthUnit,
+
+    /// The matrix file has the excitation wavelengths in the first row.
+    #[arg(long)]
+    pub transpose: bool,
+
+    /// Name of the fitted material.
+    #[arg(long, default_value = "fitted")]
+    pub name: String,
+
+    /// File to write the material to. Printed if not given.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+/// Parser for counts that can not be zero.
+fn at_least_one() -> RangedU64ValueParser<usize> {
+    RangedU64ValueParser::new().range(1..)
+}
+
+impl RenderSettings {
+    pub fn aspect(&self) -> Real {
+        (self.width as Real) / (self.height as Real)
+    }
+
+    pub fn bounces(&self) -> Bounces {
+        Bounces {
+            min: self.min_bounces,
+            max: self.max_bounces,
+        }
+    }
+}
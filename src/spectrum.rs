@@ -1,20 +1,94 @@
-//! The **Spectrum** type hides the details of the particular spectral
-//! representation used, so that changing this detail of the system
-//! only requires changing the **Spectrum** implementation.
-
-#[cfg(feature = "use_sampled_spectrum")]
-pub type Spectrum = SampledSpectrum;
-
-#[cfg(not(feature = "use_sampled_spectrum"))]
-pub type Spectrum = RGBSpectrum;
+//! The **Spectrum** trait hides the details of the particular spectral
+//! representation used, so that the renderer can be run with any of them.
+//! `RGBSpectrum` and `SampledSpectrum` are the two implementations.
 
 // std
 use std::ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub};
+use std::sync::OnceLock;
 
 // others
 use crate::core::{clamp_t, find_interval, lerp, Real, EPS};
+use crate::uplift::uplift;
 use num::Zero;
 
+/// A spectral representation. Everything that renders is generic over it,
+/// so the same scene can be rendered with RGB and sampled spectra.
+pub trait Spectrum:
+    Copy
+    + Default
+    + std::fmt::Debug
+    + Send
+    + Sync
+    + 'static
+    + Add<Output = Self>
+    + AddAssign
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Mul<Real, Output = Self>
+    + MulAssign
+    + Div<Output = Self>
+    + Div<Real, Output = Self>
+{
+    /// Name of the representation, as given on the command line.
+    const NAME: &'static str;
+    /// Scale applied to rendered radiance before it is written out, so that
+    /// all representations give images of about the same brightness.
+    const OUTPUT_SCALE: Real;
+
+    fn new(v: Real) -> Self;
+    /// Reflectance for a linear sRGB colour.
+    fn from_rgb(rgb: &[Real; 3]) -> Self;
+    /// Spectrum of the type `kind` for a linear sRGB colour.
+    fn from_rgb_typed(rgb: &[Real; 3], kind: SpectrumType) -> Self {
+        Self::from_rgb(rgb)
+    }
+    fn from_sampled(lambda: &[Real], v: &[Real], n: usize) -> Self;
+    fn to_xyz(&self) -> [Real; 3];
+    fn to_rgb(&self, rgb: &mut [Real; 3]);
+    fn is_black(&self) -> bool;
+    fn max_component_value(&self) -> Real;
+
+    /// The coefficients of the representation.
+    fn values(&self) -> &[Real];
+    fn values_mut(&mut self) -> &mut [Real];
+
+    /// Centre wavelength of every coefficient, `None` if the coefficients
+    /// are not samples of the spectrum.
+    fn lambdas() -> Option<Vec<Real>>;
+
+    /// Index of the coefficient that covers the wavelength `lambda`.
+    fn coefficient_index(lambda: Real) -> usize;
+
+    /// Wavelength range, start and end, covered by coefficient `i`.
+    fn coefficient_range(i: usize) -> (Real, Real);
+
+    /// Value of the spectrum at the wavelength `lambda`.
+    fn eval(&self, lambda: Real) -> Real {
+        self.values()[Self::coefficient_index(lambda)]
+    }
+
+    /// Matrix taking incoming light to re-radiated light for the
+    /// re-radiation function `f(lambda_in, lambda_out)`. Stored row major,
+    /// with one row per coefficient of the outgoing spectrum.
+    fn rerad_matrix(f: &dyn Fn(Real, Real) -> Real) -> Vec<Real> {
+        Self::rerad_matrix_from_sampled(&SampledSpectrum::rerad_matrix(f))
+    }
+
+    /// Re-radiation matrix for a matrix on the bins of the spectral grid,
+    /// such as one from `SampledSpectrum::rerad_matrix`.
+    fn rerad_matrix_from_sampled(m: &[Real]) -> Vec<Real>;
+
+    /// Multiply the coefficients with a matrix from `rerad_matrix`.
+    fn apply_matrix(&self, m: &[Real]) -> Self {
+        let mut out = Self::default();
+        let v = self.values();
+        for (o, row) in out.values_mut().iter_mut().zip(m.chunks(v.len())) {
+            *o = row.iter().zip(v.iter()).map(|(a, b)| a * b).sum();
+        }
+        out
+    }
+}
+
 // see spectrum.h
 
 pub const N_CIE_SAMPLES: usize = 471; //u16 = 471_u16;
@@ -1516,9 +1590,14 @@ pub fn blackbody_normalized(lambda: &[Real], n: usize, t: Real, le: &mut Vec<Rea
     }
 }
 
-#[derive(Debug, Clone)]
+/// What a spectrum made from a colour stands for, see `Spectrum::from_rgb_typed`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum SpectrumType {
+    /// Values between 0 and 1, such as an albedo.
     Reflectance,
+    /// Non-negative values of any size, such as an index of refraction.
+    Unbounded,
+    /// Emitted light, white is the white point of sRGB.
     Illuminant,
 }
 
@@ -1528,11 +1607,6 @@ pub struct RGBSpectrum {
 }
 
 impl RGBSpectrum {
-    pub fn new(v: Real) -> Self {
-        // let n_spectrum_samples = 3; // RGB
-        RGBSpectrum { c: [v, v, v] }
-        // TODO: DCHECK(!HasNaNs());
-    }
     pub fn rgb(r: Real, g: Real, b: Real) -> RGBSpectrum {
         RGBSpectrum { c: [r, g, b] }
     }
@@ -1553,7 +1627,54 @@ impl RGBSpectrum {
             inverse_gamma_convert_float(self.c[2]),
         )
     }
-    pub fn from_rgb(rgb: &[Real; 3]) -> RGBSpectrum {
+    pub fn from_xyz(xyz: &[Real; 3], _spectrum_type: SpectrumType) -> RGBSpectrum {
+        let mut r: RGBSpectrum = RGBSpectrum::new(0.0 as Real);
+        xyz_to_rgb(xyz, &mut r.c);
+        r
+    }
+    pub fn y(&self) -> Real {
+        let y_weight: [Real; 3] = [0.212671, 0.715160, 0.072169];
+        y_weight[0] * self.c[0] + y_weight[1] * self.c[1] + y_weight[2] * self.c[2]
+    }
+    pub fn sqrt(&self) -> RGBSpectrum {
+        RGBSpectrum::rgb(self.c[0].sqrt(), self.c[1].sqrt(), self.c[2].sqrt())
+    }
+    pub fn exp(&self) -> RGBSpectrum {
+        RGBSpectrum::rgb(self.c[0].exp(), self.c[1].exp(), self.c[2].exp())
+    }
+    /// Clamp spectrum to lie between the values low and high. Use
+    /// (0.0 as Real, std::f32::INFINITY as Real) if there are no
+    /// specific values.
+    pub fn clamp(&self, low: Real, high: Real) -> RGBSpectrum {
+        let mut ret: RGBSpectrum = RGBSpectrum::default();
+        let n_spectrum_samples: usize = 3; // RGB
+        for i in 0..n_spectrum_samples {
+            ret.c[i] = clamp_t(self.c[i], low, high);
+        }
+        assert!(!ret.has_nans());
+        ret
+    }
+    pub fn has_nans(&self) -> bool {
+        for i in 0..3 {
+            if self.c[i].is_nan() {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl Spectrum for RGBSpectrum {
+    const NAME: &'static str = "rgb";
+    const OUTPUT_SCALE: Real = 1.0;
+
+    fn new(v: Real) -> Self {
+        // let n_spectrum_samples = 3; // RGB
+        RGBSpectrum { c: [v, v, v] }
+        // TODO: DCHECK(!HasNaNs());
+    }
+
+    fn from_rgb(rgb: &[Real; 3]) -> RGBSpectrum {
         let mut s: RGBSpectrum = RGBSpectrum::new(0.0 as Real);
         s.c[0] = rgb[0];
         s.c[1] = rgb[1];
@@ -1561,24 +1682,20 @@ impl RGBSpectrum {
         // TODO: DCHECK(!s.HasNaNs());
         s
     }
-    pub fn to_rgb(&self, rgb: &mut [Real; 3]) {
+
+    fn to_rgb(&self, rgb: &mut [Real; 3]) {
         rgb[0] = self.c[0];
         rgb[1] = self.c[1];
         rgb[2] = self.c[2];
     }
-    pub fn to_xyz(&self, xyz: &mut [Real; 3]) {
-        rgb_to_xyz(&self.c, xyz);
-    }
-    pub fn from_xyz(xyz: &[Real; 3], _spectrum_type: SpectrumType) -> RGBSpectrum {
-        let mut r: RGBSpectrum = RGBSpectrum::new(0.0 as Real);
-        xyz_to_rgb(xyz, &mut r.c);
-        r
-    }
-    pub fn y(&self) -> Real {
-        let y_weight: [Real; 3] = [0.212671, 0.715160, 0.072169];
-        y_weight[0] * self.c[0] + y_weight[1] * self.c[1] + y_weight[2] * self.c[2]
+
+    fn to_xyz(&self) -> [Real; 3] {
+        let mut xyz = [0.0; 3];
+        rgb_to_xyz(&self.c, &mut xyz);
+        xyz
     }
-    pub fn from_sampled(lambda: &[Real], v: &[Real], n: usize) -> RGBSpectrum {
+
+    fn from_sampled(lambda: &[Real], v: &[Real], n: usize) -> RGBSpectrum {
         // sort samples if unordered, use sorted for returned spectrum
         if !spectrum_samples_sorted(lambda, v, n as i32) {
             panic!("TODO: if !spectrum_samples_sorted(...)");
@@ -1602,8 +1719,9 @@ impl RGBSpectrum {
         xyz[2] *= scale;
         RGBSpectrum::from_xyz(&xyz, SpectrumType::Reflectance)
     }
+
     // from CoefficientSpectrum
-    pub fn is_black(&self) -> bool {
+    fn is_black(&self) -> bool {
         for i in 0..3 {
             if self.c[i] != 0.0 as Real {
                 return false;
@@ -1611,25 +1729,8 @@ impl RGBSpectrum {
         }
         true
     }
-    pub fn sqrt(&self) -> RGBSpectrum {
-        RGBSpectrum::rgb(self.c[0].sqrt(), self.c[1].sqrt(), self.c[2].sqrt())
-    }
-    pub fn exp(&self) -> RGBSpectrum {
-        RGBSpectrum::rgb(self.c[0].exp(), self.c[1].exp(), self.c[2].exp())
-    }
-    /// Clamp spectrum to lie between the values low and high. Use
-    /// (0.0 as Real, std::f32::INFINITY as Real) if there are no
-    /// specific values.
-    pub fn clamp(&self, low: Real, high: Real) -> RGBSpectrum {
-        let mut ret: RGBSpectrum = RGBSpectrum::default();
-        let n_spectrum_samples: usize = 3; // RGB
-        for i in 0..n_spectrum_samples {
-            ret.c[i] = clamp_t(self.c[i], low, high);
-        }
-        assert!(!ret.has_nans());
-        ret
-    }
-    pub fn max_component_value(&self) -> Real {
+
+    fn max_component_value(&self) -> Real {
         let mut m: Real = self.c[0];
         let n_spectrum_samples: usize = 3; // RGB
         for i in 1..n_spectrum_samples {
@@ -1637,13 +1738,59 @@ impl RGBSpectrum {
         }
         m
     }
-    pub fn has_nans(&self) -> bool {
+
+    fn values(&self) -> &[Real] {
+        &self.c
+    }
+
+    fn values_mut(&mut self) -> &mut [Real] {
+        &mut self.c
+    }
+
+    fn lambdas() -> Option<Vec<Real>> {
+        None
+    }
+
+    /// The bands are the same as in `SampledSpectrum::from_rgb_bands`.
+    fn coefficient_index(lambda: Real) -> usize {
+        if lambda < 490.0 {
+            2
+        } else if lambda < 580.0 {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn coefficient_range(i: usize) -> (Real, Real) {
+        let grid = spectral_grid();
+        match i {
+            0 => (580.0, grid.lambda_end),
+            1 => (490.0, 580.0),
+            _ => (grid.lambda_start, 490.0),
+        }
+    }
+
+    /// The sampled re-radiation is applied to the red, green and blue basis
+    /// spectra. The result is relative to the RGB of a constant spectrum, so
+    /// an identity re-radiation keeps white white.
+    fn rerad_matrix_from_sampled(sampled: &[Real]) -> Vec<Real> {
+        let mut white = [0.0; 3];
+        SampledSpectrum::new(1.0).to_rgb(&mut white);
+
+        let mut m = vec![0.0; 9];
         for i in 0..3 {
-            if self.c[i].is_nan() {
-                return true;
+            let mut basis = [0.0; 3];
+            basis[i] = 1.0;
+            let mut rgb = [0.0; 3];
+            SampledSpectrum::from_rgb_bands(&basis)
+                .apply_matrix(sampled)
+                .to_rgb(&mut rgb);
+            for o in 0..3 {
+                m[o * 3 + i] = rgb[o] / white[o];
             }
         }
-        false
+        m
     }
 }
 
@@ -1904,18 +2051,90 @@ pub fn gamma_correct(v: Real) -> Real {
 
 /// SampledSpectrum
 
-pub const SAMPLED_LAMBDA_START: Real = 300.0;
-pub const SAMPLED_LAMBDA_END: Real = 700.0;
-pub const N_SPECTRAL_SAMPLES: usize = 60;
-lazy_static! {
-    pub static ref SAMPLED_LAMBDA: [Real; N_SPECTRAL_SAMPLES] = {
-        let mut tmp: [Real; N_SPECTRAL_SAMPLES] = [0.0; N_SPECTRAL_SAMPLES];
-        let step = (SAMPLED_LAMBDA_END - SAMPLED_LAMBDA_START) / N_SPECTRAL_SAMPLES as Real;
-        for i in 0..N_SPECTRAL_SAMPLES {
-            tmp[i] = SAMPLED_LAMBDA_START + (i as Real) * step;
+/// Largest number of bins a `SampledSpectrum` can have. The values are kept
+/// in a fixed array so that spectra stay `Copy` and off the heap, and
+/// `SpectralGrid::new` rejects finer grids.
+pub const MAX_SPECTRAL_SAMPLES: usize = 128;
+
+pub const DEFAULT_LAMBDA_START: Real = 300.0;
+pub const DEFAULT_LAMBDA_END: Real = 700.0;
+pub const DEFAULT_SPECTRAL_SAMPLES: usize = 60;
+
+/// Wavelength range and number of bins of `SampledSpectrum`. The bins are
+/// of equal width and cover `lambda_start` to `lambda_end`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpectralGrid {
+    pub lambda_start: Real,
+    pub lambda_end: Real,
+    pub n: usize,
+    /// Start wavelength of every bin.
+    pub lambda: Vec<Real>,
+}
+
+impl SpectralGrid {
+    /// Fails if the range is empty or not positive, or if `n` is 0 or more
+    /// than `MAX_SPECTRAL_SAMPLES`.
+    pub fn new(lambda_start: Real, lambda_end: Real, n: usize) -> Result<SpectralGrid, String> {
+        if !(lambda_start > 0.0 && lambda_start < lambda_end) {
+            return Err(format!(
+                "invalid wavelength range {} to {} nm",
+                lambda_start, lambda_end
+            ));
         }
-        tmp
-    };
+        if n == 0 || n > MAX_SPECTRAL_SAMPLES {
+            return Err(format!(
+                "the number of spectral bins must be between 1 and {}, the size of a \
+                 sampled spectrum, got {}",
+                MAX_SPECTRAL_SAMPLES, n
+            ));
+        }
+        let step = (lambda_end - lambda_start) / n as Real;
+        Ok(SpectralGrid {
+            lambda_start,
+            lambda_end,
+            n,
+            lambda: (0..n).map(|i| lambda_start + (i as Real) * step).collect(),
+        })
+    }
+
+    pub fn bin_width(&self) -> Real {
+        (self.lambda_end - self.lambda_start) / self.n as Real
+    }
+
+    /// Centre wavelength of every bin.
+    pub fn centers(&self) -> Vec<Real> {
+        let step = self.bin_width();
+        self.lambda.iter().map(|l| l + 0.5 * step).collect()
+    }
+}
+
+static SPECTRAL_GRID: OnceLock<SpectralGrid> = OnceLock::new();
+
+/// Set the grid used by every `SampledSpectrum`. This has to be done before
+/// the first spectrum is made, and fails if another grid is already in use.
+pub fn set_spectral_grid(grid: SpectralGrid) -> Result<(), String> {
+    let current = SPECTRAL_GRID.get_or_init(|| grid.clone());
+    if *current != grid {
+        return Err(format!(
+            "the spectral grid is already set to {} bins between {} and {} nm",
+            current.n, current.lambda_start, current.lambda_end
+        ));
+    }
+    Ok(())
+}
+
+/// The grid used by `SampledSpectrum`. Defaults to `DEFAULT_SPECTRAL_SAMPLES`
+/// bins between `DEFAULT_LAMBDA_START` and `DEFAULT_LAMBDA_END` if no grid
+/// was set.
+pub fn spectral_grid() -> &'static SpectralGrid {
+    SPECTRAL_GRID.get_or_init(|| {
+        SpectralGrid::new(
+            DEFAULT_LAMBDA_START,
+            DEFAULT_LAMBDA_END,
+            DEFAULT_SPECTRAL_SAMPLES,
+        )
+        .unwrap()
+    })
 }
 
 /*
@@ -1926,14 +2145,16 @@ lazy_static! {
 }
 */
 
+/// Samples of a spectrum on the `SpectralGrid`. Only the first
+/// `spectral_grid().n` values of `c` are used, the rest are zero.
 #[derive(Copy, Clone)]
 pub struct SampledSpectrum {
-    pub c: [Real; N_SPECTRAL_SAMPLES],
+    pub c: [Real; MAX_SPECTRAL_SAMPLES],
 }
 impl std::fmt::Debug for SampledSpectrum {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         _ = write!(f, "SampledSpectrum {{c: [");
-        for v in self.c.iter() {
+        for v in self.values().iter() {
             _ = write!(f, "{:?}, ", v);
         }
         write!(f, "] }}")
@@ -1943,45 +2164,12 @@ impl std::fmt::Debug for SampledSpectrum {
 impl Default for SampledSpectrum {
     fn default() -> Self {
         SampledSpectrum {
-            c: [0.0; N_SPECTRAL_SAMPLES],
+            c: [0.0; MAX_SPECTRAL_SAMPLES],
         }
     }
 }
 
 impl SampledSpectrum {
-    // Convert samples from other interval into interval defined by N_SPECTRAL_SAMPLES,
-    // SAMPLED_LAMBDA_START and SAMPLED_LAMBDA_END.
-    pub fn from_sampled(lambdas: &[Real], v: &[Real], n: usize) -> SampledSpectrum {
-        // sort samples if unordered, use sorted for returned spectrum
-        if !spectrum_samples_sorted(lambdas, v, n as i32) {
-            panic!("TODO: if !spectrum_samples_sorted(...)");
-            // std::vector<Float> slambda(&lambda[0], &lambda[n]);
-            // std::vector<Real> sv(&v[0], &v[n]);
-            // SortSpectrumSamples(&slambda[0], &sv[0], n);
-            // return FromSampled(&slambda[0], &sv[0], n);
-        }
-
-        let mut tmp = SampledSpectrum {
-            c: [0.0; N_SPECTRAL_SAMPLES],
-        };
-        for i in 0..N_SPECTRAL_SAMPLES {
-            let wl0 = lerp(
-                (i as Real) / (N_SPECTRAL_SAMPLES as Real),
-                SAMPLED_LAMBDA_START,
-                SAMPLED_LAMBDA_END,
-            );
-            let wl1 = lerp(
-                ((i + 1) as Real) / (N_SPECTRAL_SAMPLES as Real),
-                SAMPLED_LAMBDA_START,
-                SAMPLED_LAMBDA_END,
-            );
-            tmp.c[i] = SampledSpectrum::average_spectrum_samples(&lambdas, &v, n, wl0, wl1);
-        }
-
-        println!("{:?}", tmp);
-        return tmp;
-    }
-
     pub fn average_spectrum_samples(
         lambdas: &[Real],
         vals: &[Real],
@@ -2057,146 +2245,236 @@ impl SampledSpectrum {
         }
     }
 
-    pub fn is_black(&self) -> bool {
-        for i in 0..N_SPECTRAL_SAMPLES {
-            if self.c[i] > EPS {
-                return false;
+    pub fn clamp(&self, low: Real, high: Real) -> SampledSpectrum {
+        let ret = self.map(|v| clamp_t(v, low, high));
+        assert!(!ret.has_nans());
+        ret
+    }
+    pub fn has_nans(&self) -> bool {
+        self.values().iter().any(|v| v.is_nan())
+    }
+
+    /// Re-radiation matrix on the bins of the grid for the re-radiation
+    /// function `f(lambda_in, lambda_out)`, including re-radiation at
+    /// shorter wavelengths. One row per outgoing bin.
+    pub fn full_rerad_matrix(f: &dyn Fn(Real, Real) -> Real) -> Vec<Real> {
+        let lambda = &spectral_grid().lambda;
+        let n = lambda.len();
+        let mut m = vec![0.0; n * n];
+        for i_o in 0..n {
+            for i_i in 0..n {
+                m[i_o * n + i_i] = f(lambda[i_i], lambda[i_o]);
             }
         }
-        true
+        m
     }
-    pub fn clamp(&self, low: Real, high: Real) -> SampledSpectrum {
-        let mut ret: SampledSpectrum = SampledSpectrum::default();
-        for i in 0..N_SPECTRAL_SAMPLES {
-            ret.c[i] = clamp_t(self.c[i], low, high);
+
+    /// Spectrum that is the blue, green and red component of `rgb` in the
+    /// bands of `RGBSpectrum::coefficient_index`.
+    pub fn from_rgb_bands(rgb: &[Real; 3]) -> SampledSpectrum {
+        let mut s = SampledSpectrum::default();
+        for (v, lambda) in s.values_mut().iter_mut().zip(spectral_grid().lambda.iter()) {
+            *v = if *lambda < 490.0 {
+                rgb[2]
+            } else if *lambda < 580.0 {
+                rgb[1]
+            } else {
+                rgb[0]
+            };
+        }
+        s
+    }
+
+    /// Apply `f` to every sample.
+    fn map<F: Fn(Real) -> Real>(&self, f: F) -> SampledSpectrum {
+        let mut ret = SampledSpectrum::default();
+        let n = spectral_grid().n;
+        for (r, v) in ret.c[..n].iter_mut().zip(self.c[..n].iter()) {
+            *r = f(*v);
         }
-        assert!(!ret.has_nans());
         ret
     }
-    pub fn max_component_value(&self) -> Real {
-        let mut m: Real = self.c[0];
-        for i in 1..N_SPECTRAL_SAMPLES {
-            m = m.max(self.c[i]);
+
+    /// Apply `f` to every pair of samples of `self` and `rhs`.
+    fn zip_with<F: Fn(Real, Real) -> Real>(&self, rhs: &SampledSpectrum, f: F) -> SampledSpectrum {
+        let mut ret = SampledSpectrum::default();
+        let n = spectral_grid().n;
+        let pairs = self.c[..n].iter().zip(rhs.c[..n].iter());
+        for (r, (a, b)) in ret.c[..n].iter_mut().zip(pairs) {
+            *r = f(*a, *b);
         }
-        m
+        ret
     }
-    pub fn has_nans(&self) -> bool {
-        for i in 0..N_SPECTRAL_SAMPLES {
-            if self.c[i].is_nan() {
-                return true;
-            }
+}
+
+impl Spectrum for SampledSpectrum {
+    const NAME: &'static str = "sampled";
+    // The colour matching functions are not normalised, a constant spectrum
+    // of one has a luminance of about 100.
+    const OUTPUT_SCALE: Real = 0.01;
+
+    fn new(v: Real) -> Self {
+        let mut s = SampledSpectrum::default();
+        s.values_mut().fill(v);
+        s
+    }
+
+    fn from_rgb(rgb: &[Real; 3]) -> SampledSpectrum {
+        SampledSpectrum::from_rgb_typed(rgb, SpectrumType::Reflectance)
+    }
+
+    /// A smooth spectrum for the colour, see `uplift.rs`.
+    fn from_rgb_typed(rgb: &[Real; 3], kind: SpectrumType) -> SampledSpectrum {
+        let spectrum = uplift(rgb, kind);
+        let grid = spectral_grid();
+        let half_bin = 0.5 * grid.bin_width();
+        let mut s = SampledSpectrum::default();
+        for (v, lambda) in s.values_mut().iter_mut().zip(grid.lambda.iter()) {
+            *v = spectrum.eval(lambda + half_bin);
         }
-        false
+        s
     }
 
-    pub fn to_xyz(&self) -> [Real; 3] {
-        let mut xyz: [Real; 3] = [0.0; 3];
-        for i in 0..N_SPECTRAL_SAMPLES {
-            xyz[0] += X.c[i] * self.c[i];
-            xyz[1] += Y.c[i] * self.c[i];
-            xyz[2] += Z.c[i] * self.c[i];
+    // Convert samples from other interval into the bins of the spectral grid.
+    fn from_sampled(lambdas: &[Real], v: &[Real], n: usize) -> SampledSpectrum {
+        // sort samples if unordered, use sorted for returned spectrum
+        if !spectrum_samples_sorted(lambdas, v, n as i32) {
+            panic!("TODO: if !spectrum_samples_sorted(...)");
+            // std::vector<Float> slambda(&lambda[0], &lambda[n]);
+            // std::vector<Real> sv(&v[0], &v[n]);
+            // SortSpectrumSamples(&slambda[0], &sv[0], n);
+            // return FromSampled(&slambda[0], &sv[0], n);
         }
-        let scale = (SAMPLED_LAMBDA_END - SAMPLED_LAMBDA_START) / (N_SPECTRAL_SAMPLES as Real);
-        for i in 0..3 {
-            xyz[i] *= scale;
+
+        let grid = spectral_grid();
+        let mut tmp = SampledSpectrum::default();
+        for (i, value) in tmp.values_mut().iter_mut().enumerate() {
+            let wl0 = lerp(
+                (i as Real) / (grid.n as Real),
+                grid.lambda_start,
+                grid.lambda_end,
+            );
+            let wl1 = lerp(
+                ((i + 1) as Real) / (grid.n as Real),
+                grid.lambda_start,
+                grid.lambda_end,
+            );
+            *value = SampledSpectrum::average_spectrum_samples(lambdas, v, n, wl0, wl1);
+        }
+        tmp
+    }
+
+    fn is_black(&self) -> bool {
+        self.values().iter().all(|v| *v <= EPS)
+    }
+
+    fn max_component_value(&self) -> Real {
+        self.values().iter().copied().fold(Real::MIN, Real::max)
+    }
+
+    fn to_xyz(&self) -> [Real; 3] {
+        let mut xyz: [Real; 3] = [0.0; 3];
+        for (i, v) in self.values().iter().enumerate() {
+            xyz[0] += X.c[i] * v;
+            xyz[1] += Y.c[i] * v;
+            xyz[2] += Z.c[i] * v;
         }
-        return xyz;
+        let scale = spectral_grid().bin_width();
+        xyz.map(|v| v * scale)
     }
 
-    pub fn to_rgb(&self, mut rgb: &mut [Real; 3]) {
+    fn to_rgb(&self, mut rgb: &mut [Real; 3]) {
         let xyz = self.to_xyz();
         xyz_to_rgb(&xyz, &mut rgb);
     }
+
+    fn values(&self) -> &[Real] {
+        &self.c[..spectral_grid().n]
+    }
+
+    fn values_mut(&mut self) -> &mut [Real] {
+        &mut self.c[..spectral_grid().n]
+    }
+
+    fn lambdas() -> Option<Vec<Real>> {
+        Some(spectral_grid().centers())
+    }
+    fn coefficient_index(lambda: Real) -> usize {
+        let grid = spectral_grid();
+        let i = ((lambda - grid.lambda_start) / grid.bin_width()).floor();
+        (i.max(0.0) as usize).min(grid.n - 1)
+    }
+    fn coefficient_range(i: usize) -> (Real, Real) {
+        let start = spectral_grid().lambda[i];
+        (start, start + spectral_grid().bin_width())
+    }
+
+    fn rerad_matrix(f: &dyn Fn(Real, Real) -> Real) -> Vec<Real> {
+        let mut m = SampledSpectrum::full_rerad_matrix(f);
+        remove_anti_stokes(&mut m);
+        m
+    }
+    fn rerad_matrix_from_sampled(m: &[Real]) -> Vec<Real> {
+        m.to_vec()
+    }
 }
 
 impl Add for SampledSpectrum {
     type Output = SampledSpectrum;
     fn add(self, rhs: SampledSpectrum) -> SampledSpectrum {
-        let mut new_array: [Real; N_SPECTRAL_SAMPLES] = [0.0; N_SPECTRAL_SAMPLES];
-        for i in 0..N_SPECTRAL_SAMPLES {
-            new_array[i] = self.c[i] + rhs.c[i];
-        }
-        SampledSpectrum { c: new_array }
+        self.zip_with(&rhs, |a, b| a + b)
     }
 }
 impl AddAssign for SampledSpectrum {
     fn add_assign(&mut self, rhs: SampledSpectrum) {
         // TODO: DCHECK(!s2.HasNaNs());
-        for i in 0..N_SPECTRAL_SAMPLES {
-            self.c[i] += rhs.c[i];
-        }
+        *self = *self + rhs;
     }
 }
 impl Sub for SampledSpectrum {
     type Output = SampledSpectrum;
     fn sub(self, rhs: SampledSpectrum) -> SampledSpectrum {
-        let mut new_array: [Real; N_SPECTRAL_SAMPLES] = [0.0; N_SPECTRAL_SAMPLES];
-        for i in 0..N_SPECTRAL_SAMPLES {
-            new_array[i] = self.c[i] - rhs.c[i];
-        }
-        SampledSpectrum { c: new_array }
+        self.zip_with(&rhs, |a, b| a - b)
     }
 }
 
 impl Mul for SampledSpectrum {
     type Output = SampledSpectrum;
     fn mul(self, rhs: SampledSpectrum) -> SampledSpectrum {
-        let mut new_array: [Real; N_SPECTRAL_SAMPLES] = [0.0; N_SPECTRAL_SAMPLES];
-        for i in 0..N_SPECTRAL_SAMPLES {
-            new_array[i] = self.c[i] * rhs.c[i];
-        }
-        SampledSpectrum { c: new_array }
+        self.zip_with(&rhs, |a, b| a * b)
     }
 }
 impl Mul<&SampledSpectrum> for SampledSpectrum {
     type Output = SampledSpectrum;
     fn mul(self, rhs: &SampledSpectrum) -> SampledSpectrum {
-        let mut new_array: [Real; N_SPECTRAL_SAMPLES] = [0.0; N_SPECTRAL_SAMPLES];
-        for i in 0..N_SPECTRAL_SAMPLES {
-            new_array[i] = self.c[i] * rhs.c[i];
-        }
-        SampledSpectrum { c: new_array }
+        self.zip_with(rhs, |a, b| a * b)
     }
 }
 
 impl Mul<Real> for SampledSpectrum {
     type Output = SampledSpectrum;
     fn mul(self, rhs: Real) -> SampledSpectrum {
-        let mut new_array: [Real; N_SPECTRAL_SAMPLES] = [0.0; N_SPECTRAL_SAMPLES];
-        for i in 0..N_SPECTRAL_SAMPLES {
-            new_array[i] = self.c[i] * rhs;
-        }
-        SampledSpectrum { c: new_array }
+        self.map(|v| v * rhs)
     }
 }
 impl Mul<SampledSpectrum> for Real {
     type Output = SampledSpectrum;
     fn mul(self, rhs: SampledSpectrum) -> SampledSpectrum {
-        let mut new_array: [Real; N_SPECTRAL_SAMPLES] = [0.0; N_SPECTRAL_SAMPLES];
-        for i in 0..N_SPECTRAL_SAMPLES {
-            new_array[i] = rhs.c[i] * self;
-        }
-        SampledSpectrum { c: new_array }
+        rhs.map(|v| v * self)
     }
 }
 
 impl MulAssign for SampledSpectrum {
     fn mul_assign(&mut self, rhs: SampledSpectrum) {
         // TODO: DCHECK(!HasNaNs());
-        for i in 0..N_SPECTRAL_SAMPLES {
-            self.c[i] *= rhs.c[i];
-        }
+        *self = *self * rhs;
     }
 }
 
 impl Div for SampledSpectrum {
     type Output = SampledSpectrum;
     fn div(self, rhs: SampledSpectrum) -> SampledSpectrum {
-        let mut new_array: [Real; N_SPECTRAL_SAMPLES] = [0.0; N_SPECTRAL_SAMPLES];
-        for i in 0..N_SPECTRAL_SAMPLES {
-            new_array[i] = self.c[i] / rhs.c[i];
-        }
-        SampledSpectrum { c: new_array }
+        self.zip_with(&rhs, |a, b| a / b)
     }
 }
 
@@ -2205,11 +2483,7 @@ impl Div<Real> for SampledSpectrum {
     fn div(self, rhs: Real) -> SampledSpectrum {
         assert_ne!(rhs, 0.0 as Real);
         assert!(!rhs.is_nan(), "rhs is NaN");
-        let mut new_array: [Real; N_SPECTRAL_SAMPLES] = [0.0; N_SPECTRAL_SAMPLES];
-        for i in 0..N_SPECTRAL_SAMPLES {
-            new_array[i] = self.c[i] / rhs;
-        }
-        let ret = SampledSpectrum { c: new_array };
+        let ret = self.map(|v| v / rhs);
         assert!(!ret.has_nans());
         ret
     }
@@ -2217,18 +2491,61 @@ impl Div<Real> for SampledSpectrum {
 
 // When program starts, represent standard CIE matching functions as SampledSpectrum.
 lazy_static! {
-    static ref X: SampledSpectrum = {
-        print!("X:");
-        SampledSpectrum::from_sampled(&CIE_LAMBDA, &CIE_X, N_CIE_SAMPLES)
-    };
-    static ref Y: SampledSpectrum = {
-        print!("Y:");
-        SampledSpectrum::from_sampled(&CIE_LAMBDA, &CIE_Y, N_CIE_SAMPLES)
-    };
-    static ref Z: SampledSpectrum = {
-        print!("Z:");
-        SampledSpectrum::from_sampled(&CIE_LAMBDA, &CIE_Z, N_CIE_SAMPLES)
-    };
+    static ref X: SampledSpectrum =
+        SampledSpectrum::from_sampled(&CIE_LAMBDA, &CIE_X, N_CIE_SAMPLES);
+    static ref Y: SampledSpectrum =
+        SampledSpectrum::from_sampled(&CIE_LAMBDA, &CIE_Y, N_CIE_SAMPLES);
+    static ref Z: SampledSpectrum =
+        SampledSpectrum::from_sampled(&CIE_LAMBDA, &CIE_Z, N_CIE_SAMPLES);
+}
+
+/// Light can only be re-radiated at the same or a longer wavelength. Clear
+/// the entries of a matrix on the bins of the grid that would re-radiate at
+/// a shorter one.
+pub fn remove_anti_stokes(m: &mut [Real]) {
+    let n = spectral_grid().n;
+    for (i_o, row) in m.chunks_mut(n).enumerate() {
+        row[i_o + 1..].fill(0.0);
+    }
+}
+
+/// Number of wavelengths carried by a path with hero wavelength sampling.
+pub const N_HERO_WAVELENGTHS: usize = 4;
+
+/// Hero wavelength sampling (Wilkie et al. 2014). The hero wavelength is
+/// uniform over the range of the spectral grid, the others follow it at
+/// equal spacing and wrap around at the end of the range.
+pub fn sample_hero_wavelengths(u: Real) -> [Real; N_HERO_WAVELENGTHS] {
+    let grid = spectral_grid();
+    let range = grid.lambda_end - grid.lambda_start;
+    let mut lambda = [0.0; N_HERO_WAVELENGTHS];
+    for (j, l) in lambda.iter_mut().enumerate() {
+        let offset = u + (j as Real) / (N_HERO_WAVELENGTHS as Real);
+        *l = grid.lambda_start + range * (offset - offset.floor());
+    }
+    lambda
+}
+
+/// Probability density of every wavelength from `sample_hero_wavelengths`.
+pub fn hero_wavelength_pdf() -> Real {
+    let grid = spectral_grid();
+    1.0 / (grid.lambda_end - grid.lambda_start)
+}
+
+/// The CIE colour matching functions at `lambda`, interpolated from the
+/// 1 nm tables. Zero outside of the tables.
+pub fn cie_xyz(lambda: Real) -> [Real; 3] {
+    let x = lambda - CIE_LAMBDA[0];
+    if x < 0.0 || x > (N_CIE_SAMPLES - 1) as Real {
+        return [0.0; 3];
+    }
+    let i = (x as usize).min(N_CIE_SAMPLES - 2);
+    let t = x - i as Real;
+    [
+        lerp(t, CIE_X[i], CIE_X[i + 1]),
+        lerp(t, CIE_Y[i], CIE_Y[i + 1]),
+        lerp(t, CIE_Z[i], CIE_Z[i + 1]),
+    ]
 }
 
 // Define standard CIE illimunations
@@ -2374,17 +2691,57 @@ pub const HALOGEN_N_SAMPLES: usize = 4;
 pub const HALOGEN_LAMBDA: [Real; HALOGEN_N_SAMPLES as usize] = [400.0, 500.0, 600.0, 700.0];
 pub const HALOGEN_VALUES: [Real; HALOGEN_N_SAMPLES as usize] = [0.0, 8.0, 15.6, 18.4];
 
-lazy_static! {
-    pub static ref ILLUMINATION_D50: Spectrum = {
-        print!("D50 illum:");
-        Spectrum::from_sampled(&CIE_D50_LAMBDA, &CIE_D50_VALUES, CIE_D50_N_SAMPLES)
-    };
-    pub static ref ILLUMINATION_D65: Spectrum = {
-        print!("D65 illum:");
-        Spectrum::from_sampled(&CIE_D65_LAMBDA, &CIE_D65_VALUES, CIE_D65_N_SAMPLES)
-    };
-    pub static ref ILLUMINATION_HALOGEN: Spectrum = {
-        print!("HALOGEN illum:");
-        Spectrum::from_sampled(&HALOGEN_LAMBDA, &HALOGEN_VALUES, HALOGEN_N_SAMPLES)
-    };
+pub fn illumination_d50<S: Spectrum>() -> S {
+    S::from_sampled(&CIE_D50_LAMBDA, &CIE_D50_VALUES, CIE_D50_N_SAMPLES)
+}
+
+pub fn illumination_d65<S: Spectrum>() -> S {
+    S::from_sampled(&CIE_D65_LAMBDA, &CIE_D65_VALUES, CIE_D65_N_SAMPLES)
+}
+
+pub fn illumination_halogen<S: Spectrum>() -> S {
+    S::from_sampled(&HALOGEN_LAMBDA, &HALOGEN_VALUES, HALOGEN_N_SAMPLES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_is_limited_to_the_spectrum_size() {
+        let grid = SpectralGrid::new(300.0, 700.0, MAX_SPECTRAL_SAMPLES).unwrap();
+        assert_eq!(grid.lambda.len(), MAX_SPECTRAL_SAMPLES);
+        let err = SpectralGrid::new(300.0, 700.0, MAX_SPECTRAL_SAMPLES + 1).unwrap_err();
+        assert!(err.contains(&MAX_SPECTRAL_SAMPLES.to_string()), "{}", err);
+        assert!(SpectralGrid::new(300.0, 700.0, 0).is_err());
+        assert!(SpectralGrid::new(700.0, 300.0, 60).is_err());
+    }
+
+    #[test]
+    fn grid_bins() {
+        let grid = SpectralGrid::new(380.0, 780.0, 40).unwrap();
+        assert_eq!(grid.bin_width(), 10.0);
+        assert_eq!(grid.lambda[0], 380.0);
+        assert_eq!(grid.lambda[39], 770.0);
+        assert_eq!(grid.centers()[0], 385.0);
+    }
+
+    #[test]
+    fn sampled_spectrum_follows_the_grid() {
+        let grid = spectral_grid();
+        assert_eq!(SampledSpectrum::new(1.0).values().len(), grid.n);
+        assert_eq!(SampledSpectrum::lambdas().unwrap(), grid.centers());
+        for (i, center) in grid.centers().iter().enumerate() {
+            assert_eq!(SampledSpectrum::coefficient_index(*center), i);
+            let (start, end) = SampledSpectrum::coefficient_range(i);
+            assert!(start < *center && *center < end);
+        }
+
+        // A linear ramp averages to its value at the centre of every bin.
+        let lambdas = [grid.lambda_start, grid.lambda_end];
+        let s = SampledSpectrum::from_sampled(&lambdas, &lambdas, 2);
+        for (v, center) in s.values().iter().zip(grid.centers().iter()) {
+            assert!((v - center).abs() < 1e-2, "{} != {}", v, center);
+        }
+    }
 }
@@ -0,0 +1,282 @@
+//! Textures give material parameters that vary over a surface. They are
+//! evaluated at the texture coordinates `u`, `v` and the position `p` of a
+//! hit. A plain spectrum or number is a constant texture.
+
+use crate::core::*;
+use crate::spectrum::{inverse_gamma_convert_float, SpectrumType};
+
+use std::path::Path;
+use std::sync::Arc;
+
+pub trait Texture<T>: Send + Sync {
+    fn value(&self, u: Real, v: Real, p: Vec3) -> T;
+    /// Value averaged over the surface. By default the value in the middle
+    /// of the texture coordinates.
+    fn average(&self) -> T {
+        self.value(0.5, 0.5, Vec3::ZEROS)
+    }
+}
+
+/// Values a texture can give, spectra for colours and numbers for
+/// parameters like roughness.
+pub trait TextureValue: Copy + Send + Sync + 'static {
+    /// Linear interpolation from `a` at 0 to `b` at 1.
+    fn mix(t: Real, a: Self, b: Self) -> Self;
+    /// The value for the linear RGB colour `rgb`, standing for a spectrum
+    /// of type `kind`.
+    fn from_rgb(rgb: &[Real; 3], kind: SpectrumType) -> Self;
+}
+
+impl<S: Spectrum> TextureValue for S {
+    fn mix(t: Real, a: S, b: S) -> S {
+        a * (1.0 - t) + b * t
+    }
+    fn from_rgb(rgb: &[Real; 3], kind: SpectrumType) -> S {
+        S::from_rgb_typed(rgb, kind)
+    }
+}
+
+impl TextureValue for Real {
+    fn mix(t: Real, a: Real, b: Real) -> Real {
+        lerp(t, a, b)
+    }
+    /// The luminance of the colour.
+    fn from_rgb(rgb: &[Real; 3], kind: SpectrumType) -> Real {
+        0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+    }
+}
+
+impl<T: TextureValue> Texture<T> for T {
+    fn value(&self, u: Real, v: Real, p: Vec3) -> T {
+        *self
+    }
+    fn average(&self) -> T {
+        *self
+    }
+}
+
+/// Squares alternating between two textures, `scale` squares along each
+/// texture coordinate.
+pub struct CheckerTexture<T> {
+    pub odd: Arc<dyn Texture<T>>,
+    pub even: Arc<dyn Texture<T>>,
+    pub scale: Real,
+}
+
+impl<T: TextureValue> Texture<T> for CheckerTexture<T> {
+    fn value(&self, u: Real, v: Real, p: Vec3) -> T {
+        let square = (u * self.scale).floor() + (v * self.scale).floor();
+        if square.rem_euclid(2.0) == 0.0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+    fn average(&self) -> T {
+        T::mix(0.5, self.odd.average(), self.even.average())
+    }
+}
+
+/// An sRGB PNG image over the texture coordinates, repeating outside of
+/// [0, 1]. `v` = 0 is the bottom row. The colours are spectra of type
+/// `kind`.
+pub struct ImageTexture {
+    kind: SpectrumType,
+    width: usize,
+    height: usize,
+    /// Linear RGB, row by row from the top.
+    pixels: Vec<[Real; 3]>,
+}
+
+impl ImageTexture {
+    pub fn load(path: &Path, kind: SpectrumType) -> Result<ImageTexture, String> {
+        let image = lodepng::decode24_file(path).map_err(|err| err.to_string())?;
+        if image.width == 0 || image.height == 0 {
+            return Err("the image is empty".to_string());
+        }
+        let linear = |c: u8| inverse_gamma_convert_float(c as Real / 255.0);
+        let pixels = image
+            .buffer
+            .iter()
+            .map(|px| [linear(px.r), linear(px.g), linear(px.b)])
+            .collect();
+        Ok(ImageTexture {
+            kind,
+            width: image.width,
+            height: image.height,
+            pixels,
+        })
+    }
+
+    /// Nearest pixel to the texture coordinates.
+    fn pixel(&self, u: Real, v: Real) -> &[Real; 3] {
+        let x = (u.rem_euclid(1.0) * self.width as Real) as usize;
+        let y = ((1.0 - v.rem_euclid(1.0)) * self.height as Real) as usize;
+        &self.pixels[y.min(self.height - 1) * self.width + x.min(self.width - 1)]
+    }
+}
+
+impl<T: TextureValue> Texture<T> for ImageTexture {
+    fn value(&self, u: Real, v: Real, p: Vec3) -> T {
+        T::from_rgb(self.pixel(u, v), self.kind)
+    }
+    fn average(&self) -> T {
+        let mut sum = [0.0; 3];
+        for px in self.pixels.iter() {
+            for (s, c) in sum.iter_mut().zip(px.iter()) {
+                *s += c;
+            }
+        }
+        T::from_rgb(&sum.map(|s| s / self.pixels.len() as Real), self.kind)
+    }
+}
+
+/// Gradient noise in space, between `low` and `high`. `scale` is the number
+/// of features per unit length, and every octave adds finer detail at half
+/// the amplitude of the previous one.
+pub struct NoiseTexture<T> {
+    pub low: T,
+    pub high: T,
+    pub scale: Real,
+    pub octaves: usize,
+}
+
+impl<T: TextureValue> Texture<T> for NoiseTexture<T> {
+    fn value(&self, u: Real, v: Real, p: Vec3) -> T {
+        let mut sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut total = 0.0;
+        let mut p = p * self.scale;
+        for _ in 0..self.octaves.max(1) {
+            sum += amplitude * gradient_noise(p);
+            total += amplitude;
+            amplitude *= 0.5;
+            p = p * 2.0;
+        }
+        let t = (0.5 * (1.0 + sum / total)).clamp(0.0, 1.0);
+        T::mix(t, self.low, self.high)
+    }
+    fn average(&self) -> T {
+        T::mix(0.5, self.low, self.high)
+    }
+}
+
+/// Directions to the edges of a cube, the gradients of Perlin's improved
+/// noise.
+const GRADIENTS: [[Real; 3]; 12] = [
+    [1.0, 1.0, 0.0],
+    [-1.0, 1.0, 0.0],
+    [1.0, -1.0, 0.0],
+    [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0],
+    [-1.0, 0.0, 1.0],
+    [1.0, 0.0, -1.0],
+    [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0],
+    [0.0, -1.0, 1.0],
+    [0.0, 1.0, -1.0],
+    [0.0, -1.0, -1.0],
+];
+
+/// Gradient of the lattice point `(x, y, z)`, from a hash of its
+/// coordinates, so the noise is the same in every render.
+fn lattice_gradient(x: i32, y: i32, z: i32) -> [Real; 3] {
+    let mut h = (x as u32).wrapping_mul(73856093)
+        ^ (y as u32).wrapping_mul(19349663)
+        ^ (z as u32).wrapping_mul(83492791);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1e995);
+    h ^= h >> 15;
+    GRADIENTS[(h % 12) as usize]
+}
+
+/// Perlin's gradient noise at `p`, roughly in [-1, 1].
+fn gradient_noise(p: Vec3) -> Real {
+    let cell = [p.x().floor(), p.y().floor(), p.z().floor()];
+    let f = [p.x() - cell[0], p.y() - cell[1], p.z() - cell[2]];
+    let fade = |t: Real| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let w = [fade(f[0]), fade(f[1]), fade(f[2])];
+
+    let mut sum = 0.0;
+    for corner in 0..8 {
+        let d = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+        let g = lattice_gradient(
+            cell[0] as i32 + d[0],
+            cell[1] as i32 + d[1],
+            cell[2] as i32 + d[2],
+        );
+        let mut weight = 1.0;
+        let mut dot = 0.0;
+        for i in 0..3 {
+            let offset = f[i] - d[i] as Real;
+            dot += g[i] * offset;
+            weight *= if d[i] == 1 { w[i] } else { 1.0 - w[i] };
+        }
+        sum += weight * dot;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checker_alternates() {
+        let checker = CheckerTexture::<Real> {
+            odd: Arc::new(1.0),
+            even: Arc::new(0.0),
+            scale: 4.0,
+        };
+        assert_eq!(checker.value(0.1, 0.1, Vec3::ZEROS), 0.0);
+        assert_eq!(checker.value(0.3, 0.1, Vec3::ZEROS), 1.0);
+        assert_eq!(checker.value(0.3, 0.3, Vec3::ZEROS), 0.0);
+        // Negative coordinates continue the pattern.
+        assert_eq!(checker.value(-0.1, 0.1, Vec3::ZEROS), 1.0);
+        assert_eq!(checker.average(), 0.5);
+    }
+
+    #[test]
+    fn noise_stays_between_its_bounds() {
+        let noise = NoiseTexture::<Real> {
+            low: 0.2,
+            high: 0.6,
+            scale: 3.0,
+            octaves: 4,
+        };
+        let mut values = Vec::new();
+        for i in 0..1000 {
+            let p = Vec3(i as Real * 0.137, i as Real * 0.071, i as Real * -0.053);
+            let v = noise.value(0.0, 0.0, p);
+            assert!((0.2..=0.6).contains(&v), "{}", v);
+            // The same point always has the same value.
+            assert_eq!(v, noise.value(1.0, 1.0, p));
+            values.push(v);
+        }
+        let mean = values.iter().sum::<Real>() / values.len() as Real;
+        assert!((mean - noise.average()).abs() < 0.05, "{}", mean);
+        assert!(values.iter().any(|v| (v - mean).abs() > 0.05));
+    }
+
+    #[test]
+    fn image_rows_go_up_with_v() {
+        // Red on the top row, blue on the bottom one.
+        let (red, blue) = ([1.0, 0.0, 0.0], [0.0, 0.0, 1.0]);
+        let image = ImageTexture {
+            kind: SpectrumType::Reflectance,
+            width: 2,
+            height: 2,
+            pixels: vec![red, red, blue, blue],
+        };
+
+        let top: Real = image.value(0.25, 0.75, Vec3::ZEROS);
+        let bottom: Real = image.value(0.25, 0.25, Vec3::ZEROS);
+        assert!((top - 0.2126).abs() < 1e-4, "{}", top);
+        assert!((bottom - 0.0722).abs() < 1e-4, "{}", bottom);
+        // The texture repeats.
+        let repeated: Real = image.value(1.25, -0.25, Vec3::ZEROS);
+        assert_eq!(repeated, top);
+        let average: Real = image.average();
+        assert!((average - 0.5 * (0.2126 + 0.0722)).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,573 @@
+//! Spectral uplifting: smooth spectra for RGB colours, so that colours from
+//! image textures, mesh files and scene files can be rendered with the
+//! sampled spectrum.
+//!
+//! Two methods are implemented, picked with `--uplift`. Smits (1999) adds up
+//! the spectra of white, the primaries and their complements, which gives
+//! blocky spectra within [0, 1]. Jakob and Hanika (2019) use a sigmoid of a
+//! quadratic polynomial in the wavelength, which is smooth and bounded by
+//! construction. Its coefficients are fitted for a table of colours the
+//! first time one is needed and interpolated in between.
+//!
+//! The colours are linear sRGB. Reflectances are clamped to [0, 1], other
+//! spectra are scaled so that the brightest component is within the range
+//! the methods can reach, and illuminants are multiplied with D65, the white
+//! of sRGB.
+
+use crate::core::*;
+use crate::spectrum::{
+    cie_xyz, rgb_to_xyz, SpectrumType, CIE_D65_LAMBDA, CIE_D65_N_SAMPLES, CIE_D65_VALUES,
+    CIE_LAMBDA, CIE_Y, N_CIE_SAMPLES,
+};
+
+use clap::ValueEnum;
+use rayon::prelude::*;
+use std::sync::OnceLock;
+
+/// Methods to turn an RGB colour into a spectrum.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum UpliftMethod {
+    /// Sums of the spectra of white, the primaries and their complements.
+    Smits,
+    /// Sigmoids of a quadratic polynomial, fitted to the colour.
+    #[default]
+    Sigmoid,
+}
+
+static UPLIFT_METHOD: OnceLock<UpliftMethod> = OnceLock::new();
+
+/// Set the method used for every colour. This has to be done before the
+/// first colour is uplifted, and fails if another method is already in use.
+pub fn set_uplift_method(method: UpliftMethod) -> Result<(), String> {
+    let current = UPLIFT_METHOD.get_or_init(|| method);
+    if *current != method {
+        return Err(format!(
+            "the uplifting method is already set to {:?}",
+            current
+        ));
+    }
+    Ok(())
+}
+
+/// The method used for every colour, the sigmoid if none was set.
+pub fn uplift_method() -> UpliftMethod {
+    *UPLIFT_METHOD.get_or_init(UpliftMethod::default)
+}
+
+/// A spectrum for an RGB colour, from `uplift`.
+#[derive(Copy, Clone, Debug)]
+pub struct UpliftedSpectrum {
+    shape: Shape,
+    scale: Real,
+    illuminant: bool,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Shape {
+    Smits([Real; SMITS_BINS]),
+    /// Coefficients of the polynomial, highest power first, for wavelengths
+    /// in nanometers.
+    Sigmoid([Real; 3]),
+}
+
+impl UpliftedSpectrum {
+    /// Value of the spectrum at the wavelength `lambda`, in nanometers.
+    pub fn eval(&self, lambda: Real) -> Real {
+        let v = match &self.shape {
+            Shape::Smits(bins) => {
+                let i = ((lambda - SMITS_START) / SMITS_BIN_WIDTH).floor();
+                bins[(i.max(0.0) as usize).min(SMITS_BINS - 1)]
+            }
+            Shape::Sigmoid(c) => sigmoid((c[0] * lambda + c[1]) * lambda + c[2]),
+        };
+        let v = self.scale * v;
+        if self.illuminant {
+            v * d65(lambda)
+        } else {
+            v
+        }
+    }
+}
+
+/// A smooth spectrum for the linear sRGB colour `rgb`, using the method from
+/// `uplift_method`.
+pub fn uplift(rgb: &[Real; 3], kind: SpectrumType) -> UpliftedSpectrum {
+    uplift_with(uplift_method(), rgb, kind)
+}
+
+fn uplift_with(method: UpliftMethod, rgb: &[Real; 3], kind: SpectrumType) -> UpliftedSpectrum {
+    let (rgb, scale) = match kind {
+        SpectrumType::Reflectance => (rgb.map(|c| c.clamp(0.0, 1.0)), 1.0),
+        SpectrumType::Unbounded | SpectrumType::Illuminant => {
+            let rgb = rgb.map(|c| c.max(0.0));
+            let max = rgb[0].max(rgb[1]).max(rgb[2]);
+            // Keep the sigmoid away from 1, where the polynomial would
+            // have to be infinite.
+            let scale = match method {
+                UpliftMethod::Smits => max,
+                UpliftMethod::Sigmoid => 2.0 * max,
+            };
+            if scale > 0.0 {
+                (rgb.map(|c| c / scale), scale)
+            } else {
+                ([0.0; 3], 0.0)
+            }
+        }
+    };
+    let shape = match method {
+        UpliftMethod::Smits => Shape::Smits(smits(&rgb)),
+        UpliftMethod::Sigmoid => Shape::Sigmoid(sigmoid_coefficients(&rgb)),
+    };
+    UpliftedSpectrum {
+        shape,
+        scale,
+        illuminant: kind == SpectrumType::Illuminant,
+    }
+}
+
+/// The D65 illuminant, scaled to the luminance of a constant spectrum of 1
+/// so that white light is as bright as a white reflectance.
+fn d65(lambda: Real) -> Real {
+    static SCALE: OnceLock<Real> = OnceLock::new();
+    let scale = SCALE.get_or_init(|| {
+        let (mut y, mut y_d65) = (0.0, 0.0);
+        for (lambda, cie_y) in CIE_LAMBDA.iter().zip(CIE_Y.iter()) {
+            y += cie_y;
+            y_d65 += cie_y * d65_table(*lambda);
+        }
+        y / y_d65
+    });
+    scale * d65_table(lambda)
+}
+
+/// The tabulated D65 illuminant at `lambda`, interpolated between the 1 nm
+/// samples and constant outside of the table.
+fn d65_table(lambda: Real) -> Real {
+    let x = (lambda - CIE_D65_LAMBDA[0]).clamp(0.0, (CIE_D65_N_SAMPLES - 1) as Real);
+    let i = (x as usize).min(CIE_D65_N_SAMPLES - 2);
+    lerp(x - i as Real, CIE_D65_VALUES[i], CIE_D65_VALUES[i + 1])
+}
+
+// Smits, "An RGB to Spectrum Conversion for Reflectances", 1999. Ten bins
+// between 380 and 720 nm, the first and last one are used outside of that.
+const SMITS_BINS: usize = 10;
+const SMITS_START: Real = 380.0;
+const SMITS_BIN_WIDTH: Real = 34.0;
+
+const SMITS_WHITE: [Real; SMITS_BINS] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [Real; SMITS_BINS] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [Real; SMITS_BINS] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [Real; SMITS_BINS] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [Real; SMITS_BINS] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [Real; SMITS_BINS] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [Real; SMITS_BINS] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// Smits' spectrum for a colour with components in [0, 1]: white for the
+/// smallest component, the complement of the colour with the largest
+/// component for the middle one and that primary for the rest.
+fn smits(rgb: &[Real; 3]) -> [Real; SMITS_BINS] {
+    let [r, g, b] = *rgb;
+    let mut terms = [(0.0, &SMITS_WHITE); 3];
+    if r <= g && r <= b {
+        terms[0] = (r, &SMITS_WHITE);
+        if g <= b {
+            terms[1] = (g - r, &SMITS_CYAN);
+            terms[2] = (b - g, &SMITS_BLUE);
+        } else {
+            terms[1] = (b - r, &SMITS_CYAN);
+            terms[2] = (g - b, &SMITS_GREEN);
+        }
+    } else if g <= r && g <= b {
+        terms[0] = (g, &SMITS_WHITE);
+        if r <= b {
+            terms[1] = (r - g, &SMITS_MAGENTA);
+            terms[2] = (b - r, &SMITS_BLUE);
+        } else {
+            terms[1] = (b - g, &SMITS_MAGENTA);
+            terms[2] = (r - b, &SMITS_RED);
+        }
+    } else {
+        terms[0] = (b, &SMITS_WHITE);
+        if r <= g {
+            terms[1] = (r - b, &SMITS_YELLOW);
+            terms[2] = (g - r, &SMITS_GREEN);
+        } else {
+            terms[1] = (g - b, &SMITS_YELLOW);
+            terms[2] = (r - g, &SMITS_RED);
+        }
+    }
+    let mut bins = [0.0; SMITS_BINS];
+    for (i, v) in bins.iter_mut().enumerate() {
+        let sum: Real = terms.iter().map(|(w, basis)| w * basis[i]).sum();
+        *v = sum.clamp(0.0, 1.0);
+    }
+    bins
+}
+
+/// The sigmoid of Jakob and Hanika, mapping the real line onto (0, 1).
+fn sigmoid(x: Real) -> Real {
+    if x.is_infinite() {
+        return if x > 0.0 { 1.0 } else { 0.0 };
+    }
+    0.5 + x / (2.0 * (1.0 + x * x).sqrt())
+}
+
+/// Number of entries along each axis of the table of sigmoid coefficients.
+const TABLE_RES: usize = 32;
+
+/// Range of wavelengths the coefficients are fitted over, in nanometers.
+const FIT_START: f64 = 360.0;
+const FIT_END: f64 = 830.0;
+const FIT_STEP: f64 = 5.0;
+
+/// Coefficients of the sigmoid for colours with components in [0, 1],
+/// interpolated from the fitted table.
+fn sigmoid_coefficients(rgb: &[Real; 3]) -> [Real; 3] {
+    // Greys are constant spectra, which need no table.
+    if rgb[0] == rgb[1] && rgb[1] == rgb[2] {
+        let v = rgb[0];
+        return [0.0, 0.0, (v - 0.5) / (v * (1.0 - v)).sqrt()];
+    }
+
+    let table = SIGMOID_TABLE.get_or_init(SigmoidTable::fit);
+    let max = if rgb[0] > rgb[1] {
+        if rgb[0] > rgb[2] {
+            0
+        } else {
+            2
+        }
+    } else if rgb[1] > rgb[2] {
+        1
+    } else {
+        2
+    };
+    let z = rgb[max];
+    let last = (TABLE_RES - 1) as Real;
+    let x = rgb[(max + 1) % 3] * last / z;
+    let y = rgb[(max + 2) % 3] * last / z;
+
+    let xi = (x as usize).min(TABLE_RES - 2);
+    let yi = (y as usize).min(TABLE_RES - 2);
+    let zi = find_interval(TABLE_RES as i32, |i| table.scale[i as usize] <= z) as usize;
+    let dx = x - xi as Real;
+    let dy = y - yi as Real;
+    let dz = (z - table.scale[zi]) / (table.scale[zi + 1] - table.scale[zi]);
+
+    let mut c = [0.0; 3];
+    for corner in 0..8 {
+        let (ox, oy, oz) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+        let w = (if ox == 1 { dx } else { 1.0 - dx })
+            * (if oy == 1 { dy } else { 1.0 - dy })
+            * (if oz == 1 { dz } else { 1.0 - dz });
+        let entry = table.get(max, zi + oz, yi + oy, xi + ox);
+        for (c, e) in c.iter_mut().zip(entry.iter()) {
+            *c += w * e;
+        }
+    }
+    c
+}
+
+static SIGMOID_TABLE: OnceLock<SigmoidTable> = OnceLock::new();
+
+/// Sigmoid coefficients for colours on a grid, one block for each component
+/// that can be the largest. Within a block the largest component is
+/// `scale[k]` and the other two are `i / (TABLE_RES - 1)` and
+/// `j / (TABLE_RES - 1)` times that, in the order of the components.
+struct SigmoidTable {
+    scale: Vec<Real>,
+    coefficients: Vec<[Real; 3]>,
+}
+
+impl SigmoidTable {
+    fn get(&self, max: usize, k: usize, j: usize, i: usize) -> &[Real; 3] {
+        &self.coefficients[((max * TABLE_RES + k) * TABLE_RES + j) * TABLE_RES + i]
+    }
+
+    /// Fit the coefficients of every entry. Each fit starts from the
+    /// solution of the neighbour with a less saturated largest component,
+    /// starting in the middle where the spectra are close to constant.
+    fn fit() -> SigmoidTable {
+        let fit = SigmoidFit::new();
+        let smoothstep = |x: f64| x * x * (3.0 - 2.0 * x);
+        let scale: Vec<f64> = (0..TABLE_RES)
+            .map(|k| smoothstep(smoothstep(k as f64 / (TABLE_RES - 1) as f64)))
+            .collect();
+
+        let start = TABLE_RES / 5;
+        let rows: Vec<(usize, usize, usize)> = (0..3)
+            .flat_map(|max| {
+                (0..TABLE_RES).flat_map(move |j| (0..TABLE_RES).map(move |i| (max, j, i)))
+            })
+            .collect();
+        let fitted: Vec<Vec<[f64; 3]>> = rows
+            .par_iter()
+            .map(|&(max, j, i)| {
+                let colour = |k: usize| {
+                    let z = scale[k];
+                    let mut rgb = [0.0; 3];
+                    rgb[max] = z;
+                    rgb[(max + 1) % 3] = i as f64 / (TABLE_RES - 1) as f64 * z;
+                    rgb[(max + 2) % 3] = j as f64 / (TABLE_RES - 1) as f64 * z;
+                    rgb
+                };
+                let mut row = vec![[0.0; 3]; TABLE_RES];
+                let mut c = [0.0; 3];
+                for (k, entry) in row.iter_mut().enumerate().skip(start) {
+                    fit.fit(&colour(k), &mut c);
+                    *entry = c;
+                }
+                c = row[start];
+                for (k, entry) in row.iter_mut().enumerate().take(start).rev() {
+                    fit.fit(&colour(k), &mut c);
+                    *entry = c;
+                }
+                row
+            })
+            .collect();
+
+        let mut coefficients = vec![[0.0; 3]; 3 * TABLE_RES * TABLE_RES * TABLE_RES];
+        for ((max, j, i), row) in rows.iter().zip(fitted.iter()) {
+            for (k, c) in row.iter().enumerate() {
+                coefficients[((max * TABLE_RES + k) * TABLE_RES + j) * TABLE_RES + i] =
+                    to_nanometers(c);
+            }
+        }
+        SigmoidTable {
+            scale: scale.iter().map(|s| *s as Real).collect(),
+            coefficients,
+        }
+    }
+}
+
+/// Coefficients for wavelengths in nanometers, from coefficients for
+/// wavelengths mapped from [FIT_START, FIT_END] to [0, 1].
+fn to_nanometers(c: &[f64; 3]) -> [Real; 3] {
+    let a = 1.0 / (FIT_END - FIT_START);
+    let b = FIT_START;
+    [
+        (c[0] * a * a) as Real,
+        (c[1] * a - 2.0 * c[0] * b * a * a) as Real,
+        (c[2] - c[1] * b * a + c[0] * b * b * a * a) as Real,
+    ]
+}
+
+/// Gauss-Newton fit of sigmoid coefficients to a colour, minimising the
+/// difference in CIELAB.
+struct SigmoidFit {
+    /// Wavelengths, mapped to [0, 1].
+    lambda: Vec<f64>,
+    /// XYZ of every wavelength under D65, such that a constant spectrum of 1
+    /// has a luminance of 1.
+    xyz: Vec<[f64; 3]>,
+    white: [f64; 3],
+}
+
+impl SigmoidFit {
+    fn new() -> SigmoidFit {
+        let n = ((FIT_END - FIT_START) / FIT_STEP) as usize + 1;
+        let mut lambda = Vec::with_capacity(n);
+        let mut xyz = Vec::with_capacity(n);
+        for i in 0..n {
+            let l = FIT_START + i as f64 * FIT_STEP;
+            let cie = cie_xyz(l as Real);
+            let d65 = d65_table(l as Real) as f64;
+            lambda.push((l - FIT_START) / (FIT_END - FIT_START));
+            xyz.push([
+                cie[0] as f64 * d65,
+                cie[1] as f64 * d65,
+                cie[2] as f64 * d65,
+            ]);
+        }
+        let y: f64 = xyz.iter().map(|v| v[1]).sum();
+        for v in xyz.iter_mut() {
+            *v = v.map(|c| c / y);
+        }
+        let mut white = [0.0; 3];
+        for v in xyz.iter() {
+            for (w, c) in white.iter_mut().zip(v.iter()) {
+                *w += c;
+            }
+        }
+        SigmoidFit { lambda, xyz, white }
+    }
+
+    /// CIELAB coordinates of `xyz`, and their derivatives with respect to
+    /// X, Y and Z, one row per coordinate.
+    fn lab(&self, xyz: &[f64; 3]) -> ([f64; 3], [[f64; 3]; 3]) {
+        let delta: f64 = 6.0 / 29.0;
+        let mut f = [0.0; 3];
+        let mut df = [0.0; 3];
+        for i in 0..3 {
+            let t = xyz[i] / self.white[i];
+            if t > delta * delta * delta {
+                f[i] = t.cbrt();
+                df[i] = 1.0 / (3.0 * f[i] * f[i] * self.white[i]);
+            } else {
+                f[i] = t / (3.0 * delta * delta) + 4.0 / 29.0;
+                df[i] = 1.0 / (3.0 * delta * delta * self.white[i]);
+            }
+        }
+        let lab = [
+            116.0 * f[1] - 16.0,
+            500.0 * (f[0] - f[1]),
+            200.0 * (f[1] - f[2]),
+        ];
+        let jacobian = [
+            [0.0, 116.0 * df[1], 0.0],
+            [500.0 * df[0], -500.0 * df[1], 0.0],
+            [0.0, 200.0 * df[1], -200.0 * df[2]],
+        ];
+        (lab, jacobian)
+    }
+
+    /// Difference in CIELAB between the spectrum of the coefficients `c`
+    /// and the colour with the CIELAB coordinates `target`, and its
+    /// Jacobian with respect to the coefficients.
+    fn residual(&self, c: &[f64; 3], target: &[f64; 3]) -> ([f64; 3], [[f64; 3]; 3]) {
+        let mut xyz = [0.0; 3];
+        // Derivatives of X, Y and Z with respect to the coefficients.
+        let mut dxyz = [[0.0; 3]; 3];
+        for (t, w) in self.lambda.iter().zip(self.xyz.iter()) {
+            let x = (c[0] * t + c[1]) * t + c[2];
+            let r = 1.0 / (1.0 + x * x).sqrt();
+            let s = 0.5 + 0.5 * x * r;
+            let ds = 0.5 * r * r * r;
+            let dx = [t * t, *t, 1.0];
+            for i in 0..3 {
+                xyz[i] += s * w[i];
+                for j in 0..3 {
+                    dxyz[i][j] += ds * dx[j] * w[i];
+                }
+            }
+        }
+        let (lab, dlab) = self.lab(&xyz);
+        let mut jacobian = [[0.0; 3]; 3];
+        for (row, dlab) in jacobian.iter_mut().zip(dlab.iter()) {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..3).map(|k| dlab[k] * dxyz[k][j]).sum();
+            }
+        }
+        let residual = [lab[0] - target[0], lab[1] - target[1], lab[2] - target[2]];
+        (residual, jacobian)
+    }
+
+    /// Improve the coefficients `c` for the linear sRGB colour `rgb`.
+    fn fit(&self, rgb: &[f64; 3], c: &mut [f64; 3]) {
+        let mut xyz = [0.0; 3];
+        rgb_to_xyz(&rgb.map(|v| v as Real), &mut xyz);
+        let (target, _) = self.lab(&xyz.map(|v| v as f64));
+
+        for _ in 0..15 {
+            let (r, jacobian) = self.residual(c, &target);
+            if r.iter().map(|v| v * v).sum::<f64>() < 1e-12 {
+                break;
+            }
+            let step = match solve3(&jacobian, &r) {
+                Some(step) => step,
+                None => break,
+            };
+            for (c, s) in c.iter_mut().zip(step.iter()) {
+                *c -= s;
+            }
+            // Saturated colours need large coefficients, but limit them so
+            // that the fit of the next entry can start from here.
+            let largest = c.iter().fold(0.0_f64, |m, v| m.max(v.abs()));
+            if largest > 200.0 {
+                *c = c.map(|v| v * 200.0 / largest);
+            }
+        }
+    }
+}
+
+/// Solve `m x = b` with Cramer's rule, `None` if `m` is singular.
+fn solve3(m: &[[f64; 3]; 3], b: &[f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: &[[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let d = det(m);
+    if d.abs() < 1e-15 {
+        return None;
+    }
+    let mut x = [0.0; 3];
+    for (i, x) in x.iter_mut().enumerate() {
+        let mut mi = *m;
+        for (row, b) in mi.iter_mut().zip(b.iter()) {
+            row[i] = *b;
+        }
+        *x = det(&mi) / d;
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrum::{spectral_grid, SampledSpectrum};
+
+    /// The colour of the uplifted light.
+    fn round_trip(method: UpliftMethod, rgb: &[Real; 3]) -> [Real; 3] {
+        let spectrum = uplift_with(method, rgb, SpectrumType::Illuminant);
+        let grid = spectral_grid();
+        let half_bin = 0.5 * grid.bin_width();
+        let mut s = SampledSpectrum::default();
+        for (v, lambda) in s.values_mut().iter_mut().zip(grid.lambda.iter()) {
+            *v = spectrum.eval(lambda + half_bin);
+        }
+        let mut out = [0.0; 3];
+        s.to_rgb(&mut out);
+        // White light is as bright as a constant spectrum of one.
+        let white = SampledSpectrum::new(1.0).to_xyz()[1];
+        out.map(|c| c / white)
+    }
+
+    fn check(method: UpliftMethod, tolerance: Real) {
+        let colours = [
+            [1.0, 1.0, 1.0],
+            [0.5, 0.5, 0.5],
+            [0.8, 0.2, 0.1],
+            [0.1, 0.6, 0.2],
+            [0.2, 0.3, 0.9],
+            [0.9, 0.8, 0.1],
+            [3.0, 1.5, 0.5],
+        ];
+        for rgb in colours.iter() {
+            let out = round_trip(method, rgb);
+            let max = rgb[0].max(rgb[1]).max(rgb[2]);
+            for (a, b) in out.iter().zip(rgb.iter()) {
+                assert!(
+                    (a - b).abs() <= tolerance * max,
+                    "{:?}: {:?} became {:?}",
+                    method,
+                    rgb,
+                    out
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn smits_round_trip() {
+        check(UpliftMethod::Smits, 0.03);
+    }
+
+    #[test]
+    fn sigmoid_round_trip() {
+        check(UpliftMethod::Sigmoid, 0.01);
+    }
+}